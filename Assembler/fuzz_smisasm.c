@@ -0,0 +1,205 @@
+/*
+
+SMIS ASM assembler parser fuzz target
+
+Program overview:
+
+    Arbitrary input is split into lines, and each line is fed through assembleLine() exactly as if
+    it had been read from an ASM file, which runs it through tokenization, opcode resolution, and
+    operand parsing. The assembler reports bad input by calling exit(), which is redirected back
+    into the fuzz target, so only genuine crashes (and sanitizer errors) are treated as failures.
+
+    With libFuzzer (clang):
+        clang -g -DSMIS_LIBFUZZER -fsanitize=fuzzer,address,undefined fuzz_smisasm.c -o fuzz_smisasm
+        ./fuzz_smisasm -detect_leaks=0 corpus/
+
+    Standalone, for AFL or for replaying crashing inputs (gcc or clang):
+        gcc -g -fsanitize=address,undefined fuzz_smisasm.c -o fuzz_smisasm
+        ./fuzz_smisasm <input files...>     (reads stdin if no files are given)
+
+    The assembler does not free most of its temporary strings, so leak detection must be disabled.
+
+*/
+
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <stdint.h>
+#include <setjmp.h>
+
+
+jmp_buf FUZZ_EXIT;
+// Restores the fuzz target when the assembler rejects its input
+
+void fuzzExit(int status) __attribute__((noreturn));
+int fuzzOneInput(const uint8_t* data, size_t size);
+
+
+#define exit fuzzExit
+#define main smisasmMain
+#include "smisasm.c"
+#undef exit
+#undef main
+
+
+void fuzzExit(int status) {
+    // Replaces exit() in the assembler, returning to the fuzz target instead of terminating
+
+    longjmp(FUZZ_EXIT, 1);
+
+}
+
+int fuzzOneInput(const uint8_t* data, size_t size) {
+    // Assembles every line of the given input, then resets the assembler for the next input
+
+    char line[MAX_STRING_LEN];
+    size_t pos = 0;
+
+    SYMBOL_TABLE = NULL;
+    FIXUP_TABLE = NULL;
+    PROGRAM = NULL;
+    PROGRAM_SOURCE = NULL;
+    SYMBOL_COUNT = FIXUP_COUNT = PROGRAM_LEN = 0;
+    INSTRUCTION_ADDR = 0;
+    LINE_NUMBER = 1;
+    INCLUDE_DEPTH = 0;
+    CURRENT_FILE = NULL;
+    MACRO_TABLE = NULL;
+    MACRO_COUNT = MACRO_DEPTH = 0;
+    RECORDING_MACRO = NULL;
+    DATA = NULL;
+    DATA_LABELS = NULL;
+    DATA_LEN = DATA_LABEL_COUNT = 0;
+    DATA_SECTION = false;
+    DATA_ALIGNMENT = 1;
+    PINNED_DATA = NULL;
+    PINNED_DATA_COUNT = 0;
+    PINNED_SECTION = false;
+    BSS_LABELS = NULL;
+    BSS_LEN = BSS_LABEL_COUNT = 0;
+    BSS_SECTION = false;
+    BSS_ALIGNMENT = 1;
+    CONSTANT_TABLE = NULL;
+    CONSTANT_COUNT = 0;
+    CONDITION_STACK = NULL;
+    CONDITION_DEPTH = 0;
+    AFTER_UNCONDITIONAL = false;
+    UNREACHABLE = NULL;
+    UNREACHABLE_COUNT = 0;
+    LINT_WARNINGS = false;
+    // Warnings are printed to stderr, where they would drown out sanitizer errors
+    RELOCATIONS = NULL;
+    RELOCATION_COUNT = 0;
+    GLOBAL_LABELS = NULL;
+    GLOBAL_LABEL_COUNT = 0;
+    CALLED_LABELS = NULL;
+    CALLED_LABEL_COUNT = 0;
+    ENTRY_LABEL = NULL;
+    ENTRY_POINT = 0;
+    USED_LIBRARIES = NULL;
+    USED_LIBRARY_COUNT = 0;
+
+    if(!setjmp(FUZZ_EXIT)) {
+
+        definePseudoInstructions();
+
+        while(pos < size) {
+
+            size_t len = 0;
+
+            while(pos < size && len < MAX_STRING_LEN - 1) {
+
+                line[len++] = data[pos++];
+                if(line[len - 1] == '\n') break;
+
+            }
+
+            line[len] = '\0';
+            // Lines are split the same way fgets() splits them when reading an ASM file
+
+            assembleLine(line);
+
+            LINE_NUMBER++;
+
+        }
+
+        checkUnclosedMacro();
+        checkUnclosedConditional();
+        placeData();
+        checkUnresolvedFixups();
+
+    }
+
+    free(SYMBOL_TABLE);
+    free(FIXUP_TABLE);
+    free(PROGRAM);
+    free(PROGRAM_SOURCE);
+    free(MACRO_TABLE);
+    free(DATA);
+    free(DATA_LABELS);
+    free(PINNED_DATA);
+    free(CONSTANT_TABLE);
+    free(CONDITION_STACK);
+    free(RELOCATIONS);
+    free(GLOBAL_LABELS);
+    free(CALLED_LABELS);
+    free(UNREACHABLE);
+
+    return 0;
+
+}
+
+#ifdef SMIS_LIBFUZZER
+
+int LLVMFuzzerTestOneInput(const uint8_t* data, size_t size) {
+
+    return fuzzOneInput(data, size);
+
+}
+
+#else
+
+int main(int argc, char** argv) {
+
+    freopen("/dev/null", "w", stdout);
+    // The assembler reports every rejected line, which would drown out sanitizer errors
+
+    for(int i = 1; i < argc || i == 1; i++) {
+
+        FILE* input = argc > 1 ? fopen(argv[i], "rb") : stdin;
+
+        if(!input) {
+
+            fprintf(stderr, "File %s does not exist.\n", argv[i]);
+            return -1;
+
+        }
+
+        uint8_t* data = NULL;
+        size_t size = 0;
+        size_t read;
+        uint8_t buffer[4096];
+
+        while((read = fread(buffer, 1, sizeof(buffer), input))) {
+
+            data = realloc(data, size + read);
+            memcpy(data + size, buffer, read);
+            size += read;
+
+        }
+
+        if(input != stdin) fclose(input);
+
+        fuzzOneInput(data, size);
+        free(data);
+
+        if(argc <= 1) break;
+
+    }
+
+    return 0;
+
+}
+
+#endif
@@ -6,20 +6,30 @@ Documentation for the SMIS assembly language is hosted at https://github.com/Eye
 
 Program overview:
 
-    The assembling work is done in two passes.
-
-    (Setup) The input .txt ASM file and the output .bin machine code file are opened.
-
-    (Pass 1)
-        The ASM file is scanned for jump labels, which are placed into the symbol table.
-        Each symbol represents a name (to be checked against later for jump instructions),
-        and the program counter address of the first actual instruction after the label.
-
-    (Pass 2)
-        Once the symbol table has been created, the second pass parses all instructions,
-        including their operands, into the binary file. Jump instruction labels are checked
-        against the symbol table, and if the label is found, they are assembled into their
-        corresponding label address. If a label does not exist, the file cannot be assembled.
+    The assembling work is done in a single pass over the ASM file, followed by backpatching.
+
+    (Pass)
+        Each line of the input .txt ASM file is read exactly once. Jump labels are placed into
+        the symbol table as they are encountered, along with the program counter address of the
+        first actual instruction after the label. All instructions, including their operands,
+        are assembled into an in-memory program image. When a jump instruction references a label
+        that has not been defined yet, a fixup is recorded for it, and the jump address is patched
+        in as soon as the label is defined.
+
+    (Output)
+        Once the whole file has been read, the labels of .data sections are defined, since their
+        addresses depend on the length of the program. After that, any fixup that is still
+        unresolved refers to a label that does not exist, and the file cannot be assembled.
+        Otherwise, the words of any .data sections are placed after the last instruction, and the
+        program image is written to the output .bin machine code file.
+
+    (Linking)
+        If the output file ends in .o, the program image is written to a relocatable object file
+        instead, along with the labels exported by .global, the instructions which hold a label
+        address, and the labels which are left unresolved. The assembler then links any number of
+        object files with --link, placing their images one after the other, moving the label
+        addresses of each image to where it was placed, and resolving the labels they import from
+        each other.
 
 */
 
@@ -30,15 +40,64 @@ Program overview:
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
+#include <strings.h>
 #include <stdint.h>
 #include <stdbool.h>
+#include <stdarg.h>
+#include <ctype.h>
+#include <setjmp.h>
 #include <arpa/inet.h>
-
-
-#define USAGE "Usage: ./smisasm <input .txt ASM file> <output .bin executable file>\n"
+#include <unistd.h>
+#include <sys/stat.h>
+
+
+#define USAGE "Usage: ./smisasm [--warn-rzr] [--no-lint] [--raw] [--force] [--stats] [--optimize]\n" \
+    "                 [--format <bin|memh|memh32>] [--emit <IR .json file>] [--emit-labels <.json file>]\n" \
+    "                 [--symbols <.sym file>] [--debug-info <.dbg file>]\n" \
+    "                 [--semicolon-comments] [--hash-comments] [-I <include dir>]... [--dep-file <.d file>]\n" \
+    "                 [--define <name>[=<value>]]...\n" \
+    "                 <input .txt ASM file> <output .bin executable file or .o object file>\n" \
+    "       ./smisasm [--raw] [--force] [--stats] [--format <bin|memh|memh32>] [--symbols <.sym file>] [--emit-labels <.json file>] --link <input .o object file>... <output .bin executable file>\n" \
+    "       ./smisasm [--semicolon-comments] [--hash-comments] [--force] --fmt <.txt ASM file>\n" \
+    "       ./smisasm [options] --layout <input .txt ASM file>\n"
 #define MAX_INSTRUCTION_LEN 50
 #define MAX_STRING_LEN 500
 #define INT_LIMIT 65535
+#define MAX_INCLUDE_DEPTH 16
+// Deeper nesting of .include directives is assumed to be an include cycle
+#define MAX_MACRO_DEPTH 16
+// Deeper nesting of macro expansions is assumed to be a macro which expands to itself
+#define FORMAT_COMMENT_COLUMN 32
+// Column which --fmt lines up trailing comments at
+#define STATS_IMMEDIATE_BUCKETS 5
+// Immediates are counted by size for --stats: 0, up to 4 bits, up to 8 bits, up to 12 bits, and up to 16 bits
+#define LAYOUT_STACK_TOP 0xFF00
+#define LAYOUT_STACK_SIZE 0x1000
+// The stack region the emulator uses by default, which --layout compares the program against
+
+#define HEADER_MAGIC        0x534D4953
+// "SMIS" in ASCII, marks a binary which starts with a header
+#define HEADER_VERSION      2
+#define HEADER_WORDS        6
+// The header is made up of the magic number, format version, instruction count, CRC-32 of the instructions,
+// entry point address, and section count, followed by the section table
+#define SECTION_ENTRY_WORDS 3
+// Each section is described by its type, start address, and length in 16-bit words
+#define SECTION_TEXT        1
+#define SECTION_DATA        2
+#define SECTION_BSS         3
+// A .bss section takes up no space in the file, and only records the memory which starts out as zero
+
+#define SYMBOL_FILE_VERSION 1
+// Version of the .sym format written by --symbols, which is also read by the disassembler
+#define OBJECT_FILE_VERSION 2
+// Version of the .o format written for object files, which is read by --link
+#define DEBUG_FILE_VERSION  1
+// Version of the .dbg format written by --debug-info, which is also read by the emulator
+
+#define OUTPUT_BIN          0
+#define OUTPUT_MEMH         1
+#define OUTPUT_MEMH32       2
 
 #define OP_SET              1
 #define OP_COPY             2
@@ -87,44 +146,598 @@ Program overview:
 #define OP_HALT             36
 // TODO: Possibly add exit code to HALT?
 
+#define OP_CUSTOM_MIN       37
+// Opcodes from here up to 255 may be claimed by custom instructions (see registerOpcode())
+
+#define IMMEDIATE_VALID         0
+#define IMMEDIATE_MALFORMED     1
+// The argument does not start with '#', or its digit separators are misplaced
+#define IMMEDIATE_NOT_NUMERIC   2
+// The value is neither a number nor a character literal
+#define IMMEDIATE_TOO_LARGE     3
+// The value does not fit in 16 bits
+#define IMMEDIATE_OUT_OF_RANGE  4
+// The value fits in 16 bits, but is outside of the range accepted by the instruction
+#define IMMEDIATE_BAD_ESCAPE    5
+// The value is a character literal with an unknown escape sequence
+#define MAX_SHIFT_AMOUNT        15
+// Registers are 16 bits wide, so shifting by any more than this always gives 0
+
 
 typedef struct Label {
 
     char* labelName;
     uint16_t PCAddress;
+    uint32_t lineNumber;
+    bool referenced;
+    // Set once any jump or SET uses the label, so unused labels can be reported
 
 } Label;
 
+typedef struct Fixup {
+
+    char* labelName;
+    uint32_t instructionIndex;
+    uint32_t lineNumber;
+
+} Fixup;
+
+typedef struct DataRange {
+
+    uint32_t start;
+    uint32_t length;
+
+} DataRange;
+
+typedef struct SourceLine {
+
+    char* text;
+    char* fileName;
+    uint32_t lineNumber;
+
+} SourceLine;
+
+typedef struct Macro {
+
+    char* name;
+    char** params;
+    uint32_t paramCount;
+    char** lines;
+    uint32_t lineCount;
+    uint32_t lineNumber;
+
+} Macro;
+
+typedef struct Constant {
+
+    char* name;
+    uint16_t value;
+    uint32_t lineNumber;
+
+} Constant;
+
+typedef struct Condition {
+
+    bool active;
+    // Set while the lines of the current branch are assembled
+    bool taken;
+    // Set once a branch of the block has been assembled, or if the whole block is inside a skipped block
+    bool elseSeen;
+    uint32_t lineNumber;
+
+} Condition;
+
+typedef struct BuiltinFile {
+
+    char* name;
+    char* text;
+
+} BuiltinFile;
+
+typedef struct CustomOpcode {
+
+    char* mnemonic;
+    uint8_t opcode;
+    uint32_t (*assemble)(char* instruction);
+    // Returns the lower 24 bits (operands) of the given instruction, the opcode is added by the assembler
+
+} CustomOpcode;
+
+char STD_PRELUDE[] =
+    "// SMIS standard macro library\n"
+    "\n"
+    "// Prints the zero-terminated string at STR\n"
+    ".macro print_string STR\n"
+    "SUBTRACT-IMM RSP RSP #2\n"
+    "STORE R11 RSP #0\n"
+    "STORE R12 RSP #1\n"
+    "COPY R11 \\STR\n"
+    "print_string_loop_\\@:\n"
+    "LOAD R12 R11 #0\n"
+    "ADD-IMM R12 R12 #0\n"
+    "JUMP-IF-ZERO print_string_end_\\@\n"
+    "STORE R12 RZR #65280\n"
+    "ADD-IMM R11 R11 #1\n"
+    "JUMP print_string_loop_\\@\n"
+    "print_string_end_\\@:\n"
+    "LOAD R11 RSP #0\n"
+    "LOAD R12 RSP #1\n"
+    "ADD-IMM RSP RSP #2\n"
+    ".endm\n"
+    "\n"
+    "// Copies COUNT words from SRC to DEST\n"
+    ".macro memcpy DEST SRC COUNT\n"
+    "SUBTRACT-IMM RSP RSP #7\n"
+    "STORE R9 RSP #0\n"
+    "STORE R10 RSP #1\n"
+    "STORE R11 RSP #2\n"
+    "STORE R12 RSP #3\n"
+    "STORE \\DEST RSP #4\n"
+    "STORE \\SRC RSP #5\n"
+    "STORE \\COUNT RSP #6\n"
+    "LOAD R9 RSP #4\n"
+    "LOAD R10 RSP #5\n"
+    "LOAD R11 RSP #6\n"
+    "memcpy_loop_\\@:\n"
+    "ADD-IMM R11 R11 #0\n"
+    "JUMP-IF-ZERO memcpy_end_\\@\n"
+    "LOAD R12 R10 #0\n"
+    "STORE R12 R9 #0\n"
+    "ADD-IMM R9 R9 #1\n"
+    "ADD-IMM R10 R10 #1\n"
+    "SUBTRACT-IMM R11 R11 #1\n"
+    "JUMP memcpy_loop_\\@\n"
+    "memcpy_end_\\@:\n"
+    "LOAD R9 RSP #0\n"
+    "LOAD R10 RSP #1\n"
+    "LOAD R11 RSP #2\n"
+    "LOAD R12 RSP #3\n"
+    "ADD-IMM RSP RSP #7\n"
+    ".endm\n"
+    "\n"
+    "// Saves R1 through RBP to the stack\n"
+    ".macro push_all\n"
+    "SUBTRACT-IMM RSP RSP #14\n"
+    "STORE R1 RSP #0\n"
+    "STORE R2 RSP #1\n"
+    "STORE R3 RSP #2\n"
+    "STORE R4 RSP #3\n"
+    "STORE R5 RSP #4\n"
+    "STORE R6 RSP #5\n"
+    "STORE R7 RSP #6\n"
+    "STORE R8 RSP #7\n"
+    "STORE R9 RSP #8\n"
+    "STORE R10 RSP #9\n"
+    "STORE R11 RSP #10\n"
+    "STORE R12 RSP #11\n"
+    "STORE RLR RSP #12\n"
+    "STORE RBP RSP #13\n"
+    ".endm\n"
+    "\n"
+    "// Restores the registers saved by push_all\n"
+    ".macro pop_all\n"
+    "LOAD R1 RSP #0\n"
+    "LOAD R2 RSP #1\n"
+    "LOAD R3 RSP #2\n"
+    "LOAD R4 RSP #3\n"
+    "LOAD R5 RSP #4\n"
+    "LOAD R6 RSP #5\n"
+    "LOAD R7 RSP #6\n"
+    "LOAD R8 RSP #7\n"
+    "LOAD R9 RSP #8\n"
+    "LOAD R10 RSP #9\n"
+    "LOAD R11 RSP #10\n"
+    "LOAD R12 RSP #11\n"
+    "LOAD RLR RSP #12\n"
+    "LOAD RBP RSP #13\n"
+    "ADD-IMM RSP RSP #14\n"
+    ".endm\n"
+    "\n"
+    "// Sets DEST to the smaller of A and B\n"
+    ".macro min DEST A B\n"
+    "SUBTRACT-IMM RSP RSP #1\n"
+    "STORE R12 RSP #0\n"
+    "SUBTRACT R12 \\A \\B\n"
+    "SHIFT-RIGHT-IMM R12 R12 #15\n"
+    "LOAD R12 RSP #0\n"
+    "JUMP-IF-ZERO min_b_\\@\n"
+    "COPY \\DEST \\A\n"
+    "JUMP min_end_\\@\n"
+    "min_b_\\@:\n"
+    "COPY \\DEST \\B\n"
+    "min_end_\\@:\n"
+    "ADD-IMM RSP RSP #1\n"
+    ".endm\n"
+    "\n"
+    "// Sets DEST to the larger of A and B\n"
+    ".macro max DEST A B\n"
+    "SUBTRACT-IMM RSP RSP #1\n"
+    "STORE R12 RSP #0\n"
+    "SUBTRACT R12 \\A \\B\n"
+    "SHIFT-RIGHT-IMM R12 R12 #15\n"
+    "LOAD R12 RSP #0\n"
+    "JUMP-IF-ZERO max_a_\\@\n"
+    "COPY \\DEST \\B\n"
+    "JUMP max_end_\\@\n"
+    "max_a_\\@:\n"
+    "COPY \\DEST \\A\n"
+    "max_end_\\@:\n"
+    "ADD-IMM RSP RSP #1\n"
+    ".endm\n"
+    "\n"
+    "// Multiplies A and B as unsigned numbers, setting HI and LO to the upper and lower 16 bits of the 32-bit result\n"
+    ".macro mul32 HI LO A B\n"
+    "SUBTRACT-IMM RSP RSP #9\n"
+    "STORE R6 RSP #0\n"
+    "STORE R7 RSP #1\n"
+    "STORE R8 RSP #2\n"
+    "STORE R9 RSP #3\n"
+    "STORE R10 RSP #4\n"
+    "STORE R11 RSP #5\n"
+    "STORE R12 RSP #6\n"
+    "STORE \\A RSP #7\n"
+    "STORE \\B RSP #8\n"
+    "SET R6 #0\n"
+    "LOAD R7 RSP #7\n"
+    "LOAD R8 RSP #8\n"
+    "SET R9 #0\n"
+    "SET R10 #0\n"
+    "mul32_loop_\\@:\n"
+    "ADD-IMM R8 R8 #0\n"
+    "JUMP-IF-ZERO mul32_end_\\@\n"
+    "AND-IMM R11 R8 #1\n"
+    "JUMP-IF-ZERO mul32_shift_\\@\n"
+    "ADD R11 R9 R7\n"
+    "AND R12 R9 R7\n"
+    "NOR R9 R9 R7\n"
+    "NOR R9 R9 R11\n"
+    "OR R12 R12 R9\n"
+    "SHIFT-RIGHT-IMM R12 R12 #15\n"
+    "COPY R9 R11\n"
+    "ADD R10 R10 R6\n"
+    "ADD R10 R10 R12\n"
+    "mul32_shift_\\@:\n"
+    "SHIFT-RIGHT-IMM R11 R7 #15\n"
+    "SHIFT-LEFT-IMM R6 R6 #1\n"
+    "OR R6 R6 R11\n"
+    "SHIFT-LEFT-IMM R7 R7 #1\n"
+    "SHIFT-RIGHT-IMM R8 R8 #1\n"
+    "JUMP mul32_loop_\\@\n"
+    "mul32_end_\\@:\n"
+    "STORE R10 RSP #7\n"
+    "STORE R9 RSP #8\n"
+    "LOAD R6 RSP #0\n"
+    "LOAD R7 RSP #1\n"
+    "LOAD R8 RSP #2\n"
+    "LOAD R9 RSP #3\n"
+    "LOAD R10 RSP #4\n"
+    "LOAD R11 RSP #5\n"
+    "LOAD R12 RSP #6\n"
+    "LOAD \\HI RSP #7\n"
+    "LOAD \\LO RSP #8\n"
+    "ADD-IMM RSP RSP #9\n"
+    ".endm\n";
+// Macro library which is built into the assembler, whose lines must fit within MAX_STRING_LEN like any other
+// The macros keep every register other than their destination, but may change the flags, and none of their operands
+// may be RSP since they keep temporary values on the stack
+// min and max compare signed values whose difference fits in 16 bits, since they test the top bit of the difference
+// (there is no jump on the sign flag)
+// mul32 shifts and adds one bit of B at a time, where the carry out of the lower half is the top bit of
+// (a & b) | ((a | b) & ~sum), since there is no carry flag
+
+char PSEUDO_INSTRUCTIONS[] =
+    ".macro NOP\n"
+    "ADD RZR RZR RZR\n"
+    ".endm\n"
+    ".macro INCREMENT REG\n"
+    "ADD-IMM \\REG \\REG #1\n"
+    ".endm\n"
+    ".macro DECREMENT REG\n"
+    "SUBTRACT-IMM \\REG \\REG #1\n"
+    ".endm\n"
+    ".macro CLEAR REG\n"
+    "SET \\REG #0\n"
+    ".endm\n"
+    ".macro PUSH REG\n"
+    "SUBTRACT-IMM RSP RSP #1\n"
+    "STORE \\REG RSP #0\n"
+    ".endm\n"
+    ".macro POP REG\n"
+    "LOAD \\REG RSP #0\n"
+    "ADD-IMM RSP RSP #1\n"
+    ".endm\n";
+// Convenience mnemonics which are lowered to real instructions, defined as macros which are always available
+// PUSH and POP grow the stack downwards from RSP, the same way as the macros in the standard library
+
+BuiltinFile BUILTIN_FILES[] = {
+
+    {"std", STD_PRELUDE},
+    {"stdlib", STD_PRELUDE}
+
+};
+// Files which can be included with '.include <name>' or '.use <name>' without being on disk
+
+char* MNEMONICS[] = {
+
+    "SET", "COPY",
+    "ADD", "SUBTRACT", "MULTIPLY", "DIVIDE", "MODULO",
+    "COMPARE",
+    "SHIFT-LEFT", "SHIFT-RIGHT",
+    "AND", "OR", "XOR", "NAND", "NOR", "NOT",
+    "ADD-IMM", "SUBTRACT-IMM", "MULTIPLY-IMM", "DIVIDE-IMM", "MODULO-IMM",
+    "COMPARE-IMM", "SHIFT-LEFT-IMM", "SHIFT-RIGHT-IMM", "AND-IMM", "OR-IMM", "XOR-IMM", "NAND-IMM", "NOR-IMM",
+    "LOAD", "STORE",
+    "JUMP", "JUMP-IF-ZERO", "JUMP-IF-NOTZERO", "JUMP-LINK",
+    "HALT"
+
+};
+// Mnemonics of the built-in instructions, which --fmt uses to recognize instructions written in the wrong case,
+// and which cannot be used as label names
+
 
 Label* SYMBOL_TABLE;
 // Stores all labels in the assembled file
 uint32_t SYMBOL_COUNT = 0;
 // Stores the amount of symbols to avoid iterating over unallocated pointers
 
+Fixup* FIXUP_TABLE;
+// Stores all jumps to labels which have not been defined yet
+uint32_t FIXUP_COUNT = 0;
+// Stores the amount of unresolved fixups
+
+CustomOpcode* CUSTOM_OPCODES = NULL;
+// Stores all instructions registered by extensions
+uint32_t CUSTOM_OPCODE_COUNT = 0;
+// Stores the amount of custom instructions
+
+uint32_t* PROGRAM;
+// Stores the assembled instructions until they are written to the output file
+uint32_t PROGRAM_LEN = 0;
+// Stores the amount of assembled instructions
+SourceLine* PROGRAM_SOURCE;
+// Stores the line of code each assembled instruction came from
+
+uint16_t* DATA = NULL;
+uint32_t DATA_LEN = 0;
+// Stores the words declared in .data sections, which are placed in memory directly after the instructions
+Label* DATA_LABELS = NULL;
+uint32_t DATA_LABEL_COUNT = 0;
+// Stores the labels of .data sections along with their offset into the data, until the address of the data is known
+bool DATA_SECTION = false;
+// Set by .data and cleared by .text, while lines are assembled into the data section instead of the program
+uint16_t DATA_ALIGNMENT = 1;
+// Largest .align used in a .data section, which the start of the data section is aligned to as well
+DataRange* PINNED_DATA = NULL;
+uint32_t PINNED_DATA_COUNT = 0;
+// Stores the data which stays where it was placed in the program rather than being moved to the end, that of each
+// '.data #<address>' section and each linked object file, so that each one gets its own .data section
+bool PINNED_SECTION = false;
+// Set by '.data #<address>' until the next section directive, while data words are placed straight into the program
+
+uint32_t BSS_LEN = 0;
+// Number of words reserved in .bss sections, which are placed in memory after the data but not written to the file
+Label* BSS_LABELS = NULL;
+uint32_t BSS_LABEL_COUNT = 0;
+// Stores the labels of .bss sections along with their offset into the reserved words, like DATA_LABELS
+bool BSS_SECTION = false;
+// Set by .bss along with DATA_SECTION, while labels and .space directives reserve memory instead of adding data
+uint16_t BSS_ALIGNMENT = 1;
+// Largest .align used in a .bss section, which the start of the .bss section is aligned to as well
+
+Constant* CONSTANT_TABLE = NULL;
+uint32_t CONSTANT_COUNT = 0;
+// Stores all constants defined with .const, EQU, .define, or --define
+
+Condition* CONDITION_STACK = NULL;
+uint32_t CONDITION_DEPTH = 0;
+// Stores the .if blocks which are currently open, with the innermost block last
+
 uint16_t INSTRUCTION_ADDR = 0;
 // Instruction address is stored for symbol table usage
 uint32_t LINE_NUMBER = 1;
 // Line number is stored in order to give more descriptive error messages
-
-
-void readLabels(char* readfile);
-void readInstructions(char* readfile, char* writefile);
+char* CURRENT_FILE = NULL;
+// ASM file which is currently being read, which changes while an included file is read
+uint32_t INCLUDE_DEPTH = 0;
+// Number of .include directives which are currently being read, which makes errors and warnings name the file
+
+char** INCLUDE_DIRS = NULL;
+uint32_t INCLUDE_DIR_COUNT = 0;
+// Directories given with -I, which are searched in order for included files
+char** INCLUDED_FILES = NULL;
+uint32_t INCLUDED_FILE_COUNT = 0;
+// Every file read through .include, used for the dependency file
+char** USED_LIBRARIES = NULL;
+uint32_t USED_LIBRARY_COUNT = 0;
+// Text of every built-in file read through .use, so that a library used by several files is only read once
+
+Macro* MACRO_TABLE = NULL;
+uint32_t MACRO_COUNT = 0;
+// Stores all macros defined with .macro
+Macro* RECORDING_MACRO = NULL;
+// Macro whose body is currently being read, until the matching .endm
+uint32_t MACRO_DEPTH = 0;
+// Number of macro expansions which are currently being assembled
+uint32_t MACRO_EXPANSION_COUNT = 0;
+// Number of macro expansions so far, which is substituted for \@ to give each expansion its own labels
+
+bool WARN_RZR = false;
+// Warns about instructions which write to RZR, since the written value is discarded
+bool LINT_WARNINGS = true;
+// Warns about unused labels, unreachable instructions, and jumps to addresses without an instruction, which usually
+// point to a mistyped label name
+uint32_t WARNING_COUNT = 0;
+// Stores the amount of warnings printed so far
+uint32_t ERROR_COUNT = 0;
+// Stores the amount of errors printed so far in lines which were skipped
+bool RECOVER_ERRORS = false;
+jmp_buf LINE_RECOVERY;
+// While the lines of the ASM file are read, an error skips to the next line instead of stopping the assembler,
+// so that every error in the file is reported at once
+bool AFTER_UNCONDITIONAL = false;
+// Set after an unconditional JUMP or HALT, until the next label makes the following code reachable again
+uint32_t* UNREACHABLE = NULL;
+uint32_t UNREACHABLE_COUNT = 0;
+// Indices of the instructions which follow an unconditional JUMP or HALT, which are only reported once every jump
+// is known, since a jump to an explicit address can reach them without a label
+bool RAW_OUTPUT = false;
+// Omits the header, so that the output file contains nothing but instructions
+bool FORCE_EXTENSIONS = false;
+// Accepts files with any extension, such as .s or .smis sources, instead of requiring the usual ones
+bool PRINT_STATS = false;
+// Prints a summary of the size and makeup of the program once it has been assembled
+bool OPTIMIZE = false;
+// Removes instructions which have no effect before the program is written
+bool PRINT_LAYOUT = false;
+// Prints the address of every label and the extent of each section instead of writing the program
+char* ENTRY_LABEL = NULL;
+uint32_t ENTRY_LINE = 0;
+// Label named by an '.entry <label>' directive, where execution starts instead of at address 0
+uint16_t ENTRY_POINT = 0;
+// Address of the entry label, which is written to the header
+int OUTPUT_FORMAT = OUTPUT_BIN;
+// Format of the output file, either a binary or a hex text file which can be loaded with Verilog's $readmemh
+bool SEMICOLON_COMMENTS = false;
+// Treats everything from a ';' to the end of the line as a comment, as in many other assembly dialects
+bool HASH_COMMENTS = false;
+// Treats lines starting with '#' as comments
+char* UNFINISHED_OUTPUT = NULL;
+// Temporary file of the output file which is being written, which is removed if the assembler stops before it is done
+
+uint32_t* RELOCATIONS = NULL;
+uint32_t RELOCATION_COUNT = 0;
+// Indices of the instructions which hold a label address, which has to be moved if the program is linked elsewhere
+char** GLOBAL_LABELS = NULL;
+uint32_t GLOBAL_LABEL_COUNT = 0;
+// Labels declared with .global, which object files export for other object files to use
+char** CALLED_LABELS = NULL;
+uint32_t CALLED_LABEL_COUNT = 0;
+// Labels used by a JUMP-LINK, which the symbol file marks as functions
+
+
+void readInstructions(char* readfile);
+void readLines(FILE* asmFile, char* filename);
+void assembleLine(char* line);
+void abortLine() __attribute__((noreturn));
+void includeFile(char* directive);
+void useLibrary(char* directive);
+char* findIncludeFile(char* name);
+void writeDepFile(char* depfile, char* txtfile, char* binfile);
+uint32_t* buildImage(uint32_t* imageLen);
+void writeProgram(char* writefile);
+void setEntryPoint(char* directive);
+void resolveEntryPoint();
+bool hasOutputExtension(char* file);
+void writeIR(char* irfile, char* sourcefile);
+void writeSymbolFile(char* symfile);
+void writeLabelMap(char* labelfile);
+void writeDebugInfo(char* debugfile);
+void printStats();
+void printLayout();
+void printLayoutSection(char* name, uint32_t start, uint32_t len);
 uint32_t assembleInstruction(char* instruction);
 // Program control functions
 
+void placeLabel(char* lbl);
+void defineLabel(char* lbl);
+void checkDuplicateLabel(char* lbl);
+void checkLabelName(char* lbl);
+void addFixup(char* lbl);
+void checkUnresolvedFixups();
+void checkUnusedLabels();
+void checkJumpTargets();
+void checkUnreachableCode();
+void addCalledLabel(char* lbl);
+bool isCalledLabel(char* lbl);
+// Symbol table functions
+
+void declareGlobal(char* directive);
+bool isGlobalLabel(char* lbl);
+void writeObject(char* objfile);
+void linkObjects(char** objfiles, int objCount);
+void readObjectFile(char* objfile);
+// Object file functions
+
+void defineMacro(char* directive);
+Macro* findMacro(char* name);
+void expandMacro(Macro* macro, char* invocation);
+char* substituteMacroParams(Macro* macro, char* line, char** args);
+void checkUnclosedMacro();
+void definePseudoInstructions();
+// Macro functions
+
+void assembleData(char* line);
+void addDataLabel(char* lbl);
+void addDataWord(uint16_t word, char* directive);
+void addDataString(char* directive, bool terminated);
+void placeData();
+void startPinnedData(char* directive);
+bool isPinnedData(uint32_t addr);
+uint32_t getBssStart();
+// Data section functions
+
+void defineConstant(char* directive);
+Constant* findConstant(char* name);
+char* substituteConstants(char* line);
+// Constant functions
+
+bool isConditionalDirective(char* line);
+void assembleConditional(char* line);
+bool evaluateCondition(char* directive, char* operand);
+bool isConditionActive();
+void defineSymbol(char* definition);
+void checkUnclosedConditional();
+// Conditional assembly functions
+
+void formatSource(char* txtfile);
+char* formatLine(char* line);
+bool isMnemonic(char* str);
+// Formatter functions
+
+void setOrigin(char* directive);
+void alignProgram(char* directive);
+void alignData(char* directive);
+void reserveData(char* directive);
+void padProgram(uint16_t addr, char* directive);
+uint16_t getDirectiveVal(char* directive);
+// Address directive functions
+
+void optimizeProgram();
+bool canOptimize();
+void removeInstruction(uint32_t index);
+int getDestRegister(uint32_t instruction);
+bool readsRegister(uint32_t instruction, uint8_t reg);
+bool isIdentityImmediate(uint32_t instruction);
+bool areFlagsUnused(uint32_t index);
+// Optimizer functions
+
 uint32_t RType(char* instruction);
 uint32_t IType(char* instruction);
 uint32_t JType(char* instruction);
 uint32_t SType(char* instruction);
+uint32_t CType(char* instruction);
 // Instruction assembly functions
 
+void registerOpcode(char* mnemonic, uint8_t opcode, uint32_t (*assemble)(char* instruction));
+void registerExtensions() __attribute__((weak));
+// Custom opcode extension functions
+
+void warnIfZeroRegisterWrite(uint8_t rDest, char* instruction);
+void printWarning(char* format, ...) __attribute__((format(printf, 1, 2)));
+void printSourceSpan(FILE* out, char* instruction, size_t start, size_t len);
+void printWordSpan(FILE* out, char* instruction, int w);
 uint16_t getLabelAddr(char* lbl);
 uint8_t getRegisterNum(char* str);
 uint16_t getImmediateVal(char* str);
+int getImmediateError(char* str);
+void checkImmediate(char* instruction, int arg, uint16_t max);
 bool fitsRegisterSyntax(char* str);
 bool fitsImmediateSyntax(char* str);
+bool fitsLabelRefSyntax(char* str);
 int countArgs(char* instruction);//
 bool isBlankLineOrComment(char* str);//
+char* findComment(char* str);
+void stripComment(char* str);
 bool isLabel(char* str);//
 // Assembler utility functions
 
@@ -132,17 +745,250 @@ void trimLineBreak(char* str);//
 void trimLabelColon(char* str);//
 void trimChar(char* str, char c);//
 bool containsOnlyNums(char* str);//
+int parseCharLiteral(char* str);
+int parseEscapeSequence(char* str, int* len);
+long parseNumericLiteral(char* str);
+char* stripDigitSeparators(char* str);
 char* getFirstWord(char* str);//
 char* getWord(char* str, int w);//
 char* getBinary(uint32_t n, int length);
 unsigned char binaryChar(uint8_t n);
+uint32_t crc32(uint8_t* data, size_t len);
+void printJsonString(FILE* out, char* str);
 bool endsWith(char* str, char* substr);//
+FILE* openOutputFile(char* file, char* mode);
+void closeOutputFile(FILE* out, char* file);
+void removeUnfinishedOutput();
+void checkNotInputFile(char* output, char* input);
 // General utility functions
 
 
 int main(int argc, char** argv) {
 
-    if(argc != 3) {
+    char** files = malloc(argc * sizeof(char*));
+    int fileCount = 0;
+    char* irfile = NULL;
+    char* symfile = NULL;
+    char* labelfile = NULL;
+    char* depfile = NULL;
+    char* debugfile = NULL;
+    bool link = false;
+    bool format = false;
+    char** defines = malloc(argc * sizeof(char*));
+    int defineCount = 0;
+
+    for(int i = 1; i < argc; i++) {
+
+        if(!strncmp(argv[i], "--emit", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No IR file supplied for --emit.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            irfile = argv[i];
+
+        } else if(!strncmp(argv[i], "--emit-labels", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No label file supplied for --emit-labels.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            labelfile = argv[i];
+
+        } else if(!strncmp(argv[i], "--symbols", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No symbol file supplied for --symbols.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            symfile = argv[i];
+
+        } else if(!strncmp(argv[i], "--dep-file", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No dependency file supplied for --dep-file.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            depfile = argv[i];
+
+        } else if(!strncmp(argv[i], "--debug-info", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No debug info file supplied for --debug-info.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            debugfile = argv[i];
+
+        } else if(!strncmp(argv[i], "--define", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No name supplied for --define.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            defines[defineCount++] = argv[i];
+
+        } else if(!strncmp(argv[i], "--format", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No format supplied for --format.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            if(!strncmp(argv[i], "bin", MAX_STRING_LEN)) OUTPUT_FORMAT = OUTPUT_BIN;
+            else if(!strncmp(argv[i], "memh", MAX_STRING_LEN)) OUTPUT_FORMAT = OUTPUT_MEMH;
+            else if(!strncmp(argv[i], "memh32", MAX_STRING_LEN)) OUTPUT_FORMAT = OUTPUT_MEMH32;
+            else {
+
+                printf("Unknown output format %s.\n", argv[i]);
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+        } else if(!strncmp(argv[i], "-I", 2)) {
+
+            if(!argv[i][2] && ++i >= argc) {
+
+                printf("No directory supplied for -I.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            INCLUDE_DIRS = realloc(INCLUDE_DIRS, (INCLUDE_DIR_COUNT + 1) * sizeof(char*));
+            INCLUDE_DIRS[INCLUDE_DIR_COUNT++] = argv[i][0] == '-' && argv[i][1] == 'I' ? argv[i] + 2 : argv[i];
+            // Both "-I dir" and "-Idir" are accepted
+
+        } else if(!strncmp(argv[i], "--warn-rzr", MAX_STRING_LEN)) WARN_RZR = true;
+        else if(!strncmp(argv[i], "--raw", MAX_STRING_LEN)) RAW_OUTPUT = true;
+        else if(!strncmp(argv[i], "--stats", MAX_STRING_LEN)) PRINT_STATS = true;
+        else if(!strncmp(argv[i], "--optimize", MAX_STRING_LEN)) OPTIMIZE = true;
+        else if(!strncmp(argv[i], "--layout", MAX_STRING_LEN)) PRINT_LAYOUT = true;
+        else if(!strncmp(argv[i], "--force", MAX_STRING_LEN) || !strncmp(argv[i], "--no-ext-check", MAX_STRING_LEN)) FORCE_EXTENSIONS = true;
+        else if(!strncmp(argv[i], "--no-lint", MAX_STRING_LEN)) LINT_WARNINGS = false;
+        else if(!strncmp(argv[i], "--semicolon-comments", MAX_STRING_LEN)) SEMICOLON_COMMENTS = true;
+        else if(!strncmp(argv[i], "--hash-comments", MAX_STRING_LEN)) HASH_COMMENTS = true;
+        else if(!strncmp(argv[i], "--link", MAX_STRING_LEN)) link = true;
+        else if(!strncmp(argv[i], "--fmt", MAX_STRING_LEN)) format = true;
+        else files[fileCount++] = argv[i];
+
+    }
+
+    atexit(removeUnfinishedOutput);
+
+    if(format) {
+
+        if(fileCount != 1 || !(FORCE_EXTENSIONS || endsWith(files[0], ".txt"))) {
+
+            printf("Exactly one .txt ASM file must be supplied for --fmt.\n");
+            printf(USAGE);
+            exit(-1);
+
+        }
+
+        if(registerExtensions) registerExtensions();
+
+        definePseudoInstructions();
+        formatSource(files[0]);
+
+        free(files);
+        return 0;
+
+    }
+
+    if(link && PRINT_LAYOUT) {
+
+        printf("The layout can only be printed when assembling, since linking always writes the program.\n");
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    if(link) {
+
+        if(fileCount < 2) {
+
+            printf("Incorrect number of arguments supplied.\n");
+            printf(USAGE);
+            exit(-1);
+
+        }
+
+        for(int i = 0; i < fileCount; i++) {
+
+            if(!FORCE_EXTENSIONS && (i < fileCount - 1 ? !endsWith(files[i], ".o") : !hasOutputExtension(files[i]))) {
+
+                printf("One or more of the supplied files have incorrect extensions.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+        }
+
+        if(debugfile) {
+
+            printf("Debug info cannot be written when linking, since object files do not record source lines.\n");
+            printf(USAGE);
+            exit(-1);
+
+        }
+
+        for(int i = 0; i < fileCount - 1; i++) {
+
+            checkNotInputFile(files[fileCount - 1], files[i]);
+            if(symfile) checkNotInputFile(symfile, files[i]);
+            if(labelfile) checkNotInputFile(labelfile, files[i]);
+
+        }
+
+        if(OPTIMIZE) {
+
+            printf("Programs cannot be optimized when linking, since object files do not record which addresses are labels.\n");
+            printf(USAGE);
+            exit(-1);
+
+        }
+
+        linkObjects(files, fileCount - 1);
+        writeProgram(files[fileCount - 1]);
+        if(symfile) writeSymbolFile(symfile);
+        if(labelfile) writeLabelMap(labelfile);
+        if(PRINT_STATS) printStats();
+
+        free(files);
+        return 0;
+
+    }
+
+    if(fileCount != (PRINT_LAYOUT ? 1 : 2)) {
 
         printf("Incorrect number of arguments supplied.\n");
         printf(USAGE);
@@ -150,70 +996,116 @@ int main(int argc, char** argv) {
 
     }
 
-    if(!endsWith(argv[1], ".txt") || !endsWith(argv[2], ".bin")) {
+    char* txtfile = files[0];
+    char* binfile = PRINT_LAYOUT ? ".bin" : files[1];
 
-        printf("One or both of the supplied files have incorrect extensions.\n");
+    if(PRINT_LAYOUT && depfile) {
+
+        printf("A dependency file cannot be written with --layout, since no program file is written.\n");
         printf(USAGE);
         exit(-1);
 
     }
 
-    SYMBOL_TABLE = NULL;
+    if(!FORCE_EXTENSIONS && (!endsWith(txtfile, ".txt") || !(hasOutputExtension(binfile) || endsWith(binfile, ".o")))) {
 
-    readLabels(argv[1]);
-    readInstructions(argv[1], argv[2]);
+        printf("One or both of the supplied files have incorrect extensions.\n");
+        printf(USAGE);
+        exit(-1);
 
-    free(SYMBOL_TABLE);
+    }
 
-}
+    char* outputs[] = {binfile, irfile, symfile, labelfile, debugfile, depfile};
 
-void readLabels(char* readfile) {
-    // Reads all jump labels into the symbol table for use in assembling jump instructions
+    for(int i = 0; i < sizeof(outputs) / sizeof(char*); i++) if(outputs[i]) checkNotInputFile(outputs[i], txtfile);
 
-    FILE* asmFile;
+    SYMBOL_TABLE = NULL;
+    FIXUP_TABLE = NULL;
+    PROGRAM = NULL;
+    PROGRAM_SOURCE = NULL;
 
-    if(!(asmFile = fopen(readfile, "r"))) {
+    if(registerExtensions) registerExtensions();
 
-        printf("File %s does not exist.\n", readfile);
-        printf(USAGE);
+    definePseudoInstructions();
+    for(int i = 0; i < defineCount; i++) defineSymbol(defines[i]);
+    readInstructions(txtfile);
+    checkUnclosedMacro();
+    checkUnclosedConditional();
+    placeData();
+    if(!endsWith(binfile, ".o")) checkUnresolvedFixups();
+    // Labels which are still unresolved in an object file are imported from other object files when linking
+
+    if(ERROR_COUNT) {
+
+        printf("%s could not be assembled because of %u error(s)\n", txtfile, ERROR_COUNT);
         exit(-1);
 
     }
 
-    char* line = malloc(MAX_INSTRUCTION_LEN * sizeof(char));
+    if(endsWith(binfile, ".o") && ENTRY_LABEL) {
 
-    while(fgets(line, MAX_INSTRUCTION_LEN, asmFile)) {
-
-        if(isBlankLineOrComment(line)) continue;
+        printf("The entry point at line %i cannot be set in an object file, since linked programs start at address 0\n", ENTRY_LINE);
+        exit(-1);
 
-        if(isLabel(line)) {
+    }
 
-            trimLabelColon(line);
+    if(endsWith(binfile, ".o") && BSS_LEN) {
 
-            Label l;
-            l.labelName = strndup(line, MAX_INSTRUCTION_LEN);
-            l.PCAddress = INSTRUCTION_ADDR;
+        printf("A .bss section cannot be used in an object file, since object files only hold their image.\n");
+        exit(-1);
 
-            SYMBOL_TABLE = realloc(SYMBOL_TABLE, (SYMBOL_COUNT + 1) * sizeof(Label));
+    }
 
-            SYMBOL_TABLE[SYMBOL_COUNT] = l;
-            
-            SYMBOL_COUNT++;
+    if(endsWith(binfile, ".o") && OPTIMIZE) {
 
-        } else INSTRUCTION_ADDR += 2;
+        printf("Object files cannot be optimized, since their addresses are moved again when they are linked.\n");
+        printf(USAGE);
+        exit(-1);
 
     }
 
-    fclose(asmFile);
-    free(line);
+    resolveEntryPoint();
+    checkUnreachableCode();
+    checkUnusedLabels();
+    checkJumpTargets();
+    if(OPTIMIZE) optimizeProgram();
+    if(PRINT_LAYOUT) printLayout();
+    else if(endsWith(binfile, ".o")) writeObject(binfile);
+    else writeProgram(binfile);
+    if(irfile) writeIR(irfile, txtfile);
+    if(symfile) writeSymbolFile(symfile);
+    if(labelfile) writeLabelMap(labelfile);
+    if(debugfile) writeDebugInfo(debugfile);
+    if(depfile) writeDepFile(depfile, txtfile, binfile);
+    if(PRINT_STATS) printStats();
+
+    if(WARNING_COUNT) fprintf(stderr, "%s assembled with %u warning(s)\n", txtfile, WARNING_COUNT);
+
+    free(SYMBOL_TABLE);
+    free(FIXUP_TABLE);
+    free(PROGRAM);
+    free(PROGRAM_SOURCE);
+    free(DATA);
+    free(DATA_LABELS);
+    free(BSS_LABELS);
+    free(CONSTANT_TABLE);
+    free(RELOCATIONS);
+    free(GLOBAL_LABELS);
+    free(CALLED_LABELS);
+    free(UNREACHABLE);
+    free(CONDITION_STACK);
+    free(USED_LIBRARIES);
+    free(files);
+    free(defines);
+
+    return 0;
 
 }
 
-void readInstructions(char* readfile, char* writefile) {
-    // Reads all instructions from the given file and interprets them
+void readInstructions(char* readfile) {
+    // Reads all labels and instructions from the given file in a single pass, assembling them into the program image
 
     FILE* asmFile;
-    FILE* binFile;
 
     if(!(asmFile = fopen(readfile, "r"))) {
 
@@ -223,34 +1115,65 @@ void readInstructions(char* readfile, char* writefile) {
 
     }
 
-    if(!(binFile = fopen(writefile, "wb"))) {
+    readLines(asmFile, readfile);
 
-        printf("Cannot output to file %s.\n", writefile);
-        printf(USAGE);
-        exit(-1);
+    fclose(asmFile);
 
-    }
+}
 
-    char* instruction = malloc(MAX_INSTRUCTION_LEN * sizeof(char));
+void readLines(FILE* asmFile, char* filename) {
+    // Assembles every line of an opened ASM file, which may be a file on disk or a built-in file
 
-    while(fgets(instruction, MAX_INSTRUCTION_LEN, asmFile)) {
+    char* instruction = malloc(MAX_STRING_LEN * sizeof(char));
+    char* parentFile = CURRENT_FILE;
+    uint32_t parentLineNumber = LINE_NUMBER;
 
-        bool skipLine = false;
+    CURRENT_FILE = filename;
+    LINE_NUMBER = 1;
 
-        if(isBlankLineOrComment(instruction) || isLabel(instruction)) skipLine = true;
-        // Skip line breaks and comments
+    bool parentRecoverErrors = RECOVER_ERRORS;
+    jmp_buf parentRecovery;
+    memcpy(parentRecovery, LINE_RECOVERY, sizeof(jmp_buf));
+    // Errors in an included file resume at the next line of that file, and the including file resumes after it
 
-        if(!skipLine) {
-            
-            int lineBreakIndex = strnlen(instruction, MAX_INSTRUCTION_LEN) - 1;
-            if(instruction[lineBreakIndex] == '\n') instruction[lineBreakIndex] = '\0';
-            // Remove any trailing line breaks from the instruction
+    while(fgets(instruction, MAX_STRING_LEN, asmFile)) {
+
+        bool tooLong = false;
 
-            uint32_t buffer = htonl(assembleInstruction(instruction));
+        if(!strchr(instruction, '\n')) {
 
-            printf("%.8X\n", ntohl(buffer));
+            int c = fgetc(asmFile);
+            tooLong = c != '\n' && c != EOF && !findComment(instruction);
 
-            fwrite(&buffer, sizeof(uint32_t), 1, binFile);
+            while(c != '\n' && c != EOF) c = fgetc(asmFile);
+
+        }
+        // The rest of a line which is too long to be read at once is skipped, which is only an error if it is not
+        // part of a comment, so that the next line is still read as a line of its own
+
+        uint32_t macroDepth = MACRO_DEPTH;
+
+        if(!setjmp(LINE_RECOVERY)) {
+
+            RECOVER_ERRORS = true;
+
+            if(tooLong) {
+
+                printf("Line %i is longer than %i characters\n", LINE_NUMBER, MAX_STRING_LEN - 2);
+                abortLine();
+
+            }
+
+            assembleLine(instruction);
+
+        } else {
+
+            MACRO_DEPTH = macroDepth;
+            // Macros which were being expanded when the error happened are abandoned
+
+            while(FIXUP_COUNT && FIXUP_TABLE[FIXUP_COUNT - 1].instructionIndex >= PROGRAM_LEN) FIXUP_COUNT--;
+            while(RELOCATION_COUNT && RELOCATIONS[RELOCATION_COUNT - 1] >= PROGRAM_LEN) RELOCATION_COUNT--;
+            // References made by the instruction which failed are dropped, since it never took its place in the program
 
         }
 
@@ -258,147 +1181,2739 @@ void readInstructions(char* readfile, char* writefile) {
 
     }
 
-    fclose(asmFile);
-    fclose(binFile);
+    CURRENT_FILE = parentFile;
+    LINE_NUMBER = parentLineNumber;
+    // Line numbers continue where they left off in the including file
+
+    RECOVER_ERRORS = parentRecoverErrors;
+    memcpy(LINE_RECOVERY, parentRecovery, sizeof(jmp_buf));
+
     free(instruction);
 
 }
 
-uint32_t assembleInstruction(char* instruction) {
-    // Assembles all instruction types into their respective numeric values
+void assembleLine(char* line) {
+    // Assembles a single line of the ASM file, which may be a label, an instruction, a blank line, or a comment
+    // This is also the entry point of the parser fuzz target (see fuzz_smisasm.c)
 
-    uint32_t instructionNum = 0;
+    stripComment(line);
 
-    if((instructionNum = RType(instruction))) return instructionNum;
-    else if((instructionNum = IType(instruction))) return instructionNum;
-    else if((instructionNum = JType(instruction))) return instructionNum;
-    else if((instructionNum = SType(instruction))) return instructionNum;
+    if(RECORDING_MACRO) {
 
-    else {
+        trimLineBreak(line);
 
-        printf("Invalid instruction at line %i\n", LINE_NUMBER);
-        printf("Instruction: %s\n", instruction);
+        if(!strncmp(line, ".endm", MAX_INSTRUCTION_LEN)) RECORDING_MACRO = NULL;
+        else {
 
-        exit(-1);
+            RECORDING_MACRO->lines = realloc(RECORDING_MACRO->lines, (RECORDING_MACRO->lineCount + 1) * sizeof(char*));
+            RECORDING_MACRO->lines[RECORDING_MACRO->lineCount++] = strndup(line, MAX_STRING_LEN);
+
+        }
+
+        return;
 
     }
+    // Lines between .macro and .endm are stored, and only assembled when the macro is used
 
-}
+    if(isConditionalDirective(line)) {
 
-uint32_t RType(char* instruction) {
-    // Assembles all basic R-type (register) instructions, excluding COPY, COMPARE, and NOT
-    // Returns 0 if the given string is not a valid R-type instruction
+        assembleConditional(line);
+        return;
 
-    uint32_t instructionNum = 0;
+    }
 
-    char* opcodeStr = getFirstWord(instruction);
-    uint8_t opcodeNum;
+    if(!isConditionActive()) return;
+    // Lines in a branch of an .if block which is not taken are skipped
 
-    if(!strncmp(opcodeStr, "ADD", 4)) opcodeNum = OP_ADD;
-    else if(!strncmp(opcodeStr, "SUBTRACT", 9)) opcodeNum = OP_SUBTRACT;
-    else if(!strncmp(opcodeStr, "MULTIPLY", 9)) opcodeNum = OP_MULTIPLY;
-    else if(!strncmp(opcodeStr, "DIVIDE", 7)) opcodeNum = OP_DIVIDE;
-    else if(!strncmp(opcodeStr, "MODULO", 7)) opcodeNum = OP_MODULO;
+    if(isBlankLineOrComment(line)) return;
+    // Skip line breaks and comments
 
-    else if(!strncmp(opcodeStr, "SHIFT-LEFT", 11)) opcodeNum = OP_SHIFT_LEFT;
-    else if(!strncmp(opcodeStr, "SHIFT-RIGHT", 12)) opcodeNum = OP_SHIFT_RIGHT;
+    if(!strncmp(line, ".include ", 9)) {
 
-    else if(!strncmp(opcodeStr, "AND", 4)) opcodeNum = OP_AND;
-    else if(!strncmp(opcodeStr, "OR", 3)) opcodeNum = OP_OR;
-    else if(!strncmp(opcodeStr, "XOR", 4)) opcodeNum = OP_XOR;
-    else if(!strncmp(opcodeStr, "NAND", 5)) opcodeNum = OP_NAND;
-    else if(!strncmp(opcodeStr, "NOR", 4)) opcodeNum = OP_NOR;
+        includeFile(line);
+        return;
 
-    else return 0;
+    }
 
-    instructionNum += opcodeNum << 24;
+    if(!strncmp(line, ".use ", 5)) {
 
-    if(countArgs(instruction) != 4) {
+        useLibrary(line);
+        return;
 
-        printf("Incorrect number of arguments at line %i\n", LINE_NUMBER);
-        printf("Instruction: %s\n", instruction);
-        exit(-1);
+    }
+
+    if(!strncmp(line, ".macro ", 7)) {
+
+        defineMacro(line);
+        return;
 
     }
 
-    for(int arg = 1; arg <= 3; arg++) {
-        
-        if(!fitsRegisterSyntax(getWord(instruction, arg))) {
+    trimLineBreak(line);
 
-            printf("Wrong format of argument %i at line %i\n", arg, LINE_NUMBER);
-            printf("Instruction: %s\n", instruction);
-            exit(-1);
+    char* first = getFirstWord(line);
+    size_t firstLen = strnlen(first, MAX_STRING_LEN);
 
-        }
+    if(!DATA_SECTION && firstLen > 1 && first[firstLen - 1] == ':' && line[firstLen] == ' ') {
+
+        first[firstLen - 1] = '\0';
+        placeLabel(first);
+
+        line += firstLen + 1;
 
     }
+    // A label may come before an instruction on the same line, as in "loop: ADD R1 R1 R2"
+    // Labels in .data sections are split from their directive by assembleData()
 
-    uint8_t rDest = getRegisterNum(getWord(instruction, 1));
-    uint8_t rOp1 = getRegisterNum(getWord(instruction, 2));
-    uint8_t rOp2 = getRegisterNum(getWord(instruction, 3));
+    free(first);
 
-    instructionNum += rDest << 20;
-    instructionNum += rOp1 << 16;
-    instructionNum += rOp2 << 12;
+    Macro* macro = findMacro(getFirstWord(line));
 
-    return instructionNum;
+    if(macro) {
 
-}
+        expandMacro(macro, line);
+        return;
 
-uint32_t IType(char* instruction) {
-    // Assembles all basic I-type (immediate) instructions, excluding SET and COMPARE-IMM
-    // Returns 0 if the given string is not a valid I-type instruction
+    }
 
-    uint32_t instructionNum = 0;
+    if(!strncmp(line, ".const ", 7) || (strchr(line, ' ') && !strncmp(strchr(line, ' '), " EQU ", 5))) {
 
-    char* opcodeStr = getFirstWord(instruction);
-    uint8_t opcodeNum;
+        defineConstant(line);
+        return;
 
-    if(!strncmp(opcodeStr, "ADD-IMM", 8)) opcodeNum = OP_ADD_IMM;
-    else if(!strncmp(opcodeStr, "SUBTRACT-IMM", 13)) opcodeNum = OP_SUBTRACT_IMM;
-    else if(!strncmp(opcodeStr, "MULTIPLY-IMM", 13)) opcodeNum = OP_MULTIPLY_IMM;
-    else if(!strncmp(opcodeStr, "DIVIDE-IMM", 11)) opcodeNum = OP_DIVIDE_IMM;
-    else if(!strncmp(opcodeStr, "MODULO-IMM", 11)) opcodeNum = OP_MODULO_IMM;
+    }
 
-    else if(!strncmp(opcodeStr, "SHIFT-LEFT-IMM", 15)) opcodeNum = OP_SHIFT_LEFT_IMM;
-    else if(!strncmp(opcodeStr, "SHIFT-RIGHT-IMM", 16)) opcodeNum = OP_SHIFT_RIGHT_IMM;
+    if(!strncmp(line, ".data", MAX_INSTRUCTION_LEN) || !strncmp(line, ".text", MAX_INSTRUCTION_LEN)
+        || !strncmp(line, ".bss", MAX_INSTRUCTION_LEN)) {
 
-    else if(!strncmp(opcodeStr, "AND-IMM", 8)) opcodeNum = OP_AND_IMM;
-    else if(!strncmp(opcodeStr, "OR-IMM", 7)) opcodeNum = OP_OR_IMM;
-    else if(!strncmp(opcodeStr, "XOR-IMM", 8)) opcodeNum = OP_XOR_IMM;
-    else if(!strncmp(opcodeStr, "NAND-IMM", 9)) opcodeNum = OP_NAND_IMM;
-    else if(!strncmp(opcodeStr, "NOR-IMM", 8)) opcodeNum = OP_NOR_IMM;
-    else if(!strncmp(opcodeStr, "LOAD", 5)) opcodeNum = OP_LOAD;
-    else if(!strncmp(opcodeStr, "STORE", 6)) opcodeNum = OP_STORE;
+        DATA_SECTION = line[1] != 't';
+        BSS_SECTION = line[1] == 'b';
+        PINNED_SECTION = false;
+        return;
 
-    else return 0;
+    }
 
-    instructionNum += opcodeNum << 24;
+    if(!strncmp(line, ".data ", 6)) {
 
-    if(countArgs(instruction) != 4) {
+        startPinnedData(line);
+        return;
 
-        printf("Incorrect number of arguments at line %i\n", LINE_NUMBER);
-        printf("Instruction: %s\n", instruction);
-        exit(-1);
+    }
+
+    if(!strncmp(line, ".global ", 8)) {
+
+        declareGlobal(line);
+        return;
+
+    }
+
+    if(!strncmp(line, ".entry ", 7)) {
+
+        setEntryPoint(line);
+        return;
+
+    }
+
+    if(DATA_SECTION) {
+
+        assembleData(line);
+        return;
+
+    }
+
+    if(!strncmp(line, ".org ", 5)) {
+
+        setOrigin(line);
+        return;
+
+    }
+
+    if(!strncmp(line, ".align ", 7)) {
+
+        alignProgram(line);
+        return;
+
+    }
+
+    char* dataDirective = strstr(line, ".word ");
+    if(!dataDirective) dataDirective = strstr(line, ".string ");
+    if(!dataDirective) dataDirective = strstr(line, ".asciiz ");
+    if(!dataDirective) dataDirective = strstr(line, ".ascii ");
+    if(!dataDirective) dataDirective = strstr(line, ".space ");
+
+    if(dataDirective) {
+
+        printf("Data directives must be placed in a .data section, at line %i\n", LINE_NUMBER);
+        printSourceSpan(stdout, line, dataDirective - line, strcspn(dataDirective, " "));
+        abortLine();
+
+    }
+
+    if(isLabel(line)) {
+
+        trimLabelColon(line);
+        placeLabel(line);
+
+    } else {
+
+        int lineBreakIndex = strnlen(line, MAX_STRING_LEN) - 1;
+        if(lineBreakIndex >= 0 && line[lineBreakIndex] == '\n') line[lineBreakIndex] = '\0';
+        // Remove any trailing line breaks from the instruction
+
+        if(LINT_WARNINGS && AFTER_UNCONDITIONAL) {
+
+            UNREACHABLE = realloc(UNREACHABLE, (UNREACHABLE_COUNT + 1) * sizeof(uint32_t));
+            UNREACHABLE[UNREACHABLE_COUNT++] = PROGRAM_LEN;
+
+        }
+
+        line = substituteConstants(line);
+
+        if(PROGRAM_LEN * 2 > INT_LIMIT) {
+
+            printf("The instruction at line %i does not fit in memory, since the program already fills all %i words\n",
+                LINE_NUMBER, INT_LIMIT + 1);
+            exit(-1);
+
+        }
+        // The address of the next instruction would wrap around to 0, so assembling stops here rather than at every
+        // following line
+
+        PROGRAM = realloc(PROGRAM, (PROGRAM_LEN + 1) * sizeof(uint32_t));
+        PROGRAM[PROGRAM_LEN] = assembleInstruction(line);
+
+        PROGRAM_SOURCE = realloc(PROGRAM_SOURCE, (PROGRAM_LEN + 1) * sizeof(SourceLine));
+        PROGRAM_SOURCE[PROGRAM_LEN].text = strndup(line, MAX_STRING_LEN);
+        PROGRAM_SOURCE[PROGRAM_LEN].fileName = CURRENT_FILE;
+        PROGRAM_SOURCE[PROGRAM_LEN].lineNumber = LINE_NUMBER;
+
+        uint8_t opcode = PROGRAM[PROGRAM_LEN] >> 24;
+        AFTER_UNCONDITIONAL = opcode == OP_JUMP || opcode == OP_HALT;
+        // Only the first unreachable instruction of a block is reported
+
+        PROGRAM_LEN++;
+        INSTRUCTION_ADDR += 2;
+
+    }
+
+}
+
+void abortLine() {
+    // Stops assembling the current line after its error has been printed, and resumes at the next line so that the
+    // rest of the file is still checked, or terminates the program if no lines are being read
+
+    if(INCLUDE_DEPTH) printf("Line %i is in included file %s\n", LINE_NUMBER, CURRENT_FILE);
+    // Line numbers count from the start of the included file, so they would be misleading without its name
+
+    if(!RECOVER_ERRORS) exit(-1);
+
+    ERROR_COUNT++;
+    longjmp(LINE_RECOVERY, 1);
+
+}
+
+void includeFile(char* directive) {
+    // Reads the file named by an '.include "<file>"' directive as if its lines appeared in place of the directive
+
+    char name[MAX_STRING_LEN];
+
+    trimLineBreak(directive);
+
+    if(sscanf(directive, ".include <%[^>]>", name) == 1) {
+
+        for(int i = 0; i < sizeof(BUILTIN_FILES) / sizeof(BuiltinFile); i++) {
+
+            if(strncmp(name, BUILTIN_FILES[i].name, MAX_STRING_LEN)) continue;
+
+            FILE* builtinFile = fmemopen(BUILTIN_FILES[i].text, strlen(BUILTIN_FILES[i].text), "r");
+            char* builtinName = malloc((MAX_STRING_LEN + 2) * sizeof(char));
+            snprintf(builtinName, MAX_STRING_LEN + 2, "<%s>", name);
+
+            INCLUDE_DEPTH++;
+            readLines(builtinFile, builtinName);
+            INCLUDE_DEPTH--;
+
+            fclose(builtinFile);
+            return;
+
+        }
+
+        printf("There is no built-in file named <%s> at line %i of %s\n", name, LINE_NUMBER, CURRENT_FILE);
+        abortLine();
+
+    }
+
+    if(sscanf(directive, ".include \"%[^\"]\"", name) != 1) {
+
+        printf("Invalid include directive at line %i\n", LINE_NUMBER);
+        printf("Directive: %s\n", directive);
+        abortLine();
+
+    }
+
+    if(INCLUDE_DEPTH >= MAX_INCLUDE_DEPTH) {
+
+        printf("Includes are nested more than %i levels deep at line %i of %s, which probably means a file includes itself\n",
+            MAX_INCLUDE_DEPTH, LINE_NUMBER, CURRENT_FILE);
+        abortLine();
+
+    }
+
+    char* path = findIncludeFile(name);
+
+    if(!path) {
+
+        printf("Cannot find included file %s at line %i of %s\n", name, LINE_NUMBER, CURRENT_FILE);
+        abortLine();
+
+    }
+
+    INCLUDED_FILES = realloc(INCLUDED_FILES, (INCLUDED_FILE_COUNT + 1) * sizeof(char*));
+    INCLUDED_FILES[INCLUDED_FILE_COUNT++] = path;
+
+    INCLUDE_DEPTH++;
+    readInstructions(path);
+    INCLUDE_DEPTH--;
+
+}
+
+void useLibrary(char* directive) {
+    // Reads the built-in file named by a '.use <name>' directive, unless an earlier .use has already read it, so that
+    // every file of a program can use the libraries it needs without defining their macros twice
+
+    char name[MAX_STRING_LEN];
+    char include[MAX_STRING_LEN];
+
+    trimLineBreak(directive);
+
+    if(countArgs(directive) != 2 || sscanf(directive, ".use %s", name) != 1) {
+
+        printf("Invalid use directive at line %i\n", LINE_NUMBER);
+        printf("Directive: %s\n", directive);
+        abortLine();
+
+    }
+
+    for(int i = 0; i < sizeof(BUILTIN_FILES) / sizeof(BuiltinFile); i++) {
+
+        if(strncmp(name, BUILTIN_FILES[i].name, MAX_STRING_LEN)) continue;
+
+        for(int j = 0; j < USED_LIBRARY_COUNT; j++) if(USED_LIBRARIES[j] == BUILTIN_FILES[i].text) return;
+
+        USED_LIBRARIES = realloc(USED_LIBRARIES, (USED_LIBRARY_COUNT + 1) * sizeof(char*));
+        USED_LIBRARIES[USED_LIBRARY_COUNT++] = BUILTIN_FILES[i].text;
+
+        snprintf(include, MAX_STRING_LEN, ".include <%s>", name);
+        includeFile(include);
+        return;
+
+    }
+
+    printf("There is no built-in library named %s at line %i of %s\n", name, LINE_NUMBER, CURRENT_FILE);
+    abortLine();
+
+}
+
+char* findIncludeFile(char* name) {
+    // Finds an included file relative to the including file, or otherwise in the -I directories in order
+    // Returns the path of the file, or NULL if it cannot be found
+
+    char* path = malloc(MAX_STRING_LEN * sizeof(char));
+    char* dirEnd = CURRENT_FILE ? strrchr(CURRENT_FILE, '/') : NULL;
+
+    if(*name == '/') strncpy(path, name, MAX_STRING_LEN - 1);
+    else snprintf(path, MAX_STRING_LEN, "%.*s%s", dirEnd ? (int) (dirEnd - CURRENT_FILE + 1) : 0, CURRENT_FILE, name);
+
+    path[MAX_STRING_LEN - 1] = '\0';
+
+    for(int i = -1; i < (int) INCLUDE_DIR_COUNT; i++) {
+
+        if(i >= 0) snprintf(path, MAX_STRING_LEN, "%s/%s", INCLUDE_DIRS[i], name);
+
+        FILE* file = fopen(path, "r");
+
+        if(file) {
+
+            fclose(file);
+            return path;
+
+        }
+
+        if(*name == '/') break;
+
+    }
+
+    free(path);
+
+    return NULL;
+
+}
+
+void defineMacro(char* directive) {
+    // Starts a macro definition from a ".macro <name> [<param>...]" directive, whose body is read up to the next .endm
+    // Parameters are used in the body as \<param>, and \@ gives a number which is unique to each expansion
+
+    trimLineBreak(directive);
+
+    int wordCount = countArgs(directive);
+
+    if(wordCount < 2) {
+
+        printf("Macro without a name at line %i\n", LINE_NUMBER);
+        printf("Directive: %s\n", directive);
+        abortLine();
+
+    }
+
+    char* name = getWord(directive, 1);
+
+    if(findMacro(name)) {
+
+        printf("Macro %s at line %i is already defined\n", name, LINE_NUMBER);
+        abortLine();
+
+    }
+
+    MACRO_TABLE = realloc(MACRO_TABLE, (MACRO_COUNT + 1) * sizeof(Macro));
+
+    Macro* macro = &MACRO_TABLE[MACRO_COUNT++];
+    macro->name = name;
+    macro->paramCount = wordCount - 2;
+    macro->params = malloc(macro->paramCount * sizeof(char*));
+    macro->lines = NULL;
+    macro->lineCount = 0;
+    macro->lineNumber = LINE_NUMBER;
+
+    for(int i = 0; i < macro->paramCount; i++) macro->params[i] = getWord(directive, i + 2);
+
+    RECORDING_MACRO = macro;
+
+}
+
+Macro* findMacro(char* name) {
+    // Gets the macro with the given name, or NULL if there is none
+
+    for(int i = 0; i < MACRO_COUNT; i++) {
+
+        if(!strncmp(MACRO_TABLE[i].name, name, MAX_STRING_LEN)) return &MACRO_TABLE[i];
+
+    }
+
+    return NULL;
+
+}
+
+void expandMacro(Macro* macro, char* invocation) {
+    // Assembles the body of a macro in place of a line which uses it, with its parameters replaced by the given arguments
+
+    int argCount = countArgs(invocation) - 1;
+
+    if(argCount != macro->paramCount) {
+
+        printf("Macro %s takes %i argument(s), but %i were given at line %i\n", macro->name, macro->paramCount, argCount, LINE_NUMBER);
+        printf("Instruction: %s\n", invocation);
+        abortLine();
+
+    }
+
+    if(MACRO_DEPTH >= MAX_MACRO_DEPTH) {
+
+        printf("Macros are nested more than %i levels deep at line %i, which probably means macro %s uses itself\n",
+            MAX_MACRO_DEPTH, LINE_NUMBER, macro->name);
+        abortLine();
+
+    }
+
+    char** args = malloc(argCount * sizeof(char*));
+    for(int i = 0; i < argCount; i++) args[i] = getWord(invocation, i + 1);
+
+    MACRO_DEPTH++;
+    MACRO_EXPANSION_COUNT++;
+
+    uint32_t expansion = MACRO_EXPANSION_COUNT;
+
+    for(int i = 0; i < macro->lineCount; i++) {
+
+        uint32_t expansionCount = MACRO_EXPANSION_COUNT;
+        MACRO_EXPANSION_COUNT = expansion;
+        // Nested expansions must not change the number this expansion substitutes for \@
+
+        char* line = substituteMacroParams(macro, macro->lines[i], args);
+
+        MACRO_EXPANSION_COUNT = expansionCount;
+
+        assembleLine(line);
+        free(line);
+
+    }
+
+    MACRO_DEPTH--;
+
+    free(args);
+
+}
+
+char* substituteMacroParams(Macro* macro, char* line, char** args) {
+    // Replaces every \<param> in a line of a macro body with its argument, and every \@ with the expansion number
+
+    char* result = malloc(MAX_STRING_LEN * sizeof(char));
+    int len = 0;
+
+    while(*line && len < MAX_STRING_LEN - 1) {
+
+        if(*line != '\\') {
+
+            result[len++] = *line++;
+            continue;
+
+        }
+
+        line++;
+
+        if(*line == '@') {
+
+            len += snprintf(result + len, MAX_STRING_LEN - len, "%u", MACRO_EXPANSION_COUNT);
+            line++;
+            continue;
+
+        }
+
+        int nameLen = 0;
+        while(isalnum(line[nameLen]) || line[nameLen] == '_') nameLen++;
+
+        int param = 0;
+        while(param < macro->paramCount && (strnlen(macro->params[param], MAX_STRING_LEN) != nameLen
+            || strncmp(macro->params[param], line, nameLen))) param++;
+
+        if(param == macro->paramCount) {
+
+            printf("Unknown parameter \\%.*s in macro %s, used at line %i\n", nameLen, line, macro->name, LINE_NUMBER);
+            abortLine();
+
+        }
+
+        len += snprintf(result + len, MAX_STRING_LEN - len, "%s", args[param]);
+        line += nameLen;
+
+    }
+
+    result[len < MAX_STRING_LEN ? len : MAX_STRING_LEN - 1] = '\0';
+
+    if(*line || len >= MAX_STRING_LEN - 1) {
+
+        printf("Line %i of macro %s is too long once its arguments are substituted, used at line %i\n",
+            macro->lineNumber, macro->name, LINE_NUMBER);
+        printf("Instruction: %s\n", result);
+        abortLine();
+
+    }
+
+    return result;
+
+}
+
+void definePseudoInstructions() {
+    // Defines the macros which implement the pseudo-instructions, before any file is read
+
+    FILE* pseudoFile = fmemopen(PSEUDO_INSTRUCTIONS, strlen(PSEUDO_INSTRUCTIONS), "r");
+
+    readLines(pseudoFile, "<pseudo-instructions>");
+
+    fclose(pseudoFile);
+
+}
+
+void checkUnclosedMacro() {
+    // Ensures that the last macro definition was closed with .endm
+
+    if(!RECORDING_MACRO) return;
+
+    printf("Macro %s defined at line %i is never closed with .endm\n", RECORDING_MACRO->name, RECORDING_MACRO->lineNumber);
+    exit(-1);
+
+}
+
+void assembleData(char* line) {
+    // Assembles a line of a .data section, which may be a label, a '.word #<value>...' directive, a string directive,
+    // or a .space or .align directive, where directives may also be preceded by a label on the same line
+    // Data labels are only given their address by placeData(), once the length of the program is known
+
+    char* directive = getFirstWord(line);
+    size_t directiveLen = strnlen(directive, MAX_STRING_LEN);
+
+    if(directiveLen > 1 && directive[directiveLen - 1] == ':' && line[directiveLen] == ' ') {
+
+        directive[directiveLen - 1] = '\0';
+        addDataLabel(directive);
+
+        line += directiveLen + 1;
+        directive = getFirstWord(line);
+
+    } else if(isLabel(line)) {
+
+        trimLabelColon(line);
+        addDataLabel(line);
+
+        return;
+
+    }
+
+    if(BSS_SECTION && strncmp(directive, ".space", MAX_INSTRUCTION_LEN) && strncmp(directive, ".align", MAX_INSTRUCTION_LEN)) {
+
+        printf("Only labels, .space, and .align directives can be placed in a .bss section, since its memory starts out as zero, at line %i\n",
+            LINE_NUMBER);
+        printWordSpan(stdout, line, 0);
+        abortLine();
+
+    }
+
+    if(!strncmp(directive, ".string", MAX_INSTRUCTION_LEN) || !strncmp(directive, ".asciiz", MAX_INSTRUCTION_LEN)) {
+
+        addDataString(line, true);
+        return;
+
+    }
+
+    if(!strncmp(directive, ".ascii", MAX_INSTRUCTION_LEN)) {
+
+        addDataString(line, false);
+        return;
+
+    }
+
+    if(!strncmp(directive, ".space", MAX_INSTRUCTION_LEN)) {
+
+        reserveData(line);
+        return;
+
+    }
+
+    if(!strncmp(directive, ".align", MAX_INSTRUCTION_LEN)) {
+
+        alignData(line);
+        return;
+
+    }
+
+    if(strncmp(directive, ".word", MAX_INSTRUCTION_LEN) || countArgs(line) < 2) {
+
+        printf("Only labels, .word, .string, .asciiz, .ascii, .space, and .align directives can be placed in a .data section, at line %i\n",
+            LINE_NUMBER);
+        printWordSpan(stdout, line, 0);
+        abortLine();
+
+    }
+
+    line = substituteConstants(line);
+
+    for(int arg = 1; arg < countArgs(line); arg++) {
+
+        checkImmediate(line, arg, INT_LIMIT);
+
+        addDataWord(getImmediateVal(getWord(line, arg)), line);
+
+    }
+
+}
+
+void addDataLabel(char* lbl) {
+    // Adds a label at the current end of the data section, or of the reserved words in a .bss section
+
+    checkLabelName(lbl);
+    checkDuplicateLabel(lbl);
+
+    if(PINNED_SECTION) {
+
+        uint16_t addr = INSTRUCTION_ADDR;
+        DataRange* range = &PINNED_DATA[PINNED_DATA_COUNT - 1];
+
+        INSTRUCTION_ADDR = range->start + range->length;
+        defineLabel(lbl);
+        INSTRUCTION_ADDR = addr;
+        // The address of data placed at a fixed address is already known, so its labels are defined right away
+        return;
+
+    }
+
+    Label l;
+    l.labelName = strndup(lbl, MAX_INSTRUCTION_LEN);
+    l.PCAddress = BSS_SECTION ? BSS_LEN : DATA_LEN;
+    l.lineNumber = LINE_NUMBER;
+    l.referenced = false;
+
+    if(BSS_SECTION) {
+
+        BSS_LABELS = realloc(BSS_LABELS, (BSS_LABEL_COUNT + 1) * sizeof(Label));
+        BSS_LABELS[BSS_LABEL_COUNT++] = l;
+        return;
+
+    }
+
+    DATA_LABELS = realloc(DATA_LABELS, (DATA_LABEL_COUNT + 1) * sizeof(Label));
+    DATA_LABELS[DATA_LABEL_COUNT++] = l;
+
+}
+
+void addDataWord(uint16_t word, char* directive) {
+    // Appends a word to the data section, or to the program in a '.data #<address>' section, where every instruction
+    // slot holds two words of data with the first one in its upper half and is attributed to the directive which
+    // started it
+
+    if(PINNED_SECTION) {
+
+        DataRange* range = &PINNED_DATA[PINNED_DATA_COUNT - 1];
+
+        if(range->length++ % 2) {
+
+            PROGRAM[PROGRAM_LEN - 1] |= word;
+            return;
+
+        }
+
+        if(PROGRAM_LEN * 2 > INT_LIMIT) {
+
+            printf("The data at line %i does not fit in memory, since the program already fills all %i words\n",
+                LINE_NUMBER, INT_LIMIT + 1);
+            exit(-1);
+
+        }
+
+        PROGRAM = realloc(PROGRAM, (PROGRAM_LEN + 1) * sizeof(uint32_t));
+        PROGRAM[PROGRAM_LEN] = word << 16;
+
+        PROGRAM_SOURCE = realloc(PROGRAM_SOURCE, (PROGRAM_LEN + 1) * sizeof(SourceLine));
+        PROGRAM_SOURCE[PROGRAM_LEN].text = strndup(directive, MAX_STRING_LEN);
+        PROGRAM_SOURCE[PROGRAM_LEN].fileName = CURRENT_FILE;
+        PROGRAM_SOURCE[PROGRAM_LEN].lineNumber = LINE_NUMBER;
+
+        PROGRAM_LEN++;
+        INSTRUCTION_ADDR += 2;
+        return;
+
+    }
+
+    DATA = realloc(DATA, (DATA_LEN + 1) * sizeof(uint16_t));
+    DATA[DATA_LEN++] = word;
+
+}
+
+void addDataString(char* directive, bool terminated) {
+    // Appends the characters of a '<directive> "<text>"' line to the data section, one character per word
+    // The escapes \n, \t, \0, \\, \', \", and \xNN may be used in the text, and .string and .asciiz add a terminating zero word
+
+    char* text = strchr(directive, '"');
+    char* c;
+
+    if(!text || text != directive + strcspn(directive, " ") + 1) {
+
+        printf("Missing string after %s at line %i\n", getFirstWord(directive), LINE_NUMBER);
+        printWordSpan(stdout, directive, 1);
+        abortLine();
+
+    }
+
+    for(c = text + 1; *c && *c != '"'; c++) {
+
+        if(*c != '\\') {
+
+            addDataWord((unsigned char) *c, directive);
+            continue;
+
+        }
+
+        c++;
+
+        int len;
+        int value = parseEscapeSequence(c, &len);
+
+        if(value >= 0) {
+
+            addDataWord(value, directive);
+            c += len - 1;
+
+        } else if(*c == 'x') {
+
+            printf("Escape sequence \\x in string at line %i must be followed by two hexadecimal digits\n", LINE_NUMBER);
+            printSourceSpan(stdout, directive, c - 1 - directive, 2);
+            abortLine();
+
+        } else {
+
+            printf("Unknown escape sequence \\%c in string at line %i, the escapes \\n, \\t, \\0, \\\\, \\', \\\", and \\xNN can be used\n",
+                *c ? *c : ' ', LINE_NUMBER);
+            printSourceSpan(stdout, directive, c - 1 - directive, *c ? 2 : 1);
+            abortLine();
+
+        }
+
+    }
+
+    if(*c != '"' || c[1]) {
+
+        printf("The string at line %i must end with a closing quote\n", LINE_NUMBER);
+        printSourceSpan(stdout, directive, text - directive, strnlen(text, MAX_STRING_LEN));
+        abortLine();
+
+    }
+
+    if(terminated) addDataWord(0, directive);
+
+}
+
+void startPinnedData(char* directive) {
+    // Starts a '.data #<address>' section, whose data is placed at the given address in the program rather than
+    // being moved to the end of it, so that data in the middle of a program (such as that of a disassembled linked
+    // program) keeps its address
+
+    setOrigin(directive);
+
+    DATA_SECTION = true;
+    BSS_SECTION = false;
+    PINNED_SECTION = true;
+
+    if(!PINNED_DATA_COUNT || PINNED_DATA[PINNED_DATA_COUNT - 1].length) {
+
+        PINNED_DATA = realloc(PINNED_DATA, (PINNED_DATA_COUNT + 1) * sizeof(DataRange));
+        PINNED_DATA_COUNT++;
+
+    }
+    // A section left empty by an earlier '.data #<address>' is reused, so that it does not become an empty range
+
+    PINNED_DATA[PINNED_DATA_COUNT - 1].start = INSTRUCTION_ADDR;
+    PINNED_DATA[PINNED_DATA_COUNT - 1].length = 0;
+
+}
+
+bool isPinnedData(uint32_t addr) {
+    // Checks if an address in the program holds the data of a '.data #<address>' section or a linked object file
+
+    for(int i = 0; i < PINNED_DATA_COUNT; i++) {
+
+        if(addr >= PINNED_DATA[i].start && addr < PINNED_DATA[i].start + PINNED_DATA[i].length) return true;
+
+    }
+
+    return false;
+
+}
+
+void placeData() {
+    // Places the data section directly after the last instruction, and defines its labels at their final addresses
+
+    if(DATA_ALIGNMENT > 1) {
+
+        char directive[MAX_INSTRUCTION_LEN];
+        snprintf(directive, MAX_INSTRUCTION_LEN, ".align #%u", DATA_ALIGNMENT);
+
+        padProgram((INSTRUCTION_ADDR + DATA_ALIGNMENT - 1) & ~(DATA_ALIGNMENT - 1), directive);
+        // Offsets within the data section are only aligned in memory if the data section starts at an aligned address
+
+    }
+
+    uint16_t programEnd = INSTRUCTION_ADDR;
+    uint32_t lineNumber = LINE_NUMBER;
+
+    if(PROGRAM_LEN * 2 + DATA_LEN > INT_LIMIT + 1) {
+
+        printf("The .data section (%u words starting at 0x%.4X) does not fit in memory\n", DATA_LEN, PROGRAM_LEN * 2);
+        exit(-1);
+
+    }
+
+    for(int i = 0; i < DATA_LABEL_COUNT; i++) {
+
+        INSTRUCTION_ADDR = programEnd + DATA_LABELS[i].PCAddress;
+        LINE_NUMBER = DATA_LABELS[i].lineNumber;
+
+        defineLabel(DATA_LABELS[i].labelName);
+
+        free(DATA_LABELS[i].labelName);
+
+    }
+
+    uint32_t bssStart = getBssStart();
+
+    if(bssStart + BSS_LEN > INT_LIMIT + 1) {
+
+        printf("The .bss section (%u words starting at 0x%.4X) does not fit in memory\n", BSS_LEN, bssStart);
+        exit(-1);
+
+    }
+
+    for(int i = 0; i < BSS_LABEL_COUNT; i++) {
+
+        INSTRUCTION_ADDR = bssStart + BSS_LABELS[i].PCAddress;
+        LINE_NUMBER = BSS_LABELS[i].lineNumber;
+
+        defineLabel(BSS_LABELS[i].labelName);
+
+        free(BSS_LABELS[i].labelName);
+
+    }
+
+    INSTRUCTION_ADDR = programEnd;
+    LINE_NUMBER = lineNumber;
+
+}
+
+uint32_t getBssStart() {
+    // Gets the address of the .bss section, which starts after the data section at the end of the program image, at
+    // the next address matching the largest .align used in it
+
+    uint32_t imageEnd = PROGRAM_LEN * 2 + (DATA_LEN + 1) / 2 * 2;
+
+    return (imageEnd + BSS_ALIGNMENT - 1) & ~(BSS_ALIGNMENT - 1);
+
+}
+
+void defineConstant(char* directive) {
+    // Defines a constant from a ".const <name> #<value>" or "<name> EQU #<value>" directive
+    // The value may also be the name of a constant which has already been defined
+
+    if(countArgs(directive) != 3) {
+
+        printf("Incorrect number of arguments for a constant at line %i\n", LINE_NUMBER);
+        printf("Directive: %s\n", directive);
+        abortLine();
+
+    }
+
+    bool equMode = strncmp(getFirstWord(directive), ".const", MAX_INSTRUCTION_LEN);
+    char* name = getWord(directive, equMode ? 0 : 1);
+    char* value = getWord(directive, 2);
+    Constant* existing = findConstant(name);
+
+    if(existing) {
+
+        printf("Constant %s at line %i is already defined at line %i\n", name, LINE_NUMBER, existing->lineNumber);
+        abortLine();
+
+    }
+
+    for(int i = 0; i < SYMBOL_COUNT + DATA_LABEL_COUNT + BSS_LABEL_COUNT; i++) {
+
+        Label l = i < SYMBOL_COUNT ? SYMBOL_TABLE[i] : i < SYMBOL_COUNT + DATA_LABEL_COUNT ?
+            DATA_LABELS[i - SYMBOL_COUNT] : BSS_LABELS[i - SYMBOL_COUNT - DATA_LABEL_COUNT];
+
+        if(strncmp(l.labelName, name, MAX_INSTRUCTION_LEN)) continue;
+
+        printf("Constant %s at line %i has the same name as the label at line %i\n", name, LINE_NUMBER, l.lineNumber);
+        abortLine();
+
+    }
+
+    if(!fitsLabelRefSyntax(name) || *name == '&') {
+
+        printf("Invalid constant name %s at line %i, which must not be a register or start with a digit, '#', or '&'\n", name, LINE_NUMBER);
+        abortLine();
+
+    }
+
+    Constant* valueConstant = findConstant(value);
+
+    if(!valueConstant && !fitsImmediateSyntax(value)) {
+
+        printf("Wrong format of the value of constant %s at line %i\n", name, LINE_NUMBER);
+        printf("Directive: %s\n", directive);
+        abortLine();
+
+    }
+
+    Constant c;
+    c.name = name;
+    c.value = valueConstant ? valueConstant->value : getImmediateVal(value);
+    c.lineNumber = LINE_NUMBER;
+
+    CONSTANT_TABLE = realloc(CONSTANT_TABLE, (CONSTANT_COUNT + 1) * sizeof(Constant));
+    CONSTANT_TABLE[CONSTANT_COUNT++] = c;
+
+}
+
+Constant* findConstant(char* name) {
+    // Gets the constant with the given name, or NULL if there is none
+
+    for(int i = 0; i < CONSTANT_COUNT; i++) {
+
+        if(!strncmp(CONSTANT_TABLE[i].name, name, MAX_STRING_LEN)) return &CONSTANT_TABLE[i];
+
+    }
+
+    return NULL;
+
+}
+
+char* substituteConstants(char* line) {
+    // Replaces every operand of a line which names a constant with the constant's immediate value
+    // The first word is never replaced, since it is the mnemonic or directive
+
+    if(!CONSTANT_COUNT) return line;
+
+    char* substituted = malloc(MAX_STRING_LEN * sizeof(char));
+    int wordCount = countArgs(line);
+
+    strncpy(substituted, getFirstWord(line), MAX_STRING_LEN - 1);
+    substituted[MAX_STRING_LEN - 1] = '\0';
+
+    for(int w = 1; w < wordCount; w++) {
+
+        char* word = getWord(line, w);
+        Constant* c = findConstant(word);
+        size_t len = strnlen(substituted, MAX_STRING_LEN);
+
+        if(c) snprintf(substituted + len, MAX_STRING_LEN - len, " #%u", c->value);
+        else snprintf(substituted + len, MAX_STRING_LEN - len, " %s", word);
+
+    }
+
+    return substituted;
+
+}
+
+bool isConditionalDirective(char* line) {
+    // Checks if a line is one of the directives which control conditional assembly
+
+    char* directive = getFirstWord(line);
+    trimLineBreak(directive);
+
+    return !strncmp(directive, ".if", MAX_INSTRUCTION_LEN) || !strncmp(directive, ".ifdef", MAX_INSTRUCTION_LEN)
+        || !strncmp(directive, ".ifndef", MAX_INSTRUCTION_LEN) || !strncmp(directive, ".else", MAX_INSTRUCTION_LEN)
+        || !strncmp(directive, ".endif", MAX_INSTRUCTION_LEN) || !strncmp(directive, ".define", MAX_INSTRUCTION_LEN);
+
+}
+
+void assembleConditional(char* line) {
+    // Handles an '.if <name or #value>', '.ifdef <name>', '.ifndef <name>', '.else', '.endif', or '.define <name> [#value]'
+    // directive, where .if takes its branch if the value is not zero and names are defined like constants
+
+    trimLineBreak(line);
+
+    char* directive = getFirstWord(line);
+    bool parentActive = isConditionActive();
+
+    if(!strncmp(directive, ".define", MAX_INSTRUCTION_LEN)) {
+
+        if(!parentActive) return;
+
+        if(countArgs(line) < 2 || countArgs(line) > 3) {
+
+            printf("Incorrect number of arguments at line %i\n", LINE_NUMBER);
+            printf("Instruction: %s\n", line);
+            abortLine();
+
+        }
+
+        char definition[MAX_STRING_LEN];
+        snprintf(definition, MAX_STRING_LEN, "%s=%s", getWord(line, 1), countArgs(line) == 3 ? getWord(line, 2) : "#1");
+
+        defineSymbol(definition);
+        return;
+
+    }
+
+    if(!strncmp(directive, ".else", MAX_INSTRUCTION_LEN) || !strncmp(directive, ".endif", MAX_INSTRUCTION_LEN)) {
+
+        if(countArgs(line) != 1) {
+
+            printf("Incorrect number of arguments at line %i\n", LINE_NUMBER);
+            printf("Instruction: %s\n", line);
+            abortLine();
+
+        }
+
+        if(!CONDITION_DEPTH) {
+
+            printf("The %s at line %i does not have a matching .if\n", directive, LINE_NUMBER);
+            abortLine();
+
+        }
+
+        Condition* c = &CONDITION_STACK[CONDITION_DEPTH - 1];
+
+        if(!strncmp(directive, ".endif", MAX_INSTRUCTION_LEN)) CONDITION_DEPTH--;
+        else if(c->elseSeen) {
+
+            printf("The .if at line %i already has an .else before line %i\n", c->lineNumber, LINE_NUMBER);
+            abortLine();
+
+        } else {
+
+            c->active = !c->taken;
+            c->taken = true;
+            c->elseSeen = true;
+
+        }
+
+        return;
+
+    }
+
+    if(countArgs(line) != 2) {
+
+        printf("Incorrect number of arguments at line %i\n", LINE_NUMBER);
+        printf("Instruction: %s\n", line);
+        abortLine();
+
+    }
+
+    bool result = parentActive && evaluateCondition(directive, getWord(line, 1));
+    // Conditions inside a skipped block are not evaluated, since they may use names which are not defined
+
+    Condition c;
+    c.active = result;
+    c.taken = result || !parentActive;
+    c.elseSeen = false;
+    c.lineNumber = LINE_NUMBER;
+
+    CONDITION_STACK = realloc(CONDITION_STACK, (CONDITION_DEPTH + 1) * sizeof(Condition));
+    CONDITION_STACK[CONDITION_DEPTH++] = c;
+
+}
+
+bool evaluateCondition(char* directive, char* operand) {
+    // Evaluates the condition of an .if, .ifdef, or .ifndef directive
+
+    Constant* constant = findConstant(operand);
+
+    if(!strncmp(directive, ".ifdef", MAX_INSTRUCTION_LEN)) return constant;
+    if(!strncmp(directive, ".ifndef", MAX_INSTRUCTION_LEN)) return !constant;
+
+    if(constant) return constant->value;
+    if(fitsImmediateSyntax(operand)) return getImmediateVal(operand);
+
+    printf("Cannot use %s in the .if at line %i, since it is not a defined name or an immediate\n", operand, LINE_NUMBER);
+    abortLine();
+
+}
+
+bool isConditionActive() {
+    // Checks if lines are currently being assembled, which is the case unless they are in a branch which is not taken
+
+    return !CONDITION_DEPTH || CONDITION_STACK[CONDITION_DEPTH - 1].active;
+
+}
+
+void defineSymbol(char* definition) {
+    // Defines a name for conditional assembly from a "<name>" or "<name>=<value>" definition, which becomes a constant
+    // with the given value, or 1 if no value is given, and the value may be written with or without a '#'
+
+    char directive[MAX_STRING_LEN];
+    char* value = strchr(definition, '=');
+
+    if(!value) snprintf(directive, MAX_STRING_LEN, ".const %s #1", definition);
+    else snprintf(directive, MAX_STRING_LEN, ".const %.*s %s%s", (int) (value - definition), definition, value[1] == '#' ? "" : "#", value + 1);
+
+    defineConstant(directive);
+
+}
+
+void checkUnclosedConditional() {
+    // Ensures that every .if block was closed with .endif
+
+    if(!CONDITION_DEPTH) return;
+
+    printf("The .if at line %i is never closed with .endif\n", CONDITION_STACK[CONDITION_DEPTH - 1].lineNumber);
+    exit(-1);
+
+}
+
+void formatSource(char* txtfile) {
+    // Rewrites an ASM file in the canonical format, with single spaces between words, uppercase mnemonics and registers,
+    // at most one blank line in a row, and trailing comments lined up in one column
+    // Comments and labels are kept, and the file is only written once all of it has been read
+
+    FILE* asmFile;
+
+    if(!(asmFile = fopen(txtfile, "r"))) {
+
+        printf("File %s does not exist.\n", txtfile);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    char line[MAX_STRING_LEN];
+    char** formatted = NULL;
+    uint32_t lineCount = 0;
+
+    while(fgets(line, MAX_STRING_LEN, asmFile)) {
+
+        char* result = formatLine(line);
+
+        if(!*result && (!lineCount || !*formatted[lineCount - 1])) continue;
+        // Blank lines are dropped at the start of the file and after another blank line
+
+        formatted = realloc(formatted, (lineCount + 1) * sizeof(char*));
+        formatted[lineCount++] = result;
+
+    }
+
+    while(lineCount && !*formatted[lineCount - 1]) lineCount--;
+
+    fclose(asmFile);
+
+    asmFile = openOutputFile(txtfile, "w");
+
+    for(int i = 0; i < lineCount; i++) fprintf(asmFile, "%s\n", formatted[i]);
+
+    closeOutputFile(asmFile, txtfile);
+    free(formatted);
+
+}
+
+char* formatLine(char* line) {
+    // Formats a single line of an ASM file, see formatSource()
+
+    char* result = malloc(MAX_STRING_LEN * sizeof(char));
+    char* words[MAX_STRING_LEN];
+    int wordCount = 0;
+    char quote = '\0';
+
+    line[strcspn(line, "\r\n")] = '\0';
+    while(*line == ' ' || *line == '\t') line++;
+
+    char* comment = findComment(line);
+    char* code = strndup(line, comment ? comment - line : MAX_STRING_LEN);
+
+    for(char* c = code; *c; c++) {
+        // Splits the code into words, while keeping the spaces inside string and character literals
+
+        if(quote && *c == '\\' && c[1]) c++;
+        else if(quote && *c == quote) quote = '\0';
+        else if(!quote && (*c == ' ' || *c == '\t')) *c = '\0';
+        else if(!quote && (*c == '"' || *c == '\'')) quote = *c;
+
+        if(*c && (c == code || !c[-1])) words[wordCount++] = c;
+
+    }
+
+    int first = wordCount > 1 && words[0][strnlen(words[0], MAX_STRING_LEN) - 1] == ':';
+    // A label may come before an instruction or directive on the same line, so the mnemonic is the second word
+
+    char* mnemonic = NULL;
+
+    if(first < wordCount) {
+
+        mnemonic = strndup(words[first], MAX_STRING_LEN);
+        for(char* c = mnemonic; *c; c++) *c = toupper(*c);
+
+        if(isMnemonic(mnemonic) || findMacro(mnemonic)) words[first] = mnemonic;
+        else mnemonic = NULL;
+        // Only the pseudo-instructions are known as macros, since the file is not assembled, so the names of
+        // user-defined macros (which are case-sensitive) are left alone
+
+    }
+
+    *result = '\0';
+
+    for(int i = 0; i < wordCount; i++) {
+
+        char* word = words[i];
+
+        if(mnemonic && i > first && strncmp(mnemonic, "JUMP", 4) && !(!strncmp(mnemonic, "SET", 4) && i == first + 2)) {
+            // Jump targets and SET immediates may be labels, which are case-sensitive
+
+            char* upper = strndup(word, MAX_STRING_LEN);
+            for(char* c = upper; *c; c++) *c = toupper(*c);
+
+            if(fitsRegisterSyntax(upper)) word = upper;
+
+        }
+
+        snprintf(result + strlen(result), MAX_STRING_LEN - strlen(result), "%s%s", i ? " " : "", word);
+
+    }
+
+    if(comment) {
+
+        while(comment[strnlen(comment, MAX_STRING_LEN) - 1] == ' ' || comment[strnlen(comment, MAX_STRING_LEN) - 1] == '\t') {
+
+            comment[strnlen(comment, MAX_STRING_LEN) - 1] = '\0';
+
+        }
+
+        int padding = *result ? FORMAT_COMMENT_COLUMN - (int) strlen(result) : 0;
+
+        snprintf(result + strlen(result), MAX_STRING_LEN - strlen(result), "%*s%s", *result && padding < 1 ? 1 : padding, "", comment);
+
+    }
+
+    free(code);
+
+    return result;
+
+}
+
+bool isMnemonic(char* str) {
+    // Checks if a given string is the mnemonic of a built-in or custom instruction
+
+    for(int i = 0; i < sizeof(MNEMONICS) / sizeof(char*); i++) if(!strncmp(MNEMONICS[i], str, MAX_STRING_LEN)) return true;
+    for(int i = 0; i < CUSTOM_OPCODE_COUNT; i++) if(!strncmp(CUSTOM_OPCODES[i].mnemonic, str, MAX_STRING_LEN)) return true;
+
+    return false;
+
+}
+
+void setOrigin(char* directive) {
+    // Moves the following instructions to the address given by an '.org #<address>' directive, which may also be
+    // written without the '#', by filling the space in between with zero words
+
+    uint16_t addr = getDirectiveVal(directive);
+
+    if(addr % 2) {
+
+        printf("The address given to %s at line %i must be even, since every instruction takes up two words\n",
+            getFirstWord(directive), LINE_NUMBER);
+        printWordSpan(stdout, directive, 1);
+        abortLine();
+
+    }
+
+    if(addr < PROGRAM_LEN * 2) {
+
+        printf("Cannot move back to address 0x%.4X at line %i, since instructions have already been placed up to address 0x%.4X\n",
+            addr, LINE_NUMBER, PROGRAM_LEN * 2);
+        printWordSpan(stdout, directive, 1);
+        abortLine();
+
+    }
+
+    padProgram(addr, directive);
+
+    AFTER_UNCONDITIONAL = false;
+    // Code placed at a fixed address is usually reached through that address rather than a label
+
+}
+
+void alignProgram(char* directive) {
+    // Moves the following instructions to the next address which is a multiple of the '.align #<n>' directive,
+    // by filling the space in between with zero words
+
+    uint16_t alignment = getDirectiveVal(directive);
+
+    if(!alignment || alignment & (alignment - 1)) {
+
+        printf("The alignment at line %i must be a power of two\n", LINE_NUMBER);
+        printWordSpan(stdout, directive, 1);
+        abortLine();
+
+    }
+
+    if(((INSTRUCTION_ADDR + alignment - 1) & ~(alignment - 1)) > INT_LIMIT) {
+
+        printf("Cannot align the instruction at line %i, since the aligned address does not fit in memory\n", LINE_NUMBER);
+        printWordSpan(stdout, directive, 1);
+        abortLine();
+
+    }
+
+    padProgram((INSTRUCTION_ADDR + alignment - 1) & ~(alignment - 1), directive);
+
+}
+
+void alignData(char* directive) {
+    // Moves the following data to the next address which is a multiple of the '.align #<n>' directive, by filling
+    // the space in between with zero words
+
+    uint16_t alignment = getDirectiveVal(directive);
+
+    if(!alignment || alignment & (alignment - 1)) {
+
+        printf("The alignment at line %i must be a power of two\n", LINE_NUMBER);
+        printWordSpan(stdout, directive, 1);
+        abortLine();
+
+    }
+
+    if(BSS_SECTION) {
+
+        BSS_LEN = (BSS_LEN + alignment - 1) & ~(alignment - 1);
+        if(alignment > BSS_ALIGNMENT) BSS_ALIGNMENT = alignment;
+        return;
+
+    }
+
+    if(PINNED_SECTION) {
+
+        DataRange* range = &PINNED_DATA[PINNED_DATA_COUNT - 1];
+        while((range->start + range->length) % alignment) addDataWord(0, directive);
+        return;
+
+    }
+
+    while(DATA_LEN % alignment) addDataWord(0, directive);
+
+    if(alignment > DATA_ALIGNMENT) DATA_ALIGNMENT = alignment;
+
+}
+
+void reserveData(char* directive) {
+    // Reserves the number of words given by a '.space #<n>' directive in the data or .bss section, which start out
+    // as zero
+
+    uint16_t count = getDirectiveVal(directive);
+
+    if(BSS_SECTION) {
+
+        BSS_LEN += count;
+        return;
+
+    }
+
+    for(int i = 0; i < count; i++) addDataWord(0, directive);
+
+}
+
+void padProgram(uint16_t addr, char* directive) {
+    // Fills the program with zero words up to the given address, which are attributed to the directive which caused them
+
+    while(INSTRUCTION_ADDR < addr) {
+
+        PROGRAM = realloc(PROGRAM, (PROGRAM_LEN + 1) * sizeof(uint32_t));
+        PROGRAM[PROGRAM_LEN] = 0;
+
+        PROGRAM_SOURCE = realloc(PROGRAM_SOURCE, (PROGRAM_LEN + 1) * sizeof(SourceLine));
+        PROGRAM_SOURCE[PROGRAM_LEN].text = strndup(directive, MAX_STRING_LEN);
+        PROGRAM_SOURCE[PROGRAM_LEN].fileName = CURRENT_FILE;
+        PROGRAM_SOURCE[PROGRAM_LEN].lineNumber = LINE_NUMBER;
+
+        PROGRAM_LEN++;
+        INSTRUCTION_ADDR += 2;
+
+    }
+
+}
+
+uint16_t getDirectiveVal(char* directive) {
+    // Gets the value of a directive which takes a single immediate, such as '.org #<address>', where the '#' may be
+    // left out and constants may be used
+
+    directive = substituteConstants(directive);
+
+    char* valStr = getWord(directive, 1);
+
+    if(*valStr != '#') {
+
+        char* prefixed = malloc(MAX_INSTRUCTION_LEN * sizeof(char));
+        snprintf(prefixed, MAX_INSTRUCTION_LEN, "#%s", valStr);
+        valStr = prefixed;
+
+    }
+
+    if(countArgs(directive) != 2) {
+
+        printf("Incorrect number of arguments at line %i\n", LINE_NUMBER);
+        printf("Instruction: %s\n", directive);
+        abortLine();
+
+    }
+
+    checkImmediate(directive, 1, INT_LIMIT);
+
+    return getImmediateVal(valStr);
+
+}
+
+void optimizeProgram() {
+    // Removes instructions which have no effect, such as a SET whose value is overwritten by the next instruction or
+    // a jump to the instruction right after it, and replaces immediate instructions which leave their operand as it
+    // is (such as ADD-IMM with #0) with a COPY, or removes them if they copy a register onto itself
+    // Labels and every address held by an instruction are moved down along with the instructions after a removed one
+
+    if(!canOptimize()) return;
+
+    for(uint32_t i = 0; i < PROGRAM_LEN;) {
+
+        uint32_t instruction = PROGRAM[i];
+        uint8_t opcode = instruction >> 24;
+        uint8_t rDest = (instruction >> 20) & 0xF;
+        uint8_t rOp = (instruction >> 16) & 0xF;
+
+        if(opcode == OP_SET && i + 1 < PROGRAM_LEN && getDestRegister(PROGRAM[i + 1]) == rDest
+            && !readsRegister(PROGRAM[i + 1], rDest)) {
+
+            removeInstruction(i);
+
+        } else if(opcode >= OP_JUMP && opcode <= OP_JUMP_IF_NOTZERO && (instruction & 0xFFFF) == (i + 1) * 2) {
+
+            removeInstruction(i);
+
+        } else if(isIdentityImmediate(instruction) && areFlagsUnused(i)) {
+
+            if(rDest == rOp) removeInstruction(i);
+            else PROGRAM[i] = (OP_COPY << 24) | (rDest << 20) | (rOp << 16);
+
+        } else {
+
+            i++;
+            continue;
+
+        }
+
+        if(i) i--;
+        // Each change can make the instruction before it removable, such as a jump which now goes to the next one
+
+    }
+
+}
+
+bool canOptimize() {
+    // Checks if the addresses in the program can be moved, which is not the case if it jumps to an explicit address or
+    // has padding from .org or .align, since these rely on the code being at a fixed address
+
+    if(BSS_ALIGNMENT > 1) {
+
+        printWarning("the program was not optimized, since the .bss section is aligned by .align");
+        return false;
+
+    }
+
+    if(PINNED_DATA_COUNT) {
+
+        printWarning("the program was not optimized, since it has a .data section at the fixed address 0x%.4X",
+            PINNED_DATA[0].start);
+        return false;
+
+    }
+
+    for(uint32_t i = 0; i < PROGRAM_LEN; i++) {
+
+        uint8_t opcode = PROGRAM[i] >> 24;
+        bool relocated = false;
+
+        for(int j = 0; j < RELOCATION_COUNT; j++) if(RELOCATIONS[j] == i) relocated = true;
+
+        if(!PROGRAM[i]) {
+
+            printWarning("the program was not optimized, since it is padded by .org or .align at line %i",
+                PROGRAM_SOURCE[i].lineNumber);
+            return false;
+
+        }
+
+        if(opcode >= OP_JUMP && opcode <= OP_JUMP_LINK && !relocated) {
+
+            printWarning("the program was not optimized, since the jump at line %i goes to an explicit address",
+                PROGRAM_SOURCE[i].lineNumber);
+            return false;
+
+        }
+
+    }
+
+    return true;
+
+}
+
+void removeInstruction(uint32_t index) {
+    // Removes an instruction from the program, and moves every label and address after it down by one instruction
+
+    uint16_t removedAddr = index * 2;
+
+    memmove(PROGRAM + index, PROGRAM + index + 1, (PROGRAM_LEN - index - 1) * sizeof(uint32_t));
+    memmove(PROGRAM_SOURCE + index, PROGRAM_SOURCE + index + 1, (PROGRAM_LEN - index - 1) * sizeof(SourceLine));
+    PROGRAM_LEN--;
+    INSTRUCTION_ADDR -= 2;
+
+    uint32_t kept = 0;
+
+    for(int i = 0; i < RELOCATION_COUNT; i++) {
+
+        if(RELOCATIONS[i] == index) continue;
+        if(RELOCATIONS[i] > index) RELOCATIONS[i]--;
+
+        uint32_t* instruction = &PROGRAM[RELOCATIONS[i]];
+        if((*instruction & 0xFFFF) > removedAddr) *instruction -= 2;
+
+        RELOCATIONS[kept++] = RELOCATIONS[i];
+
+    }
+
+    RELOCATION_COUNT = kept;
+
+    for(int i = 0; i < SYMBOL_COUNT; i++) if(SYMBOL_TABLE[i].PCAddress > removedAddr) SYMBOL_TABLE[i].PCAddress -= 2;
+    // A label of the removed instruction now belongs to the instruction which took its place
+
+    if(ENTRY_POINT > removedAddr) ENTRY_POINT -= 2;
+
+}
+
+int getDestRegister(uint32_t instruction) {
+    // Gets the register written by an instruction, or -1 if it does not write a register (or may be a custom
+    // instruction, whose operands are unknown)
+
+    uint8_t opcode = instruction >> 24;
+
+    if(opcode < OP_SET || opcode > OP_LOAD || opcode == OP_COMPARE || opcode == OP_COMPARE_IMM) return -1;
+
+    return (instruction >> 20) & 0xF;
+
+}
+
+bool readsRegister(uint32_t instruction, uint8_t reg) {
+    // Checks if an instruction may read the given register, which is assumed for any instruction which is not known
+    // to leave it alone
+
+    uint8_t opcode = instruction >> 24;
+    bool rType = (opcode >= OP_ADD && opcode <= OP_MODULO) || (opcode >= OP_SHIFT_LEFT && opcode <= OP_NOR);
+
+    if(opcode == OP_SET) return false;
+    if(rType && ((instruction >> 12) & 0xF) == reg) return true;
+    if(rType || opcode == OP_COPY || opcode == OP_NOT || (opcode >= OP_ADD_IMM && opcode <= OP_LOAD)) {
+
+        return ((instruction >> 16) & 0xF) == reg;
+
+    }
+
+    return true;
+
+}
+
+bool isIdentityImmediate(uint32_t instruction) {
+    // Checks if an immediate instruction always gives back its operand, such as ADD-IMM with #0 or MULTIPLY-IMM with #1
+
+    uint8_t opcode = instruction >> 24;
+    uint16_t immediate = instruction & 0xFFFF;
+
+    switch(opcode) {
+
+        case OP_ADD_IMM: case OP_SUBTRACT_IMM: case OP_SHIFT_LEFT_IMM: case OP_SHIFT_RIGHT_IMM:
+        case OP_OR_IMM: case OP_XOR_IMM:
+            return immediate == 0;
+        case OP_MULTIPLY_IMM: case OP_DIVIDE_IMM:
+            return immediate == 1;
+        default:
+            return false;
+
+    }
+
+}
+
+bool areFlagsUnused(uint32_t index) {
+    // Checks if the flags set by an instruction are overwritten before anything can use them, by following the
+    // instructions after it until one sets the flags or stops the program
+    // Any jump is assumed to use them, since the code it goes to may test the flags
+
+    for(uint32_t i = index + 1; i < PROGRAM_LEN; i++) {
+
+        uint8_t opcode = PROGRAM[i] >> 24;
+
+        if(opcode == OP_HALT || (opcode >= OP_ADD && opcode <= OP_NOR_IMM)) return true;
+        if(opcode != OP_SET && opcode != OP_COPY && opcode != OP_LOAD && opcode != OP_STORE) return false;
+
+    }
+
+    return true;
+
+}
+
+void writeDepFile(char* depfile, char* txtfile, char* binfile) {
+    // Writes a make rule which lists every file the output depends on, along with an empty rule for each included
+    // file, so that make does not fail once an included file is deleted
+
+    FILE* depFile = openOutputFile(depfile, "w");
+
+    fprintf(depFile, "%s: %s", binfile, txtfile);
+    for(int i = 0; i < INCLUDED_FILE_COUNT; i++) fprintf(depFile, " \\\n  %s", INCLUDED_FILES[i]);
+    fprintf(depFile, "\n");
+
+    for(int i = 0; i < INCLUDED_FILE_COUNT; i++) fprintf(depFile, "\n%s:\n", INCLUDED_FILES[i]);
+
+    closeOutputFile(depFile, depfile);
+
+}
+
+uint32_t* buildImage(uint32_t* imageLen) {
+    // Lays out the program image, which is made up of the instructions followed by the words of any .data sections
+
+    *imageLen = PROGRAM_LEN + (DATA_LEN + 1) / 2;
+    uint32_t* image = malloc(*imageLen * sizeof(uint32_t) + 1);
+
+    for(int i = 0; i < PROGRAM_LEN; i++) image[i] = PROGRAM[i];
+
+    for(int i = 0; i < DATA_LEN; i += 2) {
+
+        image[PROGRAM_LEN + i / 2] = (DATA[i] << 16) | (i + 1 < DATA_LEN ? DATA[i + 1] : 0);
+        // Two data words fill the space of one instruction, and an odd word out is padded with zero
+
+    }
+
+    return image;
+
+}
+
+void writeProgram(char* writefile) {
+    // Writes the fully-patched program image to the given file
+
+    FILE* binFile = openOutputFile(writefile, OUTPUT_FORMAT == OUTPUT_BIN ? "wb" : "w");
+
+    uint32_t imageLen;
+    uint32_t* buffer = buildImage(&imageLen);
+
+    if(OUTPUT_FORMAT != OUTPUT_BIN) {
+
+        for(int i = 0; i < imageLen; i++) {
+
+            printf("%.8X\n", buffer[i]);
+
+            if(OUTPUT_FORMAT == OUTPUT_MEMH) fprintf(binFile, "%.4X\n%.4X\n", buffer[i] >> 16, buffer[i] & 0xFFFF);
+            else fprintf(binFile, "%.8X\n", buffer[i]);
+            // Memory is made up of 16-bit words, so each instruction is split into its high and low halves
+
+        }
+        // $readmemh has no use for the header, so hex output never includes one
+
+        free(buffer);
+        closeOutputFile(binFile, writefile);
+        return;
+
+    }
+
+    for(int i = 0; i < imageLen; i++) {
+
+        printf("%.8X\n", buffer[i]);
+
+        buffer[i] = htonl(buffer[i]);
+
+    }
+
+    if(!RAW_OUTPUT) {
+
+        uint32_t sectionCount = 1 + (DATA_LEN ? 1 : 0) + PINNED_DATA_COUNT + (BSS_LEN ? 1 : 0);
+        uint32_t* header = malloc((HEADER_WORDS + sectionCount * SECTION_ENTRY_WORDS) * sizeof(uint32_t));
+
+        header[0] = htonl(HEADER_MAGIC);
+        header[1] = htonl(HEADER_VERSION);
+        header[2] = htonl(imageLen);
+        header[3] = htonl(crc32((uint8_t*) buffer, imageLen * sizeof(uint32_t)));
+        header[4] = htonl(ENTRY_POINT);
+        header[5] = htonl(sectionCount);
+        // The checksum lets the emulator and disassembler detect corrupted or truncated files
+
+        uint32_t* section = header + HEADER_WORDS;
+
+        section[0] = htonl(SECTION_TEXT);
+        section[1] = htonl(0);
+        section[2] = htonl(PROGRAM_LEN * 2);
+        section += SECTION_ENTRY_WORDS;
+        // The text section of a linked program covers its whole image, and the data of each object file or
+        // '.data #<address>' section inside it is marked by a .data section of its own, in order of their addresses
+
+        for(int i = 0; i < PINNED_DATA_COUNT; i++) {
+
+            section[0] = htonl(SECTION_DATA);
+            section[1] = htonl(PINNED_DATA[i].start);
+            section[2] = htonl(PINNED_DATA[i].length);
+            section += SECTION_ENTRY_WORDS;
+
+        }
+
+        if(DATA_LEN) {
+
+            section[0] = htonl(SECTION_DATA);
+            section[1] = htonl(PROGRAM_LEN * 2);
+            section[2] = htonl(DATA_LEN);
+            section += SECTION_ENTRY_WORDS;
+
+        }
+
+        if(BSS_LEN) {
+
+            section[0] = htonl(SECTION_BSS);
+            section[1] = htonl(getBssStart());
+            section[2] = htonl(BSS_LEN);
+
+        }
+
+        fwrite(header, sizeof(uint32_t), HEADER_WORDS + sectionCount * SECTION_ENTRY_WORDS, binFile);
+        free(header);
+
+    }
+
+    fwrite(buffer, sizeof(uint32_t), imageLen, binFile);
+
+    free(buffer);
+    closeOutputFile(binFile, writefile);
+
+}
+
+void setEntryPoint(char* directive) {
+    // Makes execution start at the label named by an '.entry <label>' directive, which is resolved once the whole
+    // file has been read, so that it may come before the label
+
+    if(countArgs(directive) != 2) {
+
+        printf("Incorrect number of arguments at line %i\n", LINE_NUMBER);
+        printf("Instruction: %s\n", directive);
+        abortLine();
+
+    }
+
+    if(ENTRY_LABEL) {
+
+        printf("Entry point at line %i is already set at line %i\n", LINE_NUMBER, ENTRY_LINE);
+        abortLine();
+
+    }
+
+    ENTRY_LABEL = getWord(directive, 1);
+    ENTRY_LINE = LINE_NUMBER;
+
+}
+
+void resolveEntryPoint() {
+    // Looks up the address of the entry label, if there is one
+
+    if(!ENTRY_LABEL) return;
+
+    for(int i = 0; i < SYMBOL_COUNT; i++) {
+
+        if(strncmp(SYMBOL_TABLE[i].labelName, ENTRY_LABEL, MAX_INSTRUCTION_LEN)) continue;
+
+        ENTRY_POINT = SYMBOL_TABLE[i].PCAddress;
+        SYMBOL_TABLE[i].referenced = true;
+
+        if(ENTRY_POINT && (RAW_OUTPUT || OUTPUT_FORMAT != OUTPUT_BIN)) {
+
+            printWarning("the entry point at line %i is lost, since the output file has no header to hold it", ENTRY_LINE);
+
+        }
+
+        return;
+
+    }
+
+    printf("Cannot start at label %s at line %i because it does not exist in the symbol table\n", ENTRY_LABEL, ENTRY_LINE);
+    exit(-1);
+
+}
+
+bool hasOutputExtension(char* file) {
+    // Checks if the given output file has the extension matching the output format, which is the same extension
+    // the disassembler uses to recognize the format when converting it
+
+    if(OUTPUT_FORMAT == OUTPUT_MEMH) return endsWith(file, ".memh");
+    if(OUTPUT_FORMAT == OUTPUT_MEMH32) return endsWith(file, ".txt");
+
+    return endsWith(file, ".bin");
+
+}
+
+void writeIR(char* irfile, char* sourcefile) {
+    // Writes the assembled program as JSON, with the labels and the decoded form of each instruction, so that
+    // other tools can work with the program without having to parse SMIS assembly themselves
+
+    FILE* ir = openOutputFile(irfile, "w");
+
+    fprintf(ir, "{\n  \"source\": ");
+    printJsonString(ir, sourcefile);
+    fprintf(ir, ",\n  \"labels\": [");
+
+    for(int i = 0; i < SYMBOL_COUNT; i++) {
+
+        fprintf(ir, "%s\n    { \"name\": ", i ? "," : "");
+        printJsonString(ir, SYMBOL_TABLE[i].labelName);
+        fprintf(ir, ", \"address\": %u, \"line\": %u }", SYMBOL_TABLE[i].PCAddress, SYMBOL_TABLE[i].lineNumber);
+
+    }
+
+    fprintf(ir, "%s],\n  \"instructions\": [", SYMBOL_COUNT ? "\n  " : "");
+
+    for(int i = 0; i < PROGRAM_LEN; i++) {
+
+        char* text = PROGRAM_SOURCE[i].text;
+        int argCount = countArgs(text);
+
+        fprintf(ir, "%s\n    {\n      \"address\": %u,\n      \"line\": %u,\n      \"source\": ", i ? "," : "", i * 2, PROGRAM_SOURCE[i].lineNumber);
+        printJsonString(ir, text);
+        fprintf(ir, ",\n      \"mnemonic\": ");
+        printJsonString(ir, getFirstWord(text));
+        fprintf(ir, ",\n      \"opcode\": %u,\n      \"encoding\": \"0x%.8X\",\n      \"operands\": [", PROGRAM[i] >> 24, PROGRAM[i]);
+
+        for(int arg = 1; arg < argCount; arg++) {
+
+            char* word = getWord(text, arg);
+
+            fprintf(ir, "%s\n        { ", arg > 1 ? "," : "");
+
+            if(fitsRegisterSyntax(word)) fprintf(ir, "\"type\": \"register\", \"register\": %u", getRegisterNum(word));
+            else if(fitsImmediateSyntax(word)) fprintf(ir, "\"type\": \"immediate\", \"value\": %u", getImmediateVal(word));
+            else {
+
+                fprintf(ir, "\"type\": \"label\", \"name\": ");
+                printJsonString(ir, word);
+                fprintf(ir, ", \"address\": %u", PROGRAM[i] & 0xFFFF);
+                // Labels are used as jump targets and SET immediates, whose resolved address is held in the lower 16 bits
+
+            }
+
+            fprintf(ir, " }");
+
+        }
+
+        fprintf(ir, "%s]\n    }", argCount > 1 ? "\n      " : "");
+
+    }
+
+    fprintf(ir, "%s]\n}\n", PROGRAM_LEN ? "\n  " : "");
+
+    closeOutputFile(ir, irfile);
+
+}
+
+void writeSymbolFile(char* symfile) {
+    // Writes the symbol table in the .sym format, which starts with a "SMIS-SYMBOLS <version>" header line
+    // followed by one "<address> <kind> <line> <name>" line per label, where the kind is "func" for labels
+    // used by name in a JUMP-LINK and "label" otherwise, and lines starting with '#' are comments
+    // Another label at the same address is not a function just because of that, so that the disassembler can tell
+    // the name used by the calls apart from the name used by the other jumps
+
+    FILE* symFile = openOutputFile(symfile, "w");
+
+    fprintf(symFile, "SMIS-SYMBOLS %i\n# address kind line name\n", SYMBOL_FILE_VERSION);
+
+    for(int i = 0; i < SYMBOL_COUNT; i++) {
+
+        Label l = SYMBOL_TABLE[i];
+
+        fprintf(symFile, "0x%.4X %s %u %s\n", l.PCAddress, isCalledLabel(l.labelName) ? "func" : "label", l.lineNumber, l.labelName);
+
+    }
+
+    closeOutputFile(symFile, symfile);
+
+}
+
+void writeLabelMap(char* labelfile) {
+    // Writes the symbol table as JSON, with the name, address, and line of each label, for tools which show labels
+    // alongside the program but do not need the rest of the IR
+
+    FILE* labelFile = openOutputFile(labelfile, "w");
+
+    fprintf(labelFile, "{\n  \"labels\": [");
+
+    for(int i = 0; i < SYMBOL_COUNT; i++) {
+
+        fprintf(labelFile, "%s\n    { \"name\": ", i ? "," : "");
+        printJsonString(labelFile, SYMBOL_TABLE[i].labelName);
+        fprintf(labelFile, ", \"address\": %u, \"line\": %u }", SYMBOL_TABLE[i].PCAddress, SYMBOL_TABLE[i].lineNumber);
+
+    }
+
+    fprintf(labelFile, "%s]\n}\n", SYMBOL_COUNT ? "\n  " : "");
+
+    closeOutputFile(labelFile, labelfile);
+
+}
+
+void writeDebugInfo(char* debugfile) {
+    // Writes the source line of every instruction in the .dbg format, which starts with a "SMIS-DEBUG <version>"
+    // header line, followed by one "file <index> <path>" line per source file and then one
+    // "<address> <file index> <line> <source>" line per instruction, where lines starting with '#' are comments
+
+    FILE* debugFile = openOutputFile(debugfile, "w");
+
+    fprintf(debugFile, "SMIS-DEBUG %i\n# file index path\n", DEBUG_FILE_VERSION);
+
+    char** files = malloc((PROGRAM_LEN + 1) * sizeof(char*));
+    uint32_t* fileIndices = malloc((PROGRAM_LEN + 1) * sizeof(uint32_t));
+    uint32_t fileCount = 0;
+
+    for(int i = 0; i < PROGRAM_LEN; i++) {
+
+        uint32_t f = 0;
+        while(f < fileCount && strncmp(files[f], PROGRAM_SOURCE[i].fileName, MAX_STRING_LEN)) f++;
+
+        if(f == fileCount) {
+
+            files[fileCount++] = PROGRAM_SOURCE[i].fileName;
+            fprintf(debugFile, "file %u %s\n", f, PROGRAM_SOURCE[i].fileName);
+
+        }
+
+        fileIndices[i] = f;
+
+    }
+
+    fprintf(debugFile, "# address file line source\n");
+
+    for(int i = 0; i < PROGRAM_LEN; i++) {
+
+        fprintf(debugFile, "0x%.4X %u %u %s\n", i * 2, fileIndices[i], PROGRAM_SOURCE[i].lineNumber, PROGRAM_SOURCE[i].text);
+
+    }
+
+    free(files);
+    free(fileIndices);
+    closeOutputFile(debugFile, debugfile);
+
+}
+
+void printStats() {
+    // Prints the number of instructions of each format, the size of the program, the number of labels, and how
+    // large the immediates used by the program are
+
+    uint32_t rType = 0, iType = 0, jType = 0, sType = 0, cType = 0, padding = 0, pinned = 0;
+    uint32_t immediates[STATS_IMMEDIATE_BUCKETS] = {0};
+    char* bucketNames[STATS_IMMEDIATE_BUCKETS] = {"0", "1-15", "16-255", "256-4095", "4096-65535"};
+
+    for(int i = 0; i < PROGRAM_LEN; i++) {
+
+        uint8_t opcode = PROGRAM[i] >> 24;
+        uint16_t immediate = PROGRAM[i] & 0xFFFF;
+        bool hasImmediate = false;
+
+        if(isPinnedData(i * 2)) pinned++;
+        // The data of a '.data #<address>' section is counted in the data size rather than as instructions
+        else if(!PROGRAM[i]) padding++;
+        // Zero words are only written as padding by .org and .align, since there is no opcode 0
+        else if((opcode >= OP_ADD && opcode <= OP_MODULO) || (opcode >= OP_SHIFT_LEFT && opcode <= OP_NOR)) rType++;
+        else if((opcode >= OP_ADD_IMM && opcode <= OP_NOR_IMM && opcode != OP_COMPARE_IMM) || opcode == OP_LOAD || opcode == OP_STORE) {
+
+            iType++;
+            hasImmediate = true;
+
+        } else if(opcode >= OP_JUMP && opcode <= OP_JUMP_LINK) jType++;
+        else if(opcode >= OP_CUSTOM_MIN) cType++;
+        else {
+
+            sType++;
+            hasImmediate = opcode == OP_SET || opcode == OP_COMPARE_IMM;
+
+        }
+
+        if(!hasImmediate) continue;
+
+        if(!immediate) immediates[0]++;
+        else if(immediate < 0x10) immediates[1]++;
+        else if(immediate < 0x100) immediates[2]++;
+        else if(immediate < 0x1000) immediates[3]++;
+        else immediates[4]++;
+
+    }
+
+    uint32_t dataLen = DATA_LEN;
+    for(int i = 0; i < PINNED_DATA_COUNT; i++) dataLen += PINNED_DATA[i].length;
+
+    printf("Instructions:       %u\n", PROGRAM_LEN - padding - pinned);
+    printf("  R-type:           %u\n", rType);
+    printf("  I-type:           %u\n", iType);
+    printf("  J-type:           %u\n", jType);
+    printf("  Special:          %u\n", sType);
+    if(CUSTOM_OPCODE_COUNT) printf("  Custom:           %u\n", cType);
+    printf("Code size:          %u words", (PROGRAM_LEN - pinned) * 2);
+    if(padding) printf(" (%u of padding)", padding * 2);
+    printf("\nData size:          %u words\n", dataLen);
+    if(BSS_LEN) printf("Bss size:           %u words (not stored in the file)\n", BSS_LEN);
+    printf("Labels:             %u\n", SYMBOL_COUNT);
+    printf("Immediates by value:\n");
+
+    for(int i = 0; i < STATS_IMMEDIATE_BUCKETS; i++) printf("  %-16s  %u\n", bucketNames[i], immediates[i]);
+
+}
+
+void printLayout() {
+    // Prints where each section of the program is placed in memory, followed by the address of every label, and
+    // checks that the program ends before the stack the emulator uses by default
+
+    uint32_t dataStart = PROGRAM_LEN * 2;
+    uint32_t bssStart = getBssStart();
+    uint32_t programEnd = BSS_LEN ? bssStart + BSS_LEN : dataStart + (DATA_LEN + 1) / 2 * 2;
+    uint32_t stackBottom = LAYOUT_STACK_TOP - LAYOUT_STACK_SIZE;
+
+    printf("Section  Start   End     Size\n");
+    printLayoutSection(".text", 0, dataStart);
+    for(int i = 0; i < PINNED_DATA_COUNT; i++) printLayoutSection(".data", PINNED_DATA[i].start, PINNED_DATA[i].length);
+    printLayoutSection(".data", dataStart, DATA_LEN);
+    printLayoutSection(".bss", bssStart, BSS_LEN);
+    printLayoutSection("stack", stackBottom, LAYOUT_STACK_SIZE);
+    printf("Entry point: 0x%.4X\n", ENTRY_POINT);
+
+    if(programEnd > stackBottom) {
+
+        printWarning("the program (ending at 0x%.4X) overlaps the emulator's default stack region 0x%.4X-0x%.4X",
+            programEnd, stackBottom, LAYOUT_STACK_TOP - 1);
+
+    } else printf("Free:    %u words between the end of the program and the stack\n", stackBottom - programEnd);
+
+    if(!SYMBOL_COUNT) return;
+
+    int width = strlen("Label");
+    for(int i = 0; i < SYMBOL_COUNT; i++) if(strlen(SYMBOL_TABLE[i].labelName) > width) width = strlen(SYMBOL_TABLE[i].labelName);
+
+    printf("\n%-*s  Address  Line\n", width, "Label");
+
+    for(int i = 0; i < SYMBOL_COUNT; i++) {
+
+        Label l = SYMBOL_TABLE[i];
+        printf("%-*s  0x%.4X   %u\n", width, l.labelName, l.PCAddress, l.lineNumber);
+
+    }
+
+}
+
+void printLayoutSection(char* name, uint32_t start, uint32_t len) {
+    // Prints the addresses a section of the program spans for --layout, unless the section is empty
+
+    if(!len) return;
+
+    printf("%-8s 0x%.4X  0x%.4X  %u words\n", name, start, start + len - 1, len);
+
+}
+
+void placeLabel(char* lbl) {
+    // Defines a label found in the ASM file at the current instruction address, after checking its name
+
+    checkLabelName(lbl);
+    checkDuplicateLabel(lbl);
+    defineLabel(lbl);
+
+    AFTER_UNCONDITIONAL = false;
+
+}
+
+void defineLabel(char* lbl) {
+    // Adds a label at the current instruction address to the symbol table, and patches any instructions waiting on it
+
+    Label l;
+    l.labelName = strndup(lbl, MAX_INSTRUCTION_LEN);
+    l.PCAddress = INSTRUCTION_ADDR;
+    l.lineNumber = LINE_NUMBER;
+    l.referenced = false;
+
+    SYMBOL_TABLE = realloc(SYMBOL_TABLE, (SYMBOL_COUNT + 1) * sizeof(Label));
+
+    SYMBOL_TABLE[SYMBOL_COUNT] = l;
+
+    SYMBOL_COUNT++;
+
+    for(int i = 0; i < FIXUP_COUNT; i++) {
+
+        Fixup f = FIXUP_TABLE[i];
+
+        if(strncmp(f.labelName, l.labelName, MAX_INSTRUCTION_LEN)) continue;
+
+        PROGRAM[f.instructionIndex] += l.PCAddress;
+        SYMBOL_TABLE[SYMBOL_COUNT - 1].referenced = true;
+
+        free(f.labelName);
+        FIXUP_TABLE[i] = FIXUP_TABLE[FIXUP_COUNT - 1];
+        FIXUP_COUNT--;
+        i--;
+        // The last fixup is moved into the resolved slot, so the slot must be checked again
+
+    }
+
+}
+
+void checkDuplicateLabel(char* lbl) {
+    // Terminates the program if a label with the given name has already been defined in the program or a .data section,
+    // or if a constant has the same name, since the constant would be substituted wherever the label is referenced
+
+    Constant* constant = findConstant(lbl);
+
+    if(constant) {
+
+        printf("Label %s at line %i has the same name as the constant at line %i\n", lbl, LINE_NUMBER, constant->lineNumber);
+        abortLine();
+
+    }
+
+    for(int i = 0; i < SYMBOL_COUNT + DATA_LABEL_COUNT; i++) {
+
+        Label l = i < SYMBOL_COUNT ? SYMBOL_TABLE[i] : DATA_LABELS[i - SYMBOL_COUNT];
+
+        if(strncmp(l.labelName, lbl, MAX_INSTRUCTION_LEN)) continue;
+
+        printf("Label %s at line %i is already defined at line %i\n", lbl, LINE_NUMBER, l.lineNumber);
+        abortLine();
+
+    }
+
+}
+
+void checkLabelName(char* lbl) {
+    // Terminates the program if a given label name could be mistaken for something else
+    // Labels may only contain letters, digits, '_', and '.', may not start with a digit, and may not be named after
+    // a register or instruction in any case, since "r1:" or "jump:" is far more likely to be a typo than a label
+
+    if(!*lbl) {
+
+        printf("Label at line %i has no name\n", LINE_NUMBER);
+        abortLine();
+
+    }
+
+    for(char* c = lbl; *c; c++) {
+
+        if(isalnum(*c) || *c == '_' || *c == '.') continue;
+
+        printf("Label %s at line %i contains '%c', but label names may only contain letters, digits, '_', and '.'\n",
+            lbl, LINE_NUMBER, *c);
+        abortLine();
+
+    }
+
+    if(isdigit(*lbl)) {
+
+        printf("Label %s at line %i starts with a digit, which label names may not do\n", lbl, LINE_NUMBER);
+        abortLine();
+
+    }
+
+    char* upper = strndup(lbl, MAX_INSTRUCTION_LEN);
+    for(char* c = upper; *c; c++) *c = toupper(*c);
+
+    if(fitsRegisterSyntax(upper) || isMnemonic(upper)) {
+
+        printf("Label %s at line %i has the same name as %s\n", lbl, LINE_NUMBER,
+            fitsRegisterSyntax(upper) ? "a register" : "an instruction");
+        abortLine();
+
+    }
+
+    free(upper);
+
+}
+
+void addFixup(char* lbl) {
+    // Records that the instruction currently being assembled uses a label which has not been defined yet
+
+    Fixup f;
+    f.labelName = strndup(lbl, MAX_INSTRUCTION_LEN);
+    f.instructionIndex = PROGRAM_LEN;
+    f.lineNumber = LINE_NUMBER;
+
+    FIXUP_TABLE = realloc(FIXUP_TABLE, (FIXUP_COUNT + 1) * sizeof(Fixup));
+
+    FIXUP_TABLE[FIXUP_COUNT] = f;
+
+    FIXUP_COUNT++;
+
+}
+
+void checkUnresolvedFixups() {
+    // Prints an error for each instruction which references a label that was never defined, in order of their lines
+
+    bool* reported = calloc(FIXUP_COUNT, sizeof(bool));
+
+    for(int n = 0; n < FIXUP_COUNT; n++) {
+
+        int first = -1;
+
+        for(int i = 0; i < FIXUP_COUNT; i++) {
+
+            if(!reported[i] && (first < 0 || FIXUP_TABLE[i].lineNumber < FIXUP_TABLE[first].lineNumber)) first = i;
+
+        }
+
+        reported[first] = true;
+
+        Fixup f = FIXUP_TABLE[first];
+        printf("Cannot use label %s at line %i because it does not exist in the symbol table\n", f.labelName, f.lineNumber);
+
+        ERROR_COUNT++;
+
+    }
+
+    free(reported);
+
+}
+
+void checkUnusedLabels() {
+    // Prints a warning for each label which is never jumped to
+
+    if(!LINT_WARNINGS) return;
+
+    for(int i = 0; i < SYMBOL_COUNT; i++) {
+
+        Label l = SYMBOL_TABLE[i];
+
+        if(!l.referenced && !isGlobalLabel(l.labelName)) printWarning("label %s at line %i is never used", l.labelName, l.lineNumber);
+        // Global labels may be used by other object files
+
+    }
+
+}
+
+void addCalledLabel(char* lbl) {
+    // Records that a label is used by a JUMP-LINK, unless it already has been
+
+    if(isCalledLabel(lbl)) return;
+
+    CALLED_LABELS = realloc(CALLED_LABELS, (CALLED_LABEL_COUNT + 1) * sizeof(char*));
+    CALLED_LABELS[CALLED_LABEL_COUNT++] = strndup(lbl, MAX_INSTRUCTION_LEN);
+
+}
+
+bool isCalledLabel(char* lbl) {
+    // Checks if a label is used by a JUMP-LINK
+
+    for(int i = 0; i < CALLED_LABEL_COUNT; i++) if(!strncmp(CALLED_LABELS[i], lbl, MAX_INSTRUCTION_LEN)) return true;
+
+    return false;
+
+}
+
+void declareGlobal(char* directive) {
+    // Exports the label named by a '.global <label>' directive from the object file
+
+    if(countArgs(directive) != 2) {
+
+        printf("Incorrect number of arguments at line %i\n", LINE_NUMBER);
+        printf("Instruction: %s\n", directive);
+        abortLine();
+
+    }
+
+    GLOBAL_LABELS = realloc(GLOBAL_LABELS, (GLOBAL_LABEL_COUNT + 1) * sizeof(char*));
+    GLOBAL_LABELS[GLOBAL_LABEL_COUNT++] = getWord(directive, 1);
+
+}
+
+bool isGlobalLabel(char* lbl) {
+    // Checks if a label has been declared with .global
+
+    for(int i = 0; i < GLOBAL_LABEL_COUNT; i++) if(!strncmp(GLOBAL_LABELS[i], lbl, MAX_INSTRUCTION_LEN)) return true;
+
+    return false;
+
+}
+
+void writeObject(char* objfile) {
+    // Writes the program image to an object file, which starts with a "SMIS-OBJECT <version>" header line, followed by
+    // an "image <words>" line and one hexadecimal line per word of the image, and then one line for the words of any
+    // .data sections ("data <address> <words>") and one for each global label ("symbol <address> <line> <name>"),
+    // instruction holding a label address ("reloc <index>"), and instruction using a label from another object file
+    // ("import <index> <line> <name>"), where lines starting with '#' are comments
+
+    FILE* objFile = openOutputFile(objfile, "w");
+
+    uint32_t imageLen;
+    uint32_t* image = buildImage(&imageLen);
+
+    fprintf(objFile, "SMIS-OBJECT %i\nimage %u\n", OBJECT_FILE_VERSION, imageLen);
+
+    for(int i = 0; i < imageLen; i++) fprintf(objFile, "%.8X\n", image[i]);
+
+    for(int i = 0; i < PINNED_DATA_COUNT; i++) fprintf(objFile, "data 0x%.4X %u\n", PINNED_DATA[i].start, PINNED_DATA[i].length);
+    if(DATA_LEN) fprintf(objFile, "data 0x%.4X %u\n", PROGRAM_LEN * 2, DATA_LEN);
+
+    for(int i = 0; i < GLOBAL_LABEL_COUNT; i++) {
+
+        int j = 0;
+        while(j < SYMBOL_COUNT && strncmp(SYMBOL_TABLE[j].labelName, GLOBAL_LABELS[i], MAX_INSTRUCTION_LEN)) j++;
+
+        if(j == SYMBOL_COUNT) {
+
+            printf("Cannot export label %s because it does not exist in the symbol table\n", GLOBAL_LABELS[i]);
+            exit(-1);
+
+        }
+
+        fprintf(objFile, "symbol 0x%.4X %u %s\n", SYMBOL_TABLE[j].PCAddress, SYMBOL_TABLE[j].lineNumber, GLOBAL_LABELS[i]);
+
+    }
+
+    for(int i = 0; i < RELOCATION_COUNT; i++) {
+
+        bool imported = false;
+
+        for(int j = 0; j < FIXUP_COUNT; j++) if(FIXUP_TABLE[j].instructionIndex == RELOCATIONS[i]) imported = true;
+        // Addresses of labels from other object files are filled in when linking, so they are not moved
+
+        if(!imported) fprintf(objFile, "reloc %u\n", RELOCATIONS[i]);
+
+    }
+
+    for(int i = 0; i < FIXUP_COUNT; i++) {
+
+        fprintf(objFile, "import %u %u %s\n", FIXUP_TABLE[i].instructionIndex, FIXUP_TABLE[i].lineNumber, FIXUP_TABLE[i].labelName);
+
+    }
+
+    free(image);
+    closeOutputFile(objFile, objfile);
+
+}
+
+void linkObjects(char** objfiles, int objCount) {
+    // Places the images of the given object files one after the other, and resolves the labels they use from each other
+
+    for(int i = 0; i < objCount; i++) readObjectFile(objfiles[i]);
+
+    if(!FIXUP_COUNT) return;
+
+    Fixup f = FIXUP_TABLE[0];
+
+    printf("Cannot use label %s at line %i because it is not exported by any of the object files\n", f.labelName, f.lineNumber);
+    exit(-1);
+
+}
+
+void readObjectFile(char* objfile) {
+    // Adds the image of an object file to the end of the program, moving its label addresses to where it was placed
+
+    FILE* objFile;
+
+    if(!(objFile = fopen(objfile, "r"))) {
+
+        printf("File %s does not exist.\n", objfile);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    char line[MAX_STRING_LEN];
+    int version = 0;
+    int lineNumber = 1;
+    uint32_t base = PROGRAM_LEN;
+    uint32_t imageLen = 0;
+    // The image is placed at address base * 2, since each instruction takes up two words of memory
+
+    if(!fgets(line, MAX_STRING_LEN, objFile) || sscanf(line, "SMIS-OBJECT %i", &version) != 1) {
+
+        printf("File %s is not an object file.\n", objfile);
+        exit(-1);
+
+    }
+
+    if(version < 1 || version > OBJECT_FILE_VERSION) {
+
+        printf("Object file %s has unsupported version %i.\n", objfile, version);
+        exit(-1);
+
+    }
+
+    while(fgets(line, MAX_STRING_LEN, objFile)) {
+
+        unsigned int index;
+        unsigned int sourceLine;
+        unsigned int addr;
+        unsigned int word;
+        unsigned int words;
+        int nameStart = 0;
+
+        lineNumber++;
+        line[strcspn(line, "\n")] = '\0';
+
+        if(!*line || *line == '#') continue;
+
+        if(!imageLen && sscanf(line, "image %u", &imageLen) == 1) {
+
+            if((base + imageLen) * 2 > INT_LIMIT + 1) {
+
+                printf("The linked program does not fit in memory, at line %i of %s\n", lineNumber, objfile);
+                exit(-1);
+
+            }
+
+            PROGRAM = realloc(PROGRAM, (base + imageLen) * sizeof(uint32_t));
+
+            for(int i = 0; i < imageLen; i++) {
+
+                lineNumber++;
+
+                if(!fgets(line, MAX_STRING_LEN, objFile) || sscanf(line, "%x", &word) != 1) {
+
+                    printf("Missing word of the image at line %i of %s\n", lineNumber, objfile);
+                    exit(-1);
+
+                }
+
+                PROGRAM[PROGRAM_LEN++] = word;
+
+            }
+
+        } else if(sscanf(line, "data %x %u", &addr, &words) == 2 && addr <= imageLen * 2 && words <= imageLen * 2 - addr) {
+
+            PINNED_DATA = realloc(PINNED_DATA, (PINNED_DATA_COUNT + 1) * sizeof(DataRange));
+            PINNED_DATA[PINNED_DATA_COUNT].start = base * 2 + addr;
+            PINNED_DATA[PINNED_DATA_COUNT].length = words;
+            PINNED_DATA_COUNT++;
+
+        } else if(sscanf(line, "reloc %u", &index) == 1 && index < imageLen) {
+
+            uint16_t movedAddr = (PROGRAM[base + index] & 0xFFFF) + base * 2;
+            PROGRAM[base + index] = (PROGRAM[base + index] & 0xFFFF0000) | movedAddr;
+
+        } else if(sscanf(line, "symbol %x %u %n", &addr, &sourceLine, &nameStart) == 2 && nameStart && line[nameStart]
+            && addr < imageLen * 2) {
+
+            char* lbl = line + nameStart;
+
+            for(int i = 0; i < SYMBOL_COUNT; i++) {
+
+                if(strncmp(SYMBOL_TABLE[i].labelName, lbl, MAX_INSTRUCTION_LEN)) continue;
+
+                printf("Label %s at line %i of %s is already exported by another object file\n", lbl, sourceLine, objfile);
+                exit(-1);
+
+            }
+
+            INSTRUCTION_ADDR = base * 2 + addr;
+            LINE_NUMBER = sourceLine;
+
+            defineLabel(lbl);
+            // Instructions from earlier object files which import the label are patched as it is defined
+
+        } else if(sscanf(line, "import %u %u %n", &index, &sourceLine, &nameStart) == 2 && nameStart && line[nameStart]
+            && index < imageLen) {
+
+            char* lbl = line + nameStart;
+            int i = 0;
+
+            if(PROGRAM[base + index] >> 24 == OP_JUMP_LINK) addCalledLabel(lbl);
+
+            while(i < SYMBOL_COUNT && strncmp(SYMBOL_TABLE[i].labelName, lbl, MAX_INSTRUCTION_LEN)) i++;
+
+            if(i < SYMBOL_COUNT) {
+
+                PROGRAM[base + index] += SYMBOL_TABLE[i].PCAddress;
+                SYMBOL_TABLE[i].referenced = true;
+
+            } else {
+
+                FIXUP_TABLE = realloc(FIXUP_TABLE, (FIXUP_COUNT + 1) * sizeof(Fixup));
+                FIXUP_TABLE[FIXUP_COUNT].labelName = strndup(lbl, MAX_INSTRUCTION_LEN);
+                FIXUP_TABLE[FIXUP_COUNT].instructionIndex = base + index;
+                FIXUP_TABLE[FIXUP_COUNT].lineNumber = sourceLine;
+                FIXUP_COUNT++;
+                // The label may still be exported by a later object file
+
+            }
+
+        } else {
+
+            printf("Invalid line %i of %s\n", lineNumber, objfile);
+            printf("Line: %s\n", line);
+            exit(-1);
+
+        }
+
+    }
+
+    fclose(objFile);
+
+}
+
+void checkJumpTargets() {
+    // Prints a warning for each jump to an address with no instruction, such as a label at the very end of the program
+    // or a label which is followed by the padding of an .org directive, since the program would stop there
+    // Jumps into a .data section or to an odd address (the middle of an instruction) are reported separately, since
+    // they are usually an off-by-one mistake or the wrong label
+
+    if(!LINT_WARNINGS) return;
+
+    for(int i = 0; i < PROGRAM_LEN; i++) {
+
+        uint8_t opcode = PROGRAM[i] >> 24;
+        uint16_t destAddr = PROGRAM[i] & 0xFFFF;
+        bool imported = false;
+
+        if(opcode < OP_JUMP || opcode > OP_JUMP_LINK || isPinnedData(i * 2)) continue;
+
+        for(int j = 0; j < FIXUP_COUNT; j++) if(FIXUP_TABLE[j].instructionIndex == i) imported = true;
+        // The address of a label from another object file is not known until linking
+
+        if(imported || (destAddr % 2 == 0 && destAddr / 2 < PROGRAM_LEN && PROGRAM[destAddr / 2] && !isPinnedData(destAddr))) continue;
+
+        uint32_t line = PROGRAM_SOURCE[i].lineNumber;
+
+        if((destAddr >= PROGRAM_LEN * 2 && destAddr < PROGRAM_LEN * 2 + DATA_LEN) || isPinnedData(destAddr)) {
+
+            printWarning("the jump at line %i goes to address 0x%.4X, which is part of a .data section", line, destAddr);
+
+        } else if(destAddr % 2) {
+
+            printWarning("the jump at line %i goes to the odd address 0x%.4X, which is in the middle of an instruction", line, destAddr);
+
+        } else printWarning("the jump at line %i goes to address 0x%.4X, where there is no instruction", line, destAddr);
+
+        printWordSpan(stderr, PROGRAM_SOURCE[i].text, 1);
+
+    }
+
+}
+
+void checkUnreachableCode() {
+    // Prints a warning for each instruction which follows an unconditional JUMP or HALT without a label, unless
+    // another instruction jumps to its address directly
+
+    for(int i = 0; i < UNREACHABLE_COUNT; i++) {
+
+        uint32_t index = UNREACHABLE[i];
+        bool reached = false;
+
+        for(int j = 0; j < PROGRAM_LEN; j++) {
+
+            uint8_t opcode = PROGRAM[j] >> 24;
+            if(opcode >= OP_JUMP && opcode <= OP_JUMP_LINK && (PROGRAM[j] & 0xFFFF) == index * 2 && !isPinnedData(j * 2)) reached = true;
+
+        }
+
+        if(reached) continue;
+
+        printWarning("the instruction at line %i can never be reached, since it follows a JUMP or HALT without a label",
+            PROGRAM_SOURCE[index].lineNumber);
+        printSourceSpan(stderr, PROGRAM_SOURCE[index].text, 0, strnlen(PROGRAM_SOURCE[index].text, MAX_STRING_LEN));
+
+    }
+
+}
+
+uint32_t assembleInstruction(char* instruction) {
+    // Assembles all instruction types into their respective numeric values
+
+    uint32_t instructionNum = 0;
+
+    if((instructionNum = RType(instruction))) return instructionNum;
+    else if((instructionNum = IType(instruction))) return instructionNum;
+    else if((instructionNum = JType(instruction))) return instructionNum;
+    else if((instructionNum = SType(instruction))) return instructionNum;
+    else if((instructionNum = CType(instruction))) return instructionNum;
+
+    else {
+
+        printf("Invalid instruction at line %i\n", LINE_NUMBER);
+        printWordSpan(stdout, instruction, 0);
+
+        abortLine();
+
+    }
+
+}
+
+uint32_t RType(char* instruction) {
+    // Assembles all basic R-type (register) instructions, excluding COPY, COMPARE, and NOT
+    // Returns 0 if the given string is not a valid R-type instruction
+
+    uint32_t instructionNum = 0;
+
+    char* opcodeStr = getFirstWord(instruction);
+    uint8_t opcodeNum;
+
+    if(!strncmp(opcodeStr, "ADD", 4)) opcodeNum = OP_ADD;
+    else if(!strncmp(opcodeStr, "SUBTRACT", 9)) opcodeNum = OP_SUBTRACT;
+    else if(!strncmp(opcodeStr, "MULTIPLY", 9)) opcodeNum = OP_MULTIPLY;
+    else if(!strncmp(opcodeStr, "DIVIDE", 7)) opcodeNum = OP_DIVIDE;
+    else if(!strncmp(opcodeStr, "MODULO", 7)) opcodeNum = OP_MODULO;
+
+    else if(!strncmp(opcodeStr, "SHIFT-LEFT", 11)) opcodeNum = OP_SHIFT_LEFT;
+    else if(!strncmp(opcodeStr, "SHIFT-RIGHT", 12)) opcodeNum = OP_SHIFT_RIGHT;
+
+    else if(!strncmp(opcodeStr, "AND", 4)) opcodeNum = OP_AND;
+    else if(!strncmp(opcodeStr, "OR", 3)) opcodeNum = OP_OR;
+    else if(!strncmp(opcodeStr, "XOR", 4)) opcodeNum = OP_XOR;
+    else if(!strncmp(opcodeStr, "NAND", 5)) opcodeNum = OP_NAND;
+    else if(!strncmp(opcodeStr, "NOR", 4)) opcodeNum = OP_NOR;
+
+    else return 0;
+
+    instructionNum += opcodeNum << 24;
+
+    if(countArgs(instruction) != 4) {
+
+        printf("Incorrect number of arguments at line %i\n", LINE_NUMBER);
+        printf("Instruction: %s\n", instruction);
+        abortLine();
+
+    }
+
+    for(int arg = 1; arg <= 3; arg++) {
+        
+        if(!fitsRegisterSyntax(getWord(instruction, arg))) {
+
+            printf("Wrong format of argument %i at line %i\n", arg, LINE_NUMBER);
+            printWordSpan(stdout, instruction, arg);
+            abortLine();
+
+        }
+
+    }
+
+    uint8_t rDest = getRegisterNum(getWord(instruction, 1));
+    uint8_t rOp1 = getRegisterNum(getWord(instruction, 2));
+    uint8_t rOp2 = getRegisterNum(getWord(instruction, 3));
+
+    warnIfZeroRegisterWrite(rDest, instruction);
+
+    instructionNum += rDest << 20;
+    instructionNum += rOp1 << 16;
+    instructionNum += rOp2 << 12;
+
+    return instructionNum;
+
+}
+
+uint32_t IType(char* instruction) {
+    // Assembles all basic I-type (immediate) instructions, excluding SET and COMPARE-IMM
+    // Returns 0 if the given string is not a valid I-type instruction
+
+    uint32_t instructionNum = 0;
+
+    char* opcodeStr = getFirstWord(instruction);
+    uint8_t opcodeNum;
+
+    if(!strncmp(opcodeStr, "ADD-IMM", 8)) opcodeNum = OP_ADD_IMM;
+    else if(!strncmp(opcodeStr, "SUBTRACT-IMM", 13)) opcodeNum = OP_SUBTRACT_IMM;
+    else if(!strncmp(opcodeStr, "MULTIPLY-IMM", 13)) opcodeNum = OP_MULTIPLY_IMM;
+    else if(!strncmp(opcodeStr, "DIVIDE-IMM", 11)) opcodeNum = OP_DIVIDE_IMM;
+    else if(!strncmp(opcodeStr, "MODULO-IMM", 11)) opcodeNum = OP_MODULO_IMM;
+
+    else if(!strncmp(opcodeStr, "SHIFT-LEFT-IMM", 15)) opcodeNum = OP_SHIFT_LEFT_IMM;
+    else if(!strncmp(opcodeStr, "SHIFT-RIGHT-IMM", 16)) opcodeNum = OP_SHIFT_RIGHT_IMM;
+
+    else if(!strncmp(opcodeStr, "AND-IMM", 8)) opcodeNum = OP_AND_IMM;
+    else if(!strncmp(opcodeStr, "OR-IMM", 7)) opcodeNum = OP_OR_IMM;
+    else if(!strncmp(opcodeStr, "XOR-IMM", 8)) opcodeNum = OP_XOR_IMM;
+    else if(!strncmp(opcodeStr, "NAND-IMM", 9)) opcodeNum = OP_NAND_IMM;
+    else if(!strncmp(opcodeStr, "NOR-IMM", 8)) opcodeNum = OP_NOR_IMM;
+    else if(!strncmp(opcodeStr, "LOAD", 5)) opcodeNum = OP_LOAD;
+    else if(!strncmp(opcodeStr, "STORE", 6)) opcodeNum = OP_STORE;
+
+    else return 0;
+
+    instructionNum += opcodeNum << 24;
+
+    if(countArgs(instruction) != 4) {
+
+        printf("Incorrect number of arguments at line %i\n", LINE_NUMBER);
+        printf("Instruction: %s\n", instruction);
+        abortLine();
 
     }
 
-    for(int arg = 1; arg <= 3; arg++) {
+    for(int arg = 1; arg <= 2; arg++) {
         
-        if((arg != 3 && !fitsRegisterSyntax(getWord(instruction, arg)))
-            || (arg == 3 && !fitsImmediateSyntax(getWord(instruction, arg)))) {
+        if(!fitsRegisterSyntax(getWord(instruction, arg))) {
 
             printf("Wrong format of argument %i at line %i\n", arg, LINE_NUMBER);
-            printf("Instruction: %s\n", instruction);
-            exit(-1);
+            printWordSpan(stdout, instruction, arg);
+            abortLine();
 
         }
 
     }
 
+    bool shift = opcodeNum == OP_SHIFT_LEFT_IMM || opcodeNum == OP_SHIFT_RIGHT_IMM;
+    bool labelMode = (opcodeNum == OP_LOAD || opcodeNum == OP_STORE) && !fitsImmediateSyntax(getWord(instruction, 3))
+        && fitsLabelRefSyntax(getWord(instruction, 3));
+    // LOAD and STORE may take the address of a label instead of an immediate, written as "label" or "&label"
+
+    if(!labelMode) checkImmediate(instruction, 3, shift ? MAX_SHIFT_AMOUNT : INT_LIMIT);
+
     uint8_t rDest = getRegisterNum(getWord(instruction, 1));
     uint8_t rOp1 = getRegisterNum(getWord(instruction, 2));
-    uint16_t iOp2 = getImmediateVal(getWord(instruction, 3));
+    uint16_t iOp2;
+
+    if(labelMode) {
+
+        char* lbl = getWord(instruction, 3);
+        iOp2 = getLabelAddr(*lbl == '&' ? lbl + 1 : lbl);
+
+    } else iOp2 = getImmediateVal(getWord(instruction, 3));
+
+    if(opcodeNum != OP_STORE) warnIfZeroRegisterWrite(rDest, instruction);
 
     instructionNum += rDest << 20;
     instructionNum += rOp1 << 16;
@@ -430,11 +3945,24 @@ uint32_t JType(char* instruction) {
 
         printf("Incorrect number of arguments at line %i\n", LINE_NUMBER);
         printf("Instruction: %s\n", instruction);
-        exit(-1);
+        abortLine();
 
     }
 
-    uint16_t destAddr = getLabelAddr(getWord(instruction, 1));
+    uint16_t destAddr;
+
+    if(*getWord(instruction, 1) == '#') {
+
+        checkImmediate(instruction, 1, INT_LIMIT);
+        destAddr = getImmediateVal(getWord(instruction, 1));
+        // An explicit address is used as-is, and is not moved when the program is linked
+
+    } else {
+
+        destAddr = getLabelAddr(getWord(instruction, 1));
+        if(opcodeNum == OP_JUMP_LINK) addCalledLabel(getWord(instruction, 1));
+
+    }
 
     instructionNum += destAddr;
 
@@ -454,6 +3982,8 @@ uint32_t SType(char* instruction) {
     bool immediateMode = false;
     bool compareMode = false;
     bool rDestMode = false;
+    bool labelMode = false;
+    // SET may take the address of a label instead of an immediate, written as "label" or "&label"
     
     if(!strncmp(opcodeStr, "HALT", 5)) return OP_HALT << 24;
     else if(!strncmp(opcodeStr, "SET", 4)) { opcodeNum = OP_SET; immediateMode = true; }
@@ -470,26 +4000,38 @@ uint32_t SType(char* instruction) {
 
         printf("Incorrect number of arguments at line %i\n", LINE_NUMBER);
         printf("Instruction: %s\n", instruction);
-        exit(-1);
+        abortLine();
 
     }
 
+    if(opcodeNum == OP_SET && !fitsImmediateSyntax(getWord(instruction, 2))) labelMode = fitsLabelRefSyntax(getWord(instruction, 2));
+
     for(int arg = 1; arg <= 2; arg++) {
         
         if((arg == 1 && !fitsRegisterSyntax(getWord(instruction, arg)))
-            || (arg == 2 && !immediateMode && !fitsRegisterSyntax(getWord(instruction, arg)))
-            || (arg == 2 && immediateMode && !fitsImmediateSyntax(getWord(instruction, arg)))) {
+            || (arg == 2 && !immediateMode && !fitsRegisterSyntax(getWord(instruction, arg)))) {
 
             printf("Wrong format of argument %i at line %i\n", arg, LINE_NUMBER);
-            printf("Instruction: %s\n", instruction);
-            exit(-1);
+            printWordSpan(stdout, instruction, arg);
+            abortLine();
 
         }
 
     }
 
+    if(immediateMode && !labelMode) checkImmediate(instruction, 2, INT_LIMIT);
+
     uint8_t reg = getRegisterNum(getWord(instruction, 1));
-    uint16_t op = immediateMode ? getImmediateVal(getWord(instruction, 2)) : getRegisterNum(getWord(instruction, 2));
+    uint16_t op;
+
+    if(labelMode) {
+
+        char* lbl = getWord(instruction, 2);
+        op = getLabelAddr(*lbl == '&' ? lbl + 1 : lbl);
+
+    } else op = immediateMode ? getImmediateVal(getWord(instruction, 2)) : getRegisterNum(getWord(instruction, 2));
+
+    if(!compareMode) warnIfZeroRegisterWrite(reg, instruction);
 
     if(compareMode) instructionNum += reg << 16;
     else instructionNum += reg << 20;
@@ -501,19 +4043,154 @@ uint32_t SType(char* instruction) {
 
 }
 
+uint32_t CType(char* instruction) {
+    // Assembles instructions registered through the custom opcode extension mechanism
+    // Returns 0 if the given string is not a registered custom instruction
+
+    char* opcodeStr = getFirstWord(instruction);
+
+    for(int i = 0; i < CUSTOM_OPCODE_COUNT; i++) {
+
+        CustomOpcode c = CUSTOM_OPCODES[i];
+
+        if(!strncmp(opcodeStr, c.mnemonic, MAX_INSTRUCTION_LEN)) return (c.opcode << 24) + (c.assemble(instruction) & 0x00FFFFFF);
+
+    }
+
+    return 0;
+
+}
+
+void registerOpcode(char* mnemonic, uint8_t opcode, uint32_t (*assemble)(char* instruction)) {
+    // Claims an unused opcode for a custom instruction, which is assembled by the given handler
+    // Extensions call this from registerExtensions(), which is linked in alongside the assembler, e.g.
+    //
+    //     uint32_t assembleMAC(char* instruction) {
+    //         return getRegisterNum(getWord(instruction, 1)) << 20 | getRegisterNum(getWord(instruction, 2)) << 16
+    //             | getRegisterNum(getWord(instruction, 3)) << 12;
+    //     }
+    //
+    //     void registerExtensions() { registerOpcode("MAC", 40, assembleMAC); }
+
+    if(opcode < OP_CUSTOM_MIN) {
+
+        printf("Internal error: cannot register %s with opcode %i, which is reserved for built-in instructions\n", mnemonic, opcode);
+        exit(-2);
+
+    }
+
+    for(int i = 0; i < CUSTOM_OPCODE_COUNT; i++) {
+
+        if(CUSTOM_OPCODES[i].opcode == opcode || !strncmp(CUSTOM_OPCODES[i].mnemonic, mnemonic, MAX_INSTRUCTION_LEN)) {
+
+            printf("Internal error: cannot register %s with opcode %i, which conflicts with custom instruction %s\n",
+                mnemonic, opcode, CUSTOM_OPCODES[i].mnemonic);
+            exit(-2);
+
+        }
+
+    }
+
+    CustomOpcode c;
+    c.mnemonic = mnemonic;
+    c.opcode = opcode;
+    c.assemble = assemble;
+
+    CUSTOM_OPCODES = realloc(CUSTOM_OPCODES, (CUSTOM_OPCODE_COUNT + 1) * sizeof(CustomOpcode));
+
+    CUSTOM_OPCODES[CUSTOM_OPCODE_COUNT] = c;
+
+    CUSTOM_OPCODE_COUNT++;
+
+}
+
+void warnIfZeroRegisterWrite(uint8_t rDest, char* instruction) {
+    // Prints a warning if warnings for RZR are enabled and the given destination register is RZR
+
+    if(!WARN_RZR || rDest != 0) return;
+
+    if(!strncmp(instruction, "ADD RZR RZR RZR", MAX_INSTRUCTION_LEN)) return;
+    // This is what NOP is lowered to, which writes to RZR on purpose
+
+    printWarning("the result written to RZR at line %i is discarded", LINE_NUMBER);
+    printWordSpan(stderr, instruction, 1);
+
+}
+
+void printWarning(char* format, ...) {
+    // Prints a warning to stderr, keeping it apart from errors and the assembled output, since warnings do not
+    // stop the program from being assembled
+
+    va_list args;
+
+    va_start(args, format);
+    fprintf(stderr, "Warning: ");
+    vfprintf(stderr, format, args);
+    if(INCLUDE_DEPTH) fprintf(stderr, ", in included file %s", CURRENT_FILE);
+    fputc('\n', stderr);
+    va_end(args);
+
+    WARNING_COUNT++;
+
+}
+
+void printSourceSpan(FILE* out, char* instruction, size_t start, size_t len) {
+    // Prints an instruction along with a ^^^ underline beneath the given span of characters, to point out the part
+    // of the instruction that an error or warning is about
+
+    fprintf(out, "Instruction: %s\n", instruction);
+    fprintf(out, "%*s", (int) (strlen("Instruction: ") + start), "");
+
+    for(size_t i = 0; i < (len ? len : 1); i++) fputc('^', out);
+
+    fputc('\n', out);
+
+}
+
+void printWordSpan(FILE* out, char* instruction, int w) {
+    // Prints an instruction with an indexed word underlined, or the end of the instruction if the word is missing
+
+    size_t start = 0;
+
+    for(int i = 0; i < w && instruction[start]; i++) {
+
+        start += strcspn(instruction + start, " ");
+        if(instruction[start]) start++;
+
+    }
+
+    printSourceSpan(out, instruction, start, strcspn(instruction + start, " "));
+
+}
+
 uint16_t getLabelAddr(char* lbl) {
-    // Reads the symbol table and finds a corresponding label address, terminating the program if none is found
+    // Reads the symbol table and finds a corresponding label address
+    // If the label has not been defined yet, a fixup is recorded and 0 is returned until it is patched
+    // "$" is the address of the instruction being assembled, so that "JUMP $" loops forever
+
+    RELOCATIONS = realloc(RELOCATIONS, (RELOCATION_COUNT + 1) * sizeof(uint32_t));
+    RELOCATIONS[RELOCATION_COUNT++] = PROGRAM_LEN;
+    // The address is only correct as long as the program is placed at address 0, which is not the case when linking
+
+    if(!strncmp(lbl, "$", MAX_INSTRUCTION_LEN)) return INSTRUCTION_ADDR;
 
     for(int i = 0; i < SYMBOL_COUNT; i++) {
 
         Label l = SYMBOL_TABLE[i];
 
-        if(!strncmp(l.labelName, lbl, MAX_INSTRUCTION_LEN)) return l.PCAddress;
+        if(!strncmp(l.labelName, lbl, MAX_INSTRUCTION_LEN)) {
+
+            SYMBOL_TABLE[i].referenced = true;
+
+            return l.PCAddress;
+
+        }
 
     }
 
-    printf("Cannot use label %s at line %i because it does not exist in the symbol table\n", lbl, LINE_NUMBER);
-    exit(-1);
+    addFixup(lbl);
+
+    return 0;
 
 }
 
@@ -521,10 +4198,10 @@ uint8_t getRegisterNum(char* str) {
     // Gets the register address from a given string
     // Assumes that string has already been validated as a proper register address argument
 
-    if(!strncmp(str, "RZR", MAX_STRING_LEN)) return 0;
-    else if(!strncmp(str, "RSP", MAX_STRING_LEN)) return 15;
-    else if(!strncmp(str, "RBP", MAX_STRING_LEN)) return 14;
-    else if(!strncmp(str, "RLR", MAX_STRING_LEN)) return 13;
+    if(!strncasecmp(str, "RZR", MAX_STRING_LEN)) return 0;
+    else if(!strncasecmp(str, "RSP", MAX_STRING_LEN)) return 15;
+    else if(!strncasecmp(str, "RBP", MAX_STRING_LEN)) return 14;
+    else if(!strncasecmp(str, "RLR", MAX_STRING_LEN)) return 13;
 
     return strtol(str + 1, NULL, 10);
 
@@ -534,19 +4211,96 @@ uint16_t getImmediateVal(char* str) {
     // Gets the immediate value from a given string
     // Assumes that string has already been validated as a proper immediate argument
 
-    return strtol(str + 1, NULL, 10);
+    if(str[1] == '\'') return parseCharLiteral(str + 1);
+
+    return parseNumericLiteral(stripDigitSeparators(str + 1));
+
+}
+
+int getImmediateError(char* str) {
+    // Gets the reason why a given string is not a valid immediate argument, or IMMEDIATE_VALID if it is one
+
+    if(*str != '#') return IMMEDIATE_MALFORMED;
+
+    if(str[1] == '\'') {
+
+        int len;
+
+        if(parseCharLiteral(str + 1) >= 0) return IMMEDIATE_VALID;
+        if(str[2] == '\\' && parseEscapeSequence(str + 3, &len) < 0) return IMMEDIATE_BAD_ESCAPE;
+
+        return IMMEDIATE_NOT_NUMERIC;
+
+    }
+
+    char* digits = stripDigitSeparators(str + 1);
+
+    if(!digits) return IMMEDIATE_MALFORMED;
+
+    long value = parseNumericLiteral(digits);
+    free(digits);
+
+    if(value == -1) return IMMEDIATE_NOT_NUMERIC;
+    if(value == -2) return IMMEDIATE_TOO_LARGE;
+
+    return IMMEDIATE_VALID;
+
+}
+
+void checkImmediate(char* instruction, int arg, uint16_t max) {
+    // Checks that the given argument of an instruction is an immediate between 0 and the given maximum,
+    // and reports the reason that it is not one otherwise
+
+    char* str = getWord(instruction, arg);
+    int error = getImmediateError(str);
+
+    if(error == IMMEDIATE_VALID && getImmediateVal(str) > max) error = IMMEDIATE_OUT_OF_RANGE;
+
+    switch(error) {
+
+        case IMMEDIATE_VALID:
+            free(str);
+            return;
+
+        case IMMEDIATE_MALFORMED:
+            printf("Wrong format of argument %i at line %i\n", arg, LINE_NUMBER);
+            break;
+
+        case IMMEDIATE_NOT_NUMERIC:
+            printf("Argument %i at line %i is not a number or character literal\n", arg, LINE_NUMBER);
+            break;
+
+        case IMMEDIATE_TOO_LARGE:
+            printf("Argument %i at line %i does not fit in 16 bits, the largest immediate value is %i\n", arg, LINE_NUMBER, INT_LIMIT);
+            break;
+
+        case IMMEDIATE_BAD_ESCAPE:
+            printf("Argument %i at line %i has an unknown escape sequence, the escapes \\n, \\t, \\0, \\\\, \\', \\\", and \\xNN can be used\n",
+                arg, LINE_NUMBER);
+            break;
+
+        case IMMEDIATE_OUT_OF_RANGE:
+            printf("Argument %i at line %i is out of range, %s only accepts values from 0 to %u\n",
+                arg, LINE_NUMBER, getFirstWord(instruction), max);
+            break;
+
+    }
+
+    printWordSpan(stdout, instruction, arg);
+    abortLine();
 
 }
 
 bool fitsRegisterSyntax(char* str) {
     // Checks if a given string fits the SMIS register standard syntax "R<4-bit unsigned register address>"
+    // Register names are not case-sensitive, so "r1" and "rsp" are accepted as well
 
-    if(*str != 'R') return false;
+    if(toupper(*str) != 'R') return false;
 
-    if(!strncmp(str, "RZR", MAX_STRING_LEN)) return true;
-    else if(!strncmp(str, "RSP", MAX_STRING_LEN)) return true;
-    else if(!strncmp(str, "RBP", MAX_STRING_LEN)) return true;
-    else if(!strncmp(str, "RLR", MAX_STRING_LEN)) return true;
+    if(!strncasecmp(str, "RZR", MAX_STRING_LEN)) return true;
+    else if(!strncasecmp(str, "RSP", MAX_STRING_LEN)) return true;
+    else if(!strncasecmp(str, "RBP", MAX_STRING_LEN)) return true;
+    else if(!strncasecmp(str, "RLR", MAX_STRING_LEN)) return true;
 
     if(!containsOnlyNums(str + 1)) return false;
 
@@ -559,13 +4313,22 @@ bool fitsRegisterSyntax(char* str) {
 
 bool fitsImmediateSyntax(char* str) {
     // Checks if a given string fits the SMIS immediate standard syntax "#<16-bit unsigned int>"
+    // The value may be written in decimal, or in hexadecimal, binary, or octal with a 0x, 0b, or 0o prefix
+    // Underscores may be used to separate groups of digits, such as in "#10_000" or "#0b1010_0101"
+    // A quoted character such as "#'A'" or "#'\n'" may also be used, which gives its ASCII code
 
-    if(*str != '#') return false;
+    return getImmediateError(str) == IMMEDIATE_VALID;
 
-    if(!containsOnlyNums(str + 1)) return false;
+}
+
+bool fitsLabelRefSyntax(char* str) {
+    // Checks if a given string can refer to a label, either by its name or as "&<label name>"
+    // Registers and immediates are never treated as label names, and neither are names starting with a digit,
+    // which are far more likely to be an immediate missing its '#'
+
+    if(*str == '&') str++;
 
-    uint16_t immVal = strtol(str + 1, NULL, 10);
-    if(immVal > INT_LIMIT) return false;
+    if(!*str || *str == '#' || *str == '&' || isdigit(*str) || fitsRegisterSyntax(str)) return false;
 
     return true;
 
@@ -586,8 +4349,8 @@ int countArgs(char* instruction) {
         else {
 
             printf("Incorrect spacing at line %i\n", LINE_NUMBER);
-            printf("Instruction: %s\n", originalInstruction);
-            exit(-1);
+            printSourceSpan(stdout, originalInstruction, instruction - originalInstruction, 1);
+            abortLine();
 
         }
 
@@ -604,19 +4367,54 @@ int countArgs(char* instruction) {
 bool isBlankLineOrComment(char* str) {
     // Checks a line of the ASM file to see if it should be skipped
 
-    if(!strncmp(str, "\n", 2) || !strncmp(str, "//", 2)) return true;
+    if(!*str || !strncmp(str, "\n", 2) || !strncmp(str, "//", 2)) return true;
 
     return false;
 
 }
 
+char* findComment(char* str) {
+    // Finds the start of the comment in a line of the ASM file, skipping over string and character literals
+    // Returns NULL if the line has no comment
+
+    if(!strncmp(str, "//", 2) || (HASH_COMMENTS && *str == '#')) return str;
+    if(!SEMICOLON_COMMENTS) return NULL;
+
+    char quote = '\0';
+    // Quote character of the string or character literal which is currently being skipped over
+
+    for(char* c = str; *c; c++) {
+
+        if(quote && *c == '\\' && c[1]) c++;
+        else if(quote && *c == quote) quote = '\0';
+        else if(!quote && (*c == '"' || *c == '\'')) quote = *c;
+        else if(!quote && *c == ';') return c;
+
+    }
+
+    return NULL;
+
+}
+
+void stripComment(char* str) {
+    // Removes the comment from a line of the ASM file, along with any spaces before it
+
+    char* comment = findComment(str);
+
+    if(!comment) return;
+
+    while(comment > str && (comment[-1] == ' ' || comment[-1] == '\t')) comment--;
+    *comment = '\0';
+
+}
+
 bool isLabel(char* str) {
     // Checks if a given line ends with a ':', denoting that it is a jump label
 
     trimLineBreak(str);
     int len = strnlen(str, MAX_STRING_LEN) - 1;
 
-    return str[len] == ':';
+    return len >= 0 && str[len] == ':';
 
 }
 
@@ -667,6 +4465,121 @@ bool containsOnlyNums(char* str) {
 
 }
 
+int parseCharLiteral(char* str) {
+    // Parses a character literal in single quotes, which may be an escape sequence
+    // Returns -1 if the literal is malformed
+
+    if(*str != '\'' || !str[1]) return -1;
+
+    int value = (unsigned char) str[1];
+    char* end = str + 2;
+
+    if(str[1] == '\\') {
+
+        int len;
+
+        value = parseEscapeSequence(str + 2, &len);
+        end += len;
+
+    }
+
+    if(value < 0 || *end != '\'' || end[1]) return -1;
+
+    return value;
+
+}
+
+int parseEscapeSequence(char* str, int* len) {
+    // Gets the character which is written as a backslash followed by the given string in strings and character literals,
+    // and the number of characters after the backslash which make up the escape sequence
+    // "\xNN" gives the character with the hexadecimal code NN, which must be exactly two digits
+    // Returns -1 if there is no such escape sequence
+
+    *len = 1;
+
+    switch(*str) {
+
+        case 'n': return '\n';
+        case 't': return '\t';
+        case '0': return '\0';
+        case '\\': return '\\';
+        case '\'': return '\'';
+        case '"': return '"';
+
+    }
+
+    if(*str == 'x' && isxdigit(str[1]) && isxdigit(str[2])) {
+
+        *len = 3;
+
+        char digits[3] = {str[1], str[2], '\0'};
+        return strtol(digits, NULL, 16);
+
+    }
+
+    return -1;
+
+}
+
+long parseNumericLiteral(char* str) {
+    // Parses a decimal literal, or a hexadecimal, binary, or octal literal with a 0x, 0b, or 0o prefix
+    // Returns -1 if the literal contains an invalid digit, or -2 if it does not fit in 16 bits
+
+    int base = 10;
+
+    if(str[0] == '0' && str[1] && strchr("xXbBoO", str[1])) {
+
+        base = tolower(str[1]) == 'x' ? 16 : tolower(str[1]) == 'b' ? 2 : 8;
+        str += 2;
+
+    }
+
+    if(!*str) return -1;
+
+    long value = 0;
+
+    for(; *str; str++) {
+
+        int digit = isdigit(*str) ? *str - '0' : isalpha(*str) ? tolower(*str) - 'a' + 10 : base;
+
+        if(digit >= base) return -1;
+
+        value = value * base + digit;
+
+        if(value > INT_LIMIT) value = INT_LIMIT + 1;
+        // Keeps scanning so that an invalid digit after an overflow is still reported as one
+
+    }
+
+    return value > INT_LIMIT ? -2 : value;
+
+}
+
+char* stripDigitSeparators(char* str) {
+    // Gets a copy of a numeric literal with its digit separators removed
+    // Returns NULL if an underscore is not placed between two digits
+
+    char* digits = malloc(strnlen(str, MAX_STRING_LEN) + 1);
+    int len = 0;
+
+    for(int i = 0; str[i]; i++) {
+
+        if(str[i] != '_') digits[len++] = str[i];
+        else if(!i || !isalnum(str[i - 1]) || !isalnum(str[i + 1])) {
+
+            free(digits);
+            return NULL;
+
+        }
+
+    }
+
+    digits[len] = '\0';
+
+    return digits;
+
+}
+
 char* getFirstWord(char* str) {
     // Gets the first word (all characters before first space or null terminator) from a given string
 
@@ -738,6 +4651,41 @@ unsigned char binaryChar(uint8_t n) {
 
 }
 
+uint32_t crc32(uint8_t* data, size_t len) {
+    // Calculates the standard (IEEE 802.3) CRC-32 of the given bytes
+
+    uint32_t crc = 0xFFFFFFFF;
+
+    for(size_t i = 0; i < len; i++) {
+
+        crc ^= data[i];
+
+        for(int b = 0; b < 8; b++) crc = (crc >> 1) ^ (0xEDB88320 & -(crc & 1));
+
+    }
+
+    return ~crc;
+
+}
+
+void printJsonString(FILE* out, char* str) {
+    // Prints a string as a quoted JSON string literal, escaping any characters which cannot appear in one directly
+
+    fputc('"', out);
+
+    for(; *str; str++) {
+
+        if(*str == '"' || *str == '\\') fprintf(out, "\\%c", *str);
+        else if(*str == '\n') fprintf(out, "\\n");
+        else if((unsigned char) *str < 0x20) fprintf(out, "\\u%.4x", *str);
+        else fputc(*str, out);
+
+    }
+
+    fputc('"', out);
+
+}
+
 bool endsWith(char* str, char* substr) {
     // Checks if a given string ends with a given substring
 
@@ -748,4 +4696,67 @@ bool endsWith(char* str, char* substr) {
 
     return !strncmp(str, substr, MAX_STRING_LEN);
 
-}
\ No newline at end of file
+}
+
+FILE* openOutputFile(char* file, char* mode) {
+    // Opens a temporary file next to the given output file, which only takes its place once closeOutputFile() is
+    // called, so that an error part of the way through never leaves a half-written output file behind
+
+    char* tmpfile = malloc(MAX_STRING_LEN * sizeof(char));
+    snprintf(tmpfile, MAX_STRING_LEN, "%s.%i.tmp", file, (int) getpid());
+
+    FILE* out;
+
+    if(!(out = fopen(tmpfile, mode))) {
+
+        printf("Cannot output to file %s.\n", file);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    UNFINISHED_OUTPUT = tmpfile;
+
+    return out;
+
+}
+
+void closeOutputFile(FILE* out, char* file) {
+    // Closes an output file opened by openOutputFile(), and renames the temporary file to replace the output file
+
+    bool failed = ferror(out);
+
+    if(fclose(out) || failed || rename(UNFINISHED_OUTPUT, file)) {
+
+        printf("Cannot output to file %s.\n", file);
+        exit(-1);
+
+    }
+
+    free(UNFINISHED_OUTPUT);
+    UNFINISHED_OUTPUT = NULL;
+
+}
+
+void removeUnfinishedOutput() {
+    // Removes the temporary file of an output file which was not finished, when the program stops with an error
+
+    if(UNFINISHED_OUTPUT) remove(UNFINISHED_OUTPUT);
+
+}
+
+void checkNotInputFile(char* output, char* input) {
+    // Terminates the program if an output file is the same file as an input file, which would overwrite the input
+    // Both paths are compared by the file they point to, so different spellings of the same path are caught as well
+
+    struct stat outputStat;
+    struct stat inputStat;
+
+    if(stat(output, &outputStat) || stat(input, &inputStat)) return;
+    if(outputStat.st_dev != inputStat.st_dev || outputStat.st_ino != inputStat.st_ino) return;
+
+    printf("Output file %s is the same file as input file %s, which would be overwritten.\n", output, input);
+    printf(USAGE);
+    exit(-1);
+
+}
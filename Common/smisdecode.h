@@ -0,0 +1,421 @@
+/*
+
+SMIS binary decoder shared by the emulator and disassembler
+
+Documentation for the SMIS assembly language is hosted at https://github.com/Eyesonjune18/SMIS/blob/main/Documentation/SMIS.pdf
+
+Overview:
+
+    Assembled binaries are read one instruction at a time through a DecodeStream. Opening the stream reads the
+    header (if the file has one) along with its entry point and section table, which are left in the stream for
+    the tool to use. Each call to decodeNext() then gives the next instruction with its address, and once the file
+    ends, the instruction count and CRC-32 listed in the header are checked against what was decoded.
+
+    Each tool is built from a single C file, so this file is included directly and holds the definitions of its
+    functions along with their declarations.
+
+*/
+
+#ifndef SMIS_DECODE_H
+#define SMIS_DECODE_H
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <stdint.h>
+#include <stdbool.h>
+#include <arpa/inet.h>
+
+#ifdef SMIS_MMAP
+#include <sys/mman.h>
+#endif
+// Build with -DSMIS_MMAP to memory-map input binaries instead of reading them into a buffer
+
+#define DECODE_ERROR_LEN    500
+// Longest description of a problem found while decoding
+#define ADDRESS_SPACE_WORDS 0x10000
+// Number of 16-bit words which can be addressed, which every section has to fit in
+
+#define HEADER_MAGIC        0x534D4953
+// "SMIS" in ASCII, marks a binary which starts with a header
+#define HEADER_VERSION      2
+#define HEADER_WORDS        6
+// The header is made up of the magic number, format version, instruction count, CRC-32 of the instructions,
+// entry point address, and section count, followed by the section table
+#define HEADER_V1_WORDS     4
+// Version 1 headers end after the checksum, and have no entry point or section table
+#define SECTION_ENTRY_WORDS 3
+// Each section is described by its type, start address, and length in 16-bit words
+#define SECTION_TEXT        1
+#define SECTION_DATA        2
+#define SECTION_BSS         3
+// Memory which starts out as zero, which is not stored in the file
+
+#define DECODE_ERROR        -1
+#define DECODE_END          0
+#define DECODE_INSTRUCTION  1
+// Results of decodeNext()
+
+
+typedef struct DecodeSection {
+
+    uint32_t type;
+    uint32_t start;
+    uint32_t length;
+
+} DecodeSection;
+
+typedef struct DecodeStream {
+
+    FILE* file;
+    char* filename;
+    bool hasHeader;
+    size_t headerWords;
+    uint32_t count;
+    uint32_t checksum;
+    // Instruction count and CRC-32 listed in the header, which the instructions are checked against once they end
+    uint16_t entryPoint;
+    DecodeSection* sections;
+    uint32_t sectionCount;
+    // Entry point and sections listed in the header, which are freed by closeDecodeStream()
+    uint32_t buffered[HEADER_V1_WORDS];
+    uint32_t bufferedCount;
+    // Words read while looking for a header, which turned out to be instructions of a file without one
+    uint32_t decoded;
+    uint32_t crc;
+    uint32_t fileCrc;
+    // Running CRC-32 of the instructions decoded so far, and of every byte read from the file
+    char error[DECODE_ERROR_LEN];
+    // Description of the problem found, once openDecodeStream() or decodeNext() has failed
+
+} DecodeStream;
+
+
+uint8_t* mapBinaryFile(char* binfile, size_t* len);
+void unmapBinaryFile(uint8_t* data, size_t len);
+bool openDecodeStream(DecodeStream* stream, FILE* file, char* filename);
+int decodeNext(DecodeStream* stream, uint16_t* addr, uint32_t* instruction);
+void closeDecodeStream(DecodeStream* stream);
+size_t readStreamWord(DecodeStream* stream, uint32_t* word);
+bool checkSection(DecodeStream* stream, uint32_t type, uint32_t start, uint32_t length);
+uint32_t crc32(uint8_t* data, size_t len);
+uint32_t crc32Update(uint32_t crc, uint8_t* data, size_t len);
+// Binary file functions
+
+
+uint8_t* mapBinaryFile(char* binfile, size_t* len) {
+    // Maps the given binary file into memory, returning NULL if it cannot be opened
+    // With SMIS_MMAP the file is memory-mapped, otherwise it is read into a heap buffer in one go
+
+    FILE* file;
+
+    if(!(file = fopen(binfile, "rb"))) return NULL;
+
+    fseek(file, 0, SEEK_END);
+    *len = ftell(file);
+    rewind(file);
+
+    uint8_t* data;
+
+#ifdef SMIS_MMAP
+
+    data = *len ? mmap(NULL, *len, PROT_READ, MAP_PRIVATE, fileno(file), 0) : malloc(1);
+    // An empty file cannot be mapped, so it gets an empty buffer instead
+
+    if(data == MAP_FAILED) {
+
+        printf("Cannot memory-map file %s.\n", binfile);
+        exit(-1);
+
+    }
+
+#else
+
+    data = malloc(*len + 1);
+
+    if(fread(data, 1, *len, file) != *len) {
+
+        printf("Cannot read file %s.\n", binfile);
+        exit(-1);
+
+    }
+
+#endif
+
+    fclose(file);
+
+    return data;
+
+}
+
+void unmapBinaryFile(uint8_t* data, size_t len) {
+    // Releases a file previously loaded with mapBinaryFile()
+
+#ifdef SMIS_MMAP
+
+    if(len) munmap(data, len);
+    else free(data);
+
+#else
+
+    free(data);
+
+#endif
+
+}
+
+bool openDecodeStream(DecodeStream* stream, FILE* file, char* filename) {
+    // Starts decoding a binary file one instruction at a time, reading and checking its header (if it has one) so that
+    // the entry point and sections are known before the first instruction
+    // Returns false if the header is invalid, with the problem described in the error of the stream
+
+    memset(stream, 0, sizeof(DecodeStream));
+
+    stream->file = file;
+    stream->filename = filename;
+    stream->crc = 0xFFFFFFFF;
+    stream->fileCrc = 0xFFFFFFFF;
+
+    size_t bytes = 0;
+
+    while(stream->bufferedCount < HEADER_V1_WORDS && (bytes = readStreamWord(stream, &stream->buffered[stream->bufferedCount])) == sizeof(uint32_t)) {
+
+        stream->bufferedCount++;
+
+    }
+
+    if(bytes % sizeof(uint32_t)) {
+
+        snprintf(stream->error, DECODE_ERROR_LEN, "File %s is corrupted or truncated (its size is not a whole number of instructions).", filename);
+        return false;
+
+    }
+
+    if(stream->bufferedCount < HEADER_V1_WORDS || stream->buffered[0] != HEADER_MAGIC) return true;
+    // Files without a header (assembled with --raw) are accepted as-is, since they carry no checksum
+
+    uint32_t version = stream->buffered[1];
+    uint32_t sectionCount = 0;
+    uint32_t entry = 0;
+
+    stream->hasHeader = true;
+    stream->headerWords = HEADER_V1_WORDS;
+    stream->count = stream->buffered[2];
+    stream->checksum = stream->buffered[3];
+    stream->bufferedCount = 0;
+
+    if(version != 1 && version != HEADER_VERSION) {
+
+        snprintf(stream->error, DECODE_ERROR_LEN, "File %s has unsupported format version %u.", filename, version);
+        return false;
+
+    }
+
+    if(version >= 2) {
+
+        if(readStreamWord(stream, &entry) != sizeof(uint32_t) || readStreamWord(stream, &sectionCount) != sizeof(uint32_t)) {
+
+            snprintf(stream->error, DECODE_ERROR_LEN, "File %s is corrupted or truncated (the header does not fit in the file).", filename);
+            return false;
+
+        }
+
+        if(entry % 2 || entry >= (uint64_t) stream->count * 2) {
+
+            snprintf(stream->error, DECODE_ERROR_LEN, "File %s has an entry point at 0x%.4X, where there is no instruction.", filename, entry);
+            return false;
+
+        }
+
+        stream->entryPoint = entry;
+        stream->headerWords = HEADER_WORDS;
+
+    }
+
+    for(uint32_t i = 0; i < sectionCount; i++) {
+
+        uint32_t type, start, length;
+
+        if(readStreamWord(stream, &type) != sizeof(uint32_t) || readStreamWord(stream, &start) != sizeof(uint32_t)
+            || readStreamWord(stream, &length) != sizeof(uint32_t)) {
+
+            snprintf(stream->error, DECODE_ERROR_LEN, "File %s is corrupted or truncated (the header does not fit in the file).", filename);
+            return false;
+
+        }
+
+        if(!checkSection(stream, type, start, length)) return false;
+
+        stream->sections = realloc(stream->sections, (stream->sectionCount + 1) * sizeof(DecodeSection));
+        stream->sections[stream->sectionCount].type = type;
+        stream->sections[stream->sectionCount].start = start;
+        stream->sections[stream->sectionCount].length = length;
+        stream->sectionCount++;
+
+        stream->headerWords += SECTION_ENTRY_WORDS;
+
+    }
+
+    return true;
+
+}
+
+int decodeNext(DecodeStream* stream, uint16_t* addr, uint32_t* instruction) {
+    // Decodes the next instruction of the stream along with its address
+    // Returns DECODE_INSTRUCTION, or DECODE_END once the file ends, after which the instruction count and checksum
+    // from the header have been checked, or DECODE_ERROR with the problem described in the error of the stream
+
+    uint32_t word;
+
+    if(stream->decoded < stream->bufferedCount) word = stream->buffered[stream->decoded];
+    else {
+
+        size_t bytes = readStreamWord(stream, &word);
+
+        if(bytes % sizeof(uint32_t)) {
+
+            snprintf(stream->error, DECODE_ERROR_LEN, "File %s is corrupted or truncated (its size is not a whole number of instructions).", stream->filename);
+            return DECODE_ERROR;
+
+        }
+
+        if(!bytes) {
+
+            if(stream->hasHeader && stream->decoded != stream->count) {
+
+                snprintf(stream->error, DECODE_ERROR_LEN, "File %s is corrupted or truncated (header lists %u instructions, found %u).",
+                    stream->filename, stream->count, stream->decoded);
+                return DECODE_ERROR;
+
+            }
+
+            if(stream->hasHeader && stream->checksum != ~stream->crc) {
+
+                snprintf(stream->error, DECODE_ERROR_LEN, "File %s is corrupted or truncated (checksum mismatch).", stream->filename);
+                return DECODE_ERROR;
+
+            }
+
+            return DECODE_END;
+
+        }
+
+    }
+
+    if(stream->hasHeader && stream->decoded == stream->count) {
+
+        uint32_t found = stream->decoded + 1;
+        while(readStreamWord(stream, &word) == sizeof(uint32_t)) found++;
+
+        snprintf(stream->error, DECODE_ERROR_LEN, "File %s is corrupted or truncated (header lists %u instructions, found %u).",
+            stream->filename, stream->count, found);
+        return DECODE_ERROR;
+
+    }
+
+    if(stream->decoded >= ADDRESS_SPACE_WORDS / 2) {
+
+        snprintf(stream->error, DECODE_ERROR_LEN, "File %s holds more instructions than fit in memory.", stream->filename);
+        return DECODE_ERROR;
+
+    }
+
+    uint32_t bigEndian = htonl(word);
+    stream->crc = crc32Update(stream->crc, (uint8_t*) &bigEndian, sizeof(uint32_t));
+
+    *addr = stream->decoded * 2;
+    *instruction = word;
+    stream->decoded++;
+
+    return DECODE_INSTRUCTION;
+
+}
+
+void closeDecodeStream(DecodeStream* stream) {
+    // Releases the sections read from the header of the stream, once the tool has taken what it needs from them
+    // The file itself is left open, since it was opened by the caller
+
+    free(stream->sections);
+
+    stream->sections = NULL;
+    stream->sectionCount = 0;
+
+}
+
+size_t readStreamWord(DecodeStream* stream, uint32_t* word) {
+    // Reads the next big-endian 32-bit word of the stream
+    // Returns the number of bytes read, which is less than a whole word at the end of the file
+
+    uint8_t bytes[sizeof(uint32_t)];
+    size_t len = fread(bytes, 1, sizeof(uint32_t), stream->file);
+
+    stream->fileCrc = crc32Update(stream->fileCrc, bytes, len);
+    *word = (uint32_t) bytes[0] << 24 | bytes[1] << 16 | bytes[2] << 8 | bytes[3];
+
+    return len;
+
+}
+
+bool checkSection(DecodeStream* stream, uint32_t type, uint32_t start, uint32_t length) {
+    // Checks that a section from the header of the stream fits in the program, or in memory for the .bss section
+    // Returns false if it does not fit, with the problem described in the error of the stream
+
+    uint64_t programLen = (uint64_t) stream->count * 2;
+
+    if(type == SECTION_BSS) {
+
+        if(start < programLen || start > ADDRESS_SPACE_WORDS || length > ADDRESS_SPACE_WORDS - start) {
+
+            snprintf(stream->error, DECODE_ERROR_LEN, "File %s has a .bss section at 0x%.4X which does not fit in memory.", stream->filename, start);
+            return false;
+
+        }
+
+        return true;
+
+    }
+    // The .bss section is not stored in the file, so it lies past the end of the program
+
+    if(start > programLen || length > programLen - start) {
+
+        snprintf(stream->error, DECODE_ERROR_LEN, "File %s has a section at 0x%.4X which does not fit in the program.", stream->filename, start);
+        return false;
+
+    }
+
+    if(type == SECTION_DATA && length && start % 2) {
+
+        snprintf(stream->error, DECODE_ERROR_LEN, "File %s has a .data section at 0x%.4X, which does not start where an instruction would.",
+            stream->filename, start);
+        return false;
+
+    }
+    // Other section types are not checked, so that newer files with more kinds of sections can still be loaded
+
+    return true;
+
+}
+
+uint32_t crc32(uint8_t* data, size_t len) {
+    // Calculates the standard (IEEE 802.3) CRC-32 of the given bytes
+
+    return ~crc32Update(0xFFFFFFFF, data, len);
+
+}
+
+uint32_t crc32Update(uint32_t crc, uint8_t* data, size_t len) {
+    // Continues a CRC-32 with more bytes, starting from 0xFFFFFFFF, which is inverted once every byte has been added
+
+    for(size_t i = 0; i < len; i++) {
+
+        crc ^= data[i];
+
+        for(int b = 0; b < 8; b++) crc = (crc >> 1) ^ (0xEDB88320 & -(crc & 1));
+
+    }
+
+    return crc;
+
+}
+
+#endif
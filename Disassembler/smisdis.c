@@ -13,7 +13,7 @@ Program overview:
     (Pass 1)
         The machine code file is scanned for jump labels by reading J-Type instruction
         destination addresses. These addresses are placed into the symbol table, along with
-        a generic label name. Each symbol represents a name and a target program counter address
+        a generic label name (func_N for JUMP-LINK targets, Label_N otherwise). Each symbol represents a name and a target program counter address
         (to be checked against later for jump instructions).
 
     (Pass 2)
@@ -24,21 +24,84 @@ Program overview:
 
 */
 
-
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
 #include <stdint.h>
 #include <stdbool.h>
+#include <ctype.h>
 #include <arpa/inet.h>
-
-
-#define USAGE "Usage: ./smisdis <input .bin machine code file> <output .txt ASM file>\n"
+#include <unistd.h>
+#include <sys/stat.h>
+#include <sys/wait.h>
+
+#include "../Common/smisdecode.h"
+// Binary decoder shared with the emulator, which memory-maps input binaries when built with -DSMIS_MMAP
+
+
+#define USAGE "Usage: ./smisdis [options] <input .bin machine code file> <output .txt ASM file>\n" \
+    "       ./smisdis --verify <input .bin machine code file>\n" \
+    "       ./smisdis --stats <input .bin machine code file>\n" \
+    "       ./smisdis --convert <input image file> <output image file>\n" \
+    "       ./smisdis [options] --diff <old .bin machine code file> <new .bin machine code file>\n" \
+    "       ./smisdis [options] --roundtrip [--assembler <smisasm executable>] <input .txt ASM file>\n" \
+    "Options:\n" \
+    "  --explain                  Describe each instruction in a comment\n" \
+    "  --annotate                 Start each instruction with its address and 32-bit encoding\n" \
+    "  --strict                   Reject instructions with reserved bits set\n" \
+    "  --force                    Accept input and output files with any extension\n" \
+    "  --function-headers         Put a comment header above each function\n" \
+    "  --xref                     End the output with a table of the addresses which jump to each label\n" \
+    "  --symbols <.sym file>      Name labels after the symbols written by the assembler\n" \
+    "  --data-range <start>-<end> Write the words from start to end as .word data instead of instructions\n" \
+    "  --numeric-targets          Write jump targets as addresses (JUMP #0x0040) instead of generated labels\n" \
+    "  --indent <n>               Indent instructions by n spaces (default 0)\n" \
+    "  --operand-column <n>       Align operands to start at column n (default: one space after the mnemonic)\n" \
+    "  --lowercase                Write mnemonics in lowercase\n" \
+    "  --blank-before-label <n>   Put n blank lines before each label (default 1)\n" \
+    "  --blank-after-label <n>    Put n blank lines after each label (default 0)\n" \
+    "  --color <auto|always|never> Color the output printed to the terminal (default auto)\n" \
+    "Image formats for --convert are chosen by extension: .bin (raw machine code), .hex (Intel HEX),\n" \
+    ".txt (one 32-bit hex instruction per line), and .memh ($readmemh, one 16-bit hex word per line)\n"
 #define MAX_INSTRUCTION_LEN 50
 #define MAX_STRING_LEN 500
+#define EXPLANATION_COLUMN 32
+// Column at which --explain comments start
+#define DATA_WORDS_PER_LINE 8
+// Most data words written on a single .word or .ascii line
+#define DATA_MIN_STRING_LEN 3
+// Fewest characters in a row which are written as an .ascii string instead of .word data
+#define DATA_MAX_STRING_LEN ((MAX_STRING_LEN - 12) / 2)
+// Most characters written as one .ascii string, which still fits in a line of the assembler if every one is escaped
+#define MAX_FORMAT_WIDTH 40
+// Largest indentation, column, or number of blank lines accepted by the formatting options
 #define INT_LIMIT 65535
 #define INSTRUCTION_NUMBER INSTRUCTION_ADDR / 2
 
+#define SYMBOL_FILE_VERSION 1
+// Newest version of the .sym format (written by the assembler) which can be read
+
+#define COLOR_AUTO          0
+#define COLOR_ALWAYS        1
+#define COLOR_NEVER         2
+// Whether the printed output is colored, where auto only colors it when stdout is a terminal
+#define ANSI_MNEMONIC       "\033[1;34m"
+#define ANSI_REGISTER       "\033[32m"
+#define ANSI_IMMEDIATE      "\033[33m"
+#define ANSI_LABEL          "\033[35m"
+#define ANSI_DIRECTIVE      "\033[36m"
+#define ANSI_COMMENT        "\033[90m"
+#define ANSI_RESET          "\033[0m"
+
+#define IMAGE_BIN           0
+#define IMAGE_INTEL_HEX     1
+#define IMAGE_TEXT_HEX      2
+#define IMAGE_MEMH          3
+#define MAX_IMAGE_LEN       0x20000
+// Largest memory image in bytes, which is the entire 16-bit address space of 16-bit words
+#define INTEL_HEX_RECORD_LEN 16
+// Number of data bytes written per Intel HEX record
+
 #define OP_SET              1
 #define OP_COPY             2
 
@@ -85,174 +148,1819 @@ Program overview:
 
 #define OP_HALT             36
 
+#define OP_CUSTOM_MIN       37
+// Opcodes from here up to 255 may be claimed by custom instructions (see registerOpcode())
+
 
 typedef struct Label {
 
     char* labelName;
     uint16_t PCAddress;
+    bool isFunction;
+    // Labels which are the target of a JUMP-LINK are named func_N instead of Label_N
 
 } Label;
 
+typedef struct DataRange {
+
+    uint32_t start;
+    uint32_t length;
+
+} DataRange;
+
+typedef struct CustomOpcode {
+
+    uint8_t opcode;
+    char* (*disassemble)(uint32_t instruction);
+    // Returns the full line of code for the given instruction
+
+} CustomOpcode;
+
+
+char* OPCODE_EXPLANATIONS[OP_HALT + 1] = {
+
+    [OP_SET]             = "{d} = {i}",
+    [OP_COPY]            = "{d} = {a}",
+
+    [OP_ADD]             = "{d} = {a} + {b}, sets zero/sign flags",
+    [OP_SUBTRACT]        = "{d} = {a} - {b}, sets zero/sign flags",
+    [OP_MULTIPLY]        = "{d} = {a} * {b}, sets zero/sign flags",
+    [OP_DIVIDE]          = "{d} = {a} / {b}, sets zero/sign flags",
+    [OP_MODULO]          = "{d} = {a} % {b}, sets zero/sign flags",
+
+    [OP_COMPARE]         = "compares {a} with {b}, sets zero/sign flags",
+
+    [OP_SHIFT_LEFT]      = "{d} = {a} << {b}, sets zero/sign flags",
+    [OP_SHIFT_RIGHT]     = "{d} = {a} >> {b}, sets zero/sign flags",
+
+    [OP_AND]             = "{d} = {a} & {b}, sets zero/sign flags",
+    [OP_OR]              = "{d} = {a} | {b}, sets zero/sign flags",
+    [OP_XOR]             = "{d} = {a} ^ {b}, sets zero/sign flags",
+    [OP_NAND]            = "{d} = ~({a} & {b}), sets zero/sign flags",
+    [OP_NOR]             = "{d} = ~({a} | {b}), sets zero/sign flags",
+    [OP_NOT]             = "{d} = ~{a}, sets zero/sign flags",
+
+    [OP_ADD_IMM]         = "{d} = {a} + {i}, sets zero/sign flags",
+    [OP_SUBTRACT_IMM]    = "{d} = {a} - {i}, sets zero/sign flags",
+    [OP_MULTIPLY_IMM]    = "{d} = {a} * {i}, sets zero/sign flags",
+    [OP_DIVIDE_IMM]      = "{d} = {a} / {i}, sets zero/sign flags",
+    [OP_MODULO_IMM]      = "{d} = {a} % {i}, sets zero/sign flags",
+
+    [OP_COMPARE_IMM]     = "compares {a} with {i}, sets zero/sign flags",
+    [OP_SHIFT_LEFT_IMM]  = "{d} = {a} << {i}, sets zero/sign flags",
+    [OP_SHIFT_RIGHT_IMM] = "{d} = {a} >> {i}, sets zero/sign flags",
+    [OP_AND_IMM]         = "{d} = {a} & {i}, sets zero/sign flags",
+    [OP_OR_IMM]          = "{d} = {a} | {i}, sets zero/sign flags",
+    [OP_XOR_IMM]         = "{d} = {a} ^ {i}, sets zero/sign flags",
+    [OP_NAND_IMM]        = "{d} = ~({a} & {i}), sets zero/sign flags",
+    [OP_NOR_IMM]         = "{d} = ~({a} | {i}), sets zero/sign flags",
+
+    [OP_LOAD]            = "{d} = memory[{a} + {i}]",
+    [OP_STORE]           = "memory[{a} + {i}] = {d}",
+
+    [OP_JUMP]            = "continue at {l}",
+    [OP_JUMP_IF_ZERO]    = "continue at {l} if the zero flag is set",
+    [OP_JUMP_IF_NOTZERO] = "continue at {l} if the zero flag is not set",
+    [OP_JUMP_LINK]       = "RLR = return address, continue at {l}",
+
+    [OP_HALT]            = "stop the program"
+
+};
+// Plain-English description of each opcode used by --explain, where {d}, {a}, and {b} are the three register
+// operands, {i} is the immediate value, and {l} is the jump label
+
 
 Label* SYMBOL_TABLE;
 // Stores all labels in the assembled file
 uint32_t SYMBOL_COUNT = 0;
 // Stores the amount of symbols to avoid iterating over unallocated pointers
 
+CustomOpcode* CUSTOM_OPCODES = NULL;
+// Stores all instructions registered by extensions
+uint32_t CUSTOM_OPCODE_COUNT = 0;
+// Stores the amount of custom instructions
+
 uint16_t INSTRUCTION_ADDR = 0;
 // Instruction address is stored for symbol table usage
-
-
-void createLabels(char* readfile);
-void readInstructions(char* readfile, char* writefile);
+size_t PROGRAM_END = 0;
+// Address just past the last instruction, since labels are only written out in front of instructions
+uint16_t ENTRY_POINT = 0;
+// Address where execution of the program starts, which is read from the header
+DataRange* DATA_RANGES = NULL;
+uint32_t DATA_RANGE_COUNT = 0;
+// Addresses and lengths of the words which are data rather than instructions, which are read from the .data sections
+// of the header unless a range is given with --data-range
+bool DATA_RANGE_SET = false;
+uint32_t TEXT_LEN = 0;
+// Length of the .text section from the header, which also covers the data of each object file in a linked program
+bool NUMERIC_TARGETS = false;
+// Writes jump targets as addresses instead of labels, so that the output of two similar binaries can be diffed
+// without every generated label number after a change being different
+uint32_t BSS_START = 0;
+uint32_t BSS_LEN = 0;
+// Address and length of the .bss section, which is written back as a .bss section after the instructions
+bool SYMBOLIC_SET = false;
+// Writes SET immediates and LOAD/STORE addresses which match a label from the symbol file as "&<label>",
+// set when --symbols is used
+
+bool EXPLAIN_MODE = false;
+// Appends a plain-English description to each disassembled instruction
+bool ANNOTATE_MODE = false;
+// Prefixes each disassembled instruction with its address and encoding, which makes a listing rather than a file
+// that can be assembled again
+bool STRICT_MODE = false;
+// Rejects instructions which have any of their reserved encoding bits set
+bool FORCE_EXTENSIONS = false;
+// Accepts files with any extension instead of requiring .bin and .txt
+bool FUNCTION_HEADERS = false;
+// Puts a comment header above each function, so that the boundaries between functions stand out
+bool XREF_MODE = false;
+// Ends the output with a comment table listing every address which jumps to each label
+int INDENT_WIDTH = 0;
+// Number of spaces in front of each instruction
+int OPERAND_COLUMN = 0;
+// Column at which operands start, or 0 to separate them from the mnemonic by a single space
+bool LOWERCASE_MNEMONICS = false;
+// Writes mnemonics in lowercase rather than the standard uppercase
+int BLANK_LINES_BEFORE_LABEL = 1;
+int BLANK_LINES_AFTER_LABEL = 0;
+// Number of blank lines placed around each label
+int COLOR_MODE = COLOR_AUTO;
+// Colors the mnemonics, registers, immediates, labels, and comments of the printed output, but never the output file
+char* UNFINISHED_OUTPUT = NULL;
+// Temporary file of the output file which is being written, which is removed if the disassembler stops before it is done
+bool VERIFY_MODE = false;
+// Only checks that the binary file is valid instead of disassembling it
+bool STATS_MODE = false;
+// Counts how often each opcode and instruction format is used instead of disassembling the file
+bool CONVERT_MODE = false;
+// Converts the input memory image to another image format instead of disassembling it
+bool DIFF_MODE = false;
+// Compares the instructions of two binary files instead of disassembling one
+bool ROUNDTRIP_MODE = false;
+// Assembles an ASM file, disassembles it, and assembles the disassembly again to check that nothing changed
+char* ASSEMBLER_PATH = NULL;
+// Assembler which is run for --roundtrip, which is looked for next to the disassembler unless it is given
+
+
+void createLabels(uint8_t* data, size_t len);
+void readSymbolFile(char* symfile);
+void readInstructions(uint8_t* data, size_t len, char* writefile);
+void writeDataSection(FILE* txtFile, uint8_t* data, DataRange* range);
+uint32_t getDataStringLength(uint8_t* data, uint32_t addr, DataRange* range);
+void writeDataString(FILE* txtFile, uint8_t* data, uint32_t addr, uint32_t len);
+uint16_t getDataWord(uint8_t* data, uint32_t addr);
+void writeBssSection(FILE* txtFile);
+void writeCrossReferences(FILE* txtFile, uint8_t* data, size_t len);
+void printColoredLine(char* line);
+uint32_t verifyInstructions(uint8_t* data, size_t len);
+void printStatistics(char* binfile, uint8_t* data, size_t len);
 // Program control functions
 
+uint32_t getInstructionAt(uint8_t* data, size_t index);
+size_t checkBinaryHeader(uint8_t* data, size_t len, char* binfile);
+// Binary file functions
+
+void convertImage(char* infile, char* outfile);
+int getImageFormat(char* file);
+uint8_t* readImage(char* file, size_t* len);
+uint8_t* readIntelHex(FILE* file, char* filename, size_t* len);
+uint8_t* readHexWords(FILE* file, char* filename, int wordLen, size_t* len);
+void writeImage(char* file, uint8_t* data, size_t len);
+// Image conversion functions
+
+int diffBinaries(char* binA, char* binB);
+char* describeWord(uint32_t word, uint32_t addr);
+// Binary comparison functions
+
+int roundTrip(char* txtfile);
+bool compareBinaries(char* binA, char* binB);
+bool compareSymbols(char* symA, char* symB);
+int runAssembler(char* txtfile, char* binfile, char* symfile);
+char* findAssembler(char* disassembler);
+char* makeTempFile(char* extension);
+// Round trip functions
+
 char* disassembleInstruction(uint32_t instruction);
 char* RType(uint32_t instruction);
 char* IType(uint32_t instruction);
 char* JType(uint32_t instruction);
+char* CType(uint32_t instruction);
+char* explainInstruction(uint32_t instruction);
+char* formatInstruction(char* instructionStr);
 // Instruction disassembly functions
 
+void registerOpcode(uint8_t opcode, char* (*disassemble)(uint32_t instruction));
+void registerExtensions() __attribute__((weak));
+// Custom opcode extension functions
+
 char* formatRegNum(uint16_t regNum);
 char* formatImmediateVal(uint16_t immVal);
 bool labelExists(uint16_t addr);
+bool isDataAddress(uint32_t addr);
+DataRange* findDataRange(uint32_t addr);
 uint8_t getOpcode(uint32_t instruction);
 uint8_t getRegOperand(uint32_t instruction, uint8_t opNum);
 uint16_t getDestOrImmVal(uint32_t instruction);
-char* getLabelName(uint16_t addr);
-char* generateLabelName(uint16_t labelNum);
+char* getLabelName(uint16_t addr, bool isCall);
+char* formatJumpTarget(uint16_t addr, bool isCall);
+uint32_t findLabelNames(uint16_t addr, char*** names);
+void addLabel(char* labelName, uint16_t addr, bool isFunction);
+char* generateLabelName(char* prefix, uint16_t labelNum);
+bool isFunctionStart(uint16_t addr);
 bool isJump(uint32_t instruction);
+bool isValidJumpTarget(uint16_t addr);
+bool isRegisterName(char* str, size_t len);
+uint32_t getReservedBits(uint8_t opcode);
 // Disassembler utility functions
 
 bool isEmpty(char* str);
+int parseNumberArg(char** argv, int argc, int* i);
 bool endsWith(char* str, char* substr);
 void addLineBreak(char* str);
 void trimLabelColon(char* str);
 void trimChar(char* str, char c);
+FILE* openOutputFile(char* file, char* mode);
+void closeOutputFile(FILE* out, char* file);
+void removeUnfinishedOutput();
+void checkNotInputFile(char* output, char* input);
 // General utility functions
 
 
 int main(int argc, char** argv) {
 
-    if(argc != 3) {
+    char* files[2];
+    int fileCount = 0;
+    char* symfile = NULL;
+
+    for(int i = 1; i < argc; i++) {
+
+        if(!strncmp(argv[i], "--explain", MAX_STRING_LEN)) EXPLAIN_MODE = true;
+        else if(!strncmp(argv[i], "--annotate", MAX_STRING_LEN)) ANNOTATE_MODE = true;
+        else if(!strncmp(argv[i], "--strict", MAX_STRING_LEN)) STRICT_MODE = true;
+        else if(!strncmp(argv[i], "--force", MAX_STRING_LEN) || !strncmp(argv[i], "--no-ext-check", MAX_STRING_LEN)) FORCE_EXTENSIONS = true;
+        else if(!strncmp(argv[i], "--verify", MAX_STRING_LEN)) VERIFY_MODE = true;
+        else if(!strncmp(argv[i], "--stats", MAX_STRING_LEN)) STATS_MODE = true;
+        else if(!strncmp(argv[i], "--convert", MAX_STRING_LEN)) CONVERT_MODE = true;
+        else if(!strncmp(argv[i], "--diff", MAX_STRING_LEN)) DIFF_MODE = true;
+        else if(!strncmp(argv[i], "--roundtrip", MAX_STRING_LEN)) ROUNDTRIP_MODE = true;
+        else if(!strncmp(argv[i], "--assembler", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No assembler supplied for --assembler.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            ASSEMBLER_PATH = argv[i];
+
+        }
+        else if(!strncmp(argv[i], "--function-headers", MAX_STRING_LEN)) FUNCTION_HEADERS = true;
+        else if(!strncmp(argv[i], "--xref", MAX_STRING_LEN)) XREF_MODE = true;
+        else if(!strncmp(argv[i], "--numeric-targets", MAX_STRING_LEN)) NUMERIC_TARGETS = true;
+        else if(!strncmp(argv[i], "--symbols", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No symbol file supplied for --symbols.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            symfile = argv[i];
+
+        }
+        else if(!strncmp(argv[i], "--data-range", MAX_STRING_LEN)) {
+
+            int start, end;
+            char extra;
+
+            if(++i >= argc) {
+
+                printf("No range supplied for --data-range.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            if(sscanf(argv[i], "%i-%i%c", &start, &end, &extra) != 2 || start < 0 || start % 2 || end < start || end > INT_LIMIT) {
+
+                printf("Range %s for --data-range must be written as <start>-<end>, where start is the even address of an instruction and end is not before it.\n",
+                    argv[i]);
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            DATA_RANGES = malloc(sizeof(DataRange));
+            DATA_RANGES[0].start = start;
+            DATA_RANGES[0].length = end - start + 1;
+            DATA_RANGE_COUNT = 1;
+            DATA_RANGE_SET = true;
+
+        }
+        else if(!strncmp(argv[i], "--indent", MAX_STRING_LEN)) INDENT_WIDTH = parseNumberArg(argv, argc, &i);
+        else if(!strncmp(argv[i], "--operand-column", MAX_STRING_LEN)) OPERAND_COLUMN = parseNumberArg(argv, argc, &i);
+        else if(!strncmp(argv[i], "--lowercase", MAX_STRING_LEN)) LOWERCASE_MNEMONICS = true;
+        else if(!strncmp(argv[i], "--blank-before-label", MAX_STRING_LEN)) BLANK_LINES_BEFORE_LABEL = parseNumberArg(argv, argc, &i);
+        else if(!strncmp(argv[i], "--blank-after-label", MAX_STRING_LEN)) BLANK_LINES_AFTER_LABEL = parseNumberArg(argv, argc, &i);
+        else if(!strncmp(argv[i], "--color", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No mode supplied for --color.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            if(!strncmp(argv[i], "auto", MAX_STRING_LEN)) COLOR_MODE = COLOR_AUTO;
+            else if(!strncmp(argv[i], "always", MAX_STRING_LEN)) COLOR_MODE = COLOR_ALWAYS;
+            else if(!strncmp(argv[i], "never", MAX_STRING_LEN)) COLOR_MODE = COLOR_NEVER;
+            else {
+
+                printf("Mode %s for --color must be auto, always, or never.\n", argv[i]);
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+        }
+        else if(fileCount < 2) files[fileCount++] = argv[i];
+        else {
+
+            fileCount++;
+            break;
+
+        }
+
+    }
+
+    if(fileCount != (VERIFY_MODE || STATS_MODE || ROUNDTRIP_MODE ? 1 : 2)) {
+
+        printf("Incorrect number of arguments supplied.\n");
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    atexit(removeUnfinishedOutput);
+
+    if(!VERIFY_MODE && !STATS_MODE && !ROUNDTRIP_MODE && !DIFF_MODE) {
+
+        checkNotInputFile(files[1], files[0]);
+        if(symfile) checkNotInputFile(files[1], symfile);
+
+    }
+
+    if(CONVERT_MODE) {
+
+        convertImage(files[0], files[1]);
+        return 0;
+
+    }
+
+    if(DIFF_MODE) {
+
+        if(!FORCE_EXTENSIONS && (!endsWith(files[0], ".bin") || !endsWith(files[1], ".bin"))) {
+
+            printf("One or both of the supplied files have incorrect extensions.\n");
+            printf(USAGE);
+            exit(-1);
+
+        }
+
+        if(registerExtensions) registerExtensions();
+
+        return diffBinaries(files[0], files[1]);
+
+    }
+
+    if(ROUNDTRIP_MODE) {
+
+        if(!FORCE_EXTENSIONS && !endsWith(files[0], ".txt")) {
+
+            printf("The supplied file has an incorrect extension.\n");
+            printf(USAGE);
+            exit(-1);
+
+        }
+
+        if(!ASSEMBLER_PATH) ASSEMBLER_PATH = findAssembler(argv[0]);
+        if(registerExtensions) registerExtensions();
+
+        return roundTrip(files[0]);
+
+    }
+
+    char* binfile = files[0];
+    char* txtfile = VERIFY_MODE || STATS_MODE ? ".txt" : files[1];
+
+    if(!FORCE_EXTENSIONS && (!endsWith(binfile, ".bin") || !endsWith(txtfile, ".txt"))) {
+
+        printf("One or both of the supplied files have incorrect extensions.\n");
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    SYMBOL_TABLE = NULL;
+
+    if(registerExtensions) registerExtensions();
+
+    size_t len;
+    uint8_t* data;
+
+    if(!(data = mapBinaryFile(binfile, &len))) {
+
+        printf("File %s does not exist.\n", binfile);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    size_t headerLen = checkBinaryHeader(data, len, binfile) * sizeof(uint32_t);
+
+    if(DATA_RANGE_SET && DATA_RANGES[0].start + DATA_RANGES[0].length > (len - headerLen) / sizeof(uint16_t)) {
+
+        printf("The range given to --data-range ends past the end of the program at 0x%.4zX.\n", (len - headerLen) / sizeof(uint16_t));
+        exit(-1);
+
+    }
+
+    if(VERIFY_MODE) {
+
+        uint32_t errorCount = verifyInstructions(data + headerLen, len - headerLen);
+        size_t instructionCount = (len - headerLen) / sizeof(uint32_t);
+
+        if(errorCount) {
+
+            printf("File %s failed verification with %u error(s).\n", binfile, errorCount);
+            exit(-1);
+
+        }
+
+        if(headerLen) printf("File %s is intact (%zu instructions, CRC-32 0x%.8X).\n", binfile, instructionCount, getInstructionAt(data, 3));
+        else printf("File %s is valid (%zu instructions, no header to check the checksum against).\n", binfile, instructionCount);
+
+    } else if(STATS_MODE) printStatistics(binfile, data + headerLen, len - headerLen);
+    else {
+
+        if(symfile) {
+
+            readSymbolFile(symfile);
+            SYMBOLIC_SET = true;
+            // Without real label names there is no telling whether a SET immediate is meant as an address
+
+        }
+
+        createLabels(data + headerLen, len - headerLen);
+        readInstructions(data + headerLen, len - headerLen, txtfile);
+
+    }
+
+    unmapBinaryFile(data, len);
+    free(SYMBOL_TABLE);
+    
+}
+
+uint32_t verifyInstructions(uint8_t* data, size_t len) {
+    // Checks that every instruction can be decoded, has no reserved bits set, and only jumps to an instruction
+    // within the program, printing each problem found
+    // Returns the number of problems
+
+    uint32_t errorCount = 0;
+    size_t programEnd = len / sizeof(uint32_t) * 2;
+
+    for(size_t i = 0; i < len / sizeof(uint32_t); i++) {
+
+        uint32_t instruction = getInstructionAt(data, i);
+        uint8_t opcode = getOpcode(instruction);
+        uint16_t addr = getDestOrImmVal(instruction);
+        uint32_t reservedBits = instruction & getReservedBits(opcode);
+
+        if(!instruction || isDataAddress(i * 2)) continue;
+        // Zero words are the padding left by an .org directive, and data words are not instructions at all
+
+        if((opcode < OP_SET || opcode > OP_HALT) && isEmpty(CType(instruction))) {
+
+            printf("Unknown instruction 0x%.8X at address 0x%.4zX\n", instruction, i * 2);
+            errorCount++;
+            continue;
+
+        }
+
+        if(reservedBits) {
+
+            printf("Reserved bits 0x%.8X are set in instruction 0x%.8X at address 0x%.4zX\n", reservedBits, instruction, i * 2);
+            errorCount++;
+
+        }
+
+        if(isJump(instruction) && (addr % 2 || addr >= programEnd)) {
+
+            printf("Jump target 0x%.4X of instruction 0x%.8X at address 0x%.4zX is %s\n", addr, instruction, i * 2,
+                addr % 2 ? "not aligned to an instruction" : "outside of the program");
+            errorCount++;
+
+        }
+
+    }
+
+    return errorCount;
+
+}
+
+void printStatistics(char* binfile, uint8_t* data, size_t len) {
+    // Prints how many times each instruction format and opcode is used in the program, listing the standard opcodes
+    // even when they are never used, so that missing instructions stand out
+
+    uint32_t opcodeCounts[256] = { 0 };
+    char* opcodeNames[256] = { NULL };
+    uint32_t formatCounts[5] = { 0 };
+    char* formatNames[5] = { "R-Type", "I-Type", "J-Type", "Special", "Custom" };
+    uint32_t instructionCount = 0;
+    uint32_t unknownCount = 0;
+    uint32_t paddingCount = 0;
+
+    NUMERIC_TARGETS = true;
+    // No labels are created, so the jump targets of the decoded instructions are left as addresses
+
+    for(size_t i = 0; i < len / sizeof(uint32_t); i++) {
+
+        uint32_t instruction = getInstructionAt(data, i);
+        uint8_t opcode = getOpcode(instruction);
+
+        if(isDataAddress(i * 2)) continue;
+
+        if(!instruction) {
+
+            paddingCount++;
+            continue;
+
+        }
+        // Zero words are the padding left by an .org directive, which is not counted as an instruction
+
+        char* instructionStr;
+        int format;
+
+        if(isEmpty(instructionStr = RType(instruction)) && isEmpty(instructionStr = IType(instruction))
+            && isEmpty(instructionStr = JType(instruction)) && isEmpty(instructionStr = CType(instruction))) {
+
+            unknownCount++;
+            continue;
+
+        }
+
+        if((opcode >= OP_ADD && opcode <= OP_MODULO) || (opcode >= OP_SHIFT_LEFT && opcode <= OP_NOR)) format = 0;
+        else if((opcode >= OP_ADD_IMM && opcode <= OP_NOR_IMM && opcode != OP_COMPARE_IMM) || opcode == OP_LOAD || opcode == OP_STORE) format = 1;
+        else if(opcode >= OP_JUMP && opcode <= OP_JUMP_LINK) format = 2;
+        else if(opcode >= OP_CUSTOM_MIN) format = 4;
+        else format = 3;
+        // Formats are counted the same way as by the assembler's --stats, where SET, COPY, COMPARE, NOT, and HALT are
+        // special instructions rather than the format they are encoded in
+
+        if(!opcodeNames[opcode]) opcodeNames[opcode] = strndup(instructionStr, strcspn(instructionStr, " "));
+
+        opcodeCounts[opcode]++;
+        formatCounts[format]++;
+        instructionCount++;
+
+    }
+
+    for(int opcode = OP_SET; opcode <= OP_HALT; opcode++) {
+
+        if(opcodeNames[opcode]) continue;
+
+        char* instructionStr = disassembleInstruction(opcode << 24);
+        opcodeNames[opcode] = strndup(instructionStr, strcspn(instructionStr, " "));
+
+    }
+    // The names of unused opcodes are found by decoding an instruction with no operands set
+
+    printf("File %s has %u instructions", binfile, instructionCount);
+    if(paddingCount) printf(", %u words of .org padding", paddingCount);
+    if(unknownCount) printf(", %u unknown instructions", unknownCount);
+    printf("\n\nFormat           Count\n");
+
+    for(int f = 0; f < 5; f++) if(f < 4 || formatCounts[f]) printf("%-16s %5u\n", formatNames[f], formatCounts[f]);
+
+    printf("\nOpcode           Count\n");
+
+    for(int opcode = 0; opcode < 256; opcode++) {
+
+        if(!opcodeNames[opcode] || (opcode > OP_HALT && !opcodeCounts[opcode])) continue;
+
+        printf("%-16s %5u\n", opcodeNames[opcode], opcodeCounts[opcode]);
+        free(opcodeNames[opcode]);
+
+    }
+
+}
+
+void createLabels(uint8_t* data, size_t len) {
+    // Adds a generic label for every jump target which does not have a label yet
+
+    uint16_t functionCount = 0;
+    uint16_t labelCount = 0;
+
+    PROGRAM_END = len / sizeof(uint32_t) * 2;
+
+    for(int pass = 0; pass < 2; pass++) {
+        // JUMP-LINK targets are collected first, so that an address which is both called and jumped to becomes a function
+
+        for(size_t i = 0; i < len / sizeof(uint32_t); i++) {
+
+            if(isDataAddress(i * 2)) continue;
+
+            uint32_t instruction = getInstructionAt(data, i);
+        
+            uint16_t addr = getDestOrImmVal(instruction);
+            bool isCall = getOpcode(instruction) == OP_JUMP_LINK;
+
+            if(isJump(instruction) && isCall == !pass && !NUMERIC_TARGETS && isValidJumpTarget(addr)) {
+        
+
+                if(!labelExists(addr)) {
+
+                    if(isCall) addLabel(generateLabelName("func", functionCount++), addr, true);
+                    else addLabel(generateLabelName("Label", labelCount++), addr, false);
+
+                }
+
+            }
+
+        }
+
+    }
+
+    if(ENTRY_POINT && !labelExists(ENTRY_POINT)) addLabel(generateLabelName("Label", labelCount), ENTRY_POINT, false);
+    // The entry point is written as an .entry directive, which needs a label to refer to
+
+}
+
+void readSymbolFile(char* symfile) {
+    // Loads the labels from a .sym file written by the assembler into the symbol table
+    // The file starts with a "SMIS-SYMBOLS <version>" header line, followed by one "<address> <kind> <line> <name>"
+    // line per label, where the kind is "func" or "label", and lines starting with '#' are comments
+
+    FILE* symFile;
+
+    if(!(symFile = fopen(symfile, "r"))) {
+
+        printf("File %s does not exist.\n", symfile);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    char line[MAX_STRING_LEN];
+    int version = 0;
+    int lineNumber = 1;
+
+    if(!fgets(line, MAX_STRING_LEN, symFile) || sscanf(line, "SMIS-SYMBOLS %i", &version) != 1) {
+
+        printf("File %s is not a symbol file.\n", symfile);
+        exit(-1);
+
+    }
+
+    if(version < 1 || version > SYMBOL_FILE_VERSION) {
+
+        printf("Symbol file %s has unsupported version %i.\n", symfile, version);
+        exit(-1);
+
+    }
+
+    while(fgets(line, MAX_STRING_LEN, symFile)) {
+
+        unsigned int addr;
+        unsigned int sourceLine;
+        char kind[MAX_STRING_LEN];
+        int nameStart = 0;
+
+        lineNumber++;
+        line[strcspn(line, "\n")] = '\0';
+
+        if(!*line || *line == '#') continue;
+
+        if(sscanf(line, "%x %s %u %n", &addr, kind, &sourceLine, &nameStart) != 3 || !nameStart || !line[nameStart] || addr > INT_LIMIT
+            || (strncmp(kind, "func", MAX_STRING_LEN) && strncmp(kind, "label", MAX_STRING_LEN))) {
+
+            printf("Invalid symbol at line %i of %s\n", lineNumber, symfile);
+            printf("Symbol: %s\n", line);
+            exit(-1);
+
+        }
+
+        char* labelName = malloc(MAX_INSTRUCTION_LEN * sizeof(char));
+        snprintf(labelName, MAX_INSTRUCTION_LEN, "%s:", line + nameStart);
+        // Names in the symbol table include their trailing colon
+
+        addLabel(labelName, addr, !strncmp(kind, "func", MAX_STRING_LEN));
+
+    }
+
+    fclose(symFile);
+
+}
+
+void readInstructions(uint8_t* data, size_t len, char* writefile) {
+
+    FILE* txtFile = openOutputFile(writefile, "w");
+
+    if(ENTRY_POINT) {
+
+        char* name = getLabelName(ENTRY_POINT, true);
+        trimLabelColon(name);
+
+        fprintf(txtFile, ".entry %s\n", name);
+        free(name);
+
+    }
+
+    for(size_t i = 0; i < len / sizeof(uint32_t); i++) {
+
+        uint32_t instruction = getInstructionAt(data, i);
+
+        DataRange* range = findDataRange(INSTRUCTION_ADDR);
+
+        if(range) {
+
+            writeDataSection(txtFile, data, range);
+
+            INSTRUCTION_ADDR = (range->start + range->length + 1) & ~1;
+            i = INSTRUCTION_ADDR / 2 - 1;
+            // An odd word out at the end of the data is the padding which fills its last instruction
+
+            if(i + 1 < len / sizeof(uint32_t)) fprintf(txtFile, "\n.text\n");
+            continue;
+
+        }
+
+        if(labelExists(INSTRUCTION_ADDR)) {
+
+            if(INSTRUCTION_ADDR != 0) for(int b = 0; b < BLANK_LINES_BEFORE_LABEL; b++) fputc('\n', txtFile);
+
+            if(FUNCTION_HEADERS && isFunctionStart(INSTRUCTION_ADDR)) {
+
+                char* name = getLabelName(INSTRUCTION_ADDR, true);
+                trimLabelColon(name);
+
+                fprintf(txtFile, "//--------------------------------\n// %s\n//--------------------------------\n", name);
+
+            }
+
+            char** names;
+            uint32_t nameCount = findLabelNames(INSTRUCTION_ADDR, &names);
+
+            for(int n = 0; n < nameCount; n++) fprintf(txtFile, "%s\n", names[n]);
+            // Every label at the address is kept, in the order they were added to the symbol table
+
+            free(names);
+
+            for(int b = 0; b < BLANK_LINES_AFTER_LABEL; b++) fputc('\n', txtFile);
+
+        }
+
+        if(!instruction) {
+
+            size_t padEnd = i + 1;
+            while(padEnd < len / sizeof(uint32_t) && !getInstructionAt(data, padEnd) && !labelExists(padEnd * 2)
+                && !isDataAddress(padEnd * 2)) padEnd++;
+
+            fprintf(txtFile, ".org #0x%.4zX\n", padEnd * 2);
+            // A run of zero words is the padding left by an .org directive, which is not a valid instruction
+
+            INSTRUCTION_ADDR = padEnd * 2;
+            i = padEnd - 1;
+            continue;
+
+        }
+
+        char* instructionStr = formatInstruction(disassembleInstruction(instruction));
+        uint16_t target = getDestOrImmVal(instruction);
+
+        if(isJump(instruction) && !isValidJumpTarget(target)) {
+
+            fprintf(txtFile, "// Warning: the jump target 0x%.4X is %s\n", target,
+                target % 2 ? "not aligned to an instruction" : "past the end of the program");
+
+        }
+        // The address is written as it is, since there is no instruction to put a label in front of
+
+        if(ANNOTATE_MODE) fprintf(txtFile, "0x%.4X  0x%.8X  ", INSTRUCTION_ADDR, instruction);
+
+        char* explanation = EXPLAIN_MODE ? explainInstruction(instruction) : NULL;
+
+        if(explanation) {
+
+            fprintf(txtFile, "%-*s// %s\n", EXPLANATION_COLUMN, instructionStr, explanation);
+            free(explanation);
+
+        } else fprintf(txtFile, "%s\n", instructionStr);
+
+        INSTRUCTION_ADDR += 2;
+
+    }
+
+    if(BSS_LEN) writeBssSection(txtFile);
+    if(XREF_MODE && SYMBOL_COUNT) writeCrossReferences(txtFile, data, len);
+
+    closeOutputFile(txtFile, writefile);
+
+    if(ROUNDTRIP_MODE) return;
+    // Only the result of the comparison is printed during a round trip
+
+    if(!(txtFile = fopen(writefile, "r"))) {
+
+        printf("Cannot read back the written file %s.\n", writefile);
+        exit(-1);
+
+    }
+
+    bool color = COLOR_MODE == COLOR_ALWAYS || (COLOR_MODE == COLOR_AUTO && isatty(STDOUT_FILENO));
+
+    char* instructionStr = malloc(MAX_STRING_LEN * sizeof(char));
+
+    while(fgets(instructionStr, MAX_STRING_LEN, txtFile)) {
+
+        if(color) printColoredLine(instructionStr);
+        else printf("%s", instructionStr);
+
+    }
+
+    free(instructionStr);
+    // TODO: Possible refactor into separate function
+
+    fclose(txtFile);
+
+}
+
+void writeDataSection(FILE* txtFile, uint8_t* data, DataRange* range) {
+    // Writes the data words of the given range as .word directives instead of decoding them as instructions, starting a
+    // new line at each label so that it keeps its address
+    // Runs of text characters are written as .ascii strings instead, so that messages in the data can be read
+    // Data which is followed by more of the program or covered by the .text section (as in a linked program) is pinned
+    // to its address with '.data #<address>', since the assembler would otherwise move it to the end
+
+    int lineWords = 0;
+
+    if(((range->start + range->length + 1) & ~1) < PROGRAM_END || range->start < TEXT_LEN) fprintf(txtFile, "\n.data #0x%.4X\n", range->start);
+    else fprintf(txtFile, "\n.data\n");
+
+    for(uint32_t addr = range->start; addr < range->start + range->length; addr++) {
+
+        if(labelExists(addr)) {
+
+            if(lineWords) fputc('\n', txtFile);
+            lineWords = 0;
+
+            char** names;
+            uint32_t nameCount = findLabelNames(addr, &names);
+
+            for(int n = 0; n < nameCount; n++) fprintf(txtFile, "%s\n", names[n]);
+
+            free(names);
+
+        }
+
+        uint32_t stringLen = getDataStringLength(data, addr, range);
+
+        if(stringLen >= DATA_MIN_STRING_LEN) {
+
+            if(lineWords) fputc('\n', txtFile);
+            lineWords = 0;
+
+            writeDataString(txtFile, data, addr, stringLen);
+
+            addr += stringLen - 1;
+            continue;
+
+        }
+
+        fprintf(txtFile, lineWords ? " #%u" : ".word #%u", getDataWord(data, addr));
+
+        if(++lineWords == DATA_WORDS_PER_LINE) {
+
+            fputc('\n', txtFile);
+            lineWords = 0;
+
+        }
+
+    }
+
+    if(lineWords) fputc('\n', txtFile);
+
+}
+
+uint32_t getDataStringLength(uint8_t* data, uint32_t addr, DataRange* range) {
+    // Returns the number of text characters in a row starting at the given data address, which are printable characters,
+    // newlines, and tabs, stopping at the end of the range or at the next label, or once the string is as long as one
+    // line can hold
+
+    uint32_t len = 0;
+
+    while(len < DATA_MAX_STRING_LEN && addr + len < range->start + range->length && (!len || !labelExists(addr + len))) {
+
+        uint16_t word = getDataWord(data, addr + len);
+
+        if((word < ' ' || word > '~') && word != '\n' && word != '\t') break;
+
+        len++;
+
+    }
+
+    return len;
+
+}
+
+void writeDataString(FILE* txtFile, uint8_t* data, uint32_t addr, uint32_t len) {
+    // Writes a run of text characters as an .ascii directive, after a comment holding its raw words, since the
+    // assembler has no comments at the end of a line
+
+    fprintf(txtFile, "//");
+    for(uint32_t a = addr; a < addr + len; a++) fprintf(txtFile, " #%u", getDataWord(data, a));
+
+    fprintf(txtFile, "\n.ascii \"");
+
+    for(uint32_t a = addr; a < addr + len; a++) {
+
+        uint16_t c = getDataWord(data, a);
+
+        if(c == '\n') fprintf(txtFile, "\\n");
+        else if(c == '\t') fprintf(txtFile, "\\t");
+        else if(c == '"' || c == '\\') fprintf(txtFile, "\\%c", c);
+        else fputc(c, txtFile);
+
+    }
+
+    fprintf(txtFile, "\"\n");
+
+}
+
+uint16_t getDataWord(uint8_t* data, uint32_t addr) {
+    // Gets the data word at the given address, since each instruction slot holds two data words, with the first one
+    // in its upper half
+
+    uint32_t pair = getInstructionAt(data, addr / 2);
+
+    return addr % 2 ? pair & 0xFFFF : pair >> 16;
+
+}
+
+void writeBssSection(FILE* txtFile) {
+    // Writes the .bss section listed in the header, with a .space directive for the words up to each of its labels,
+    // and an .align directive if it does not start right after the program, since that is how it was aligned
+
+    uint32_t last = BSS_START;
+
+    fprintf(txtFile, "\n.bss\n");
+    if(BSS_START > PROGRAM_END) fprintf(txtFile, ".align #%u\n", BSS_START & -BSS_START);
+    // The lowest set bit of the address is an alignment which places the section at the same address again
+
+    for(uint32_t addr = BSS_START; addr < BSS_START + BSS_LEN; addr++) {
+
+        if(!labelExists(addr)) continue;
+
+        if(addr > last) fprintf(txtFile, ".space #%u\n", addr - last);
+
+        char** names;
+        uint32_t nameCount = findLabelNames(addr, &names);
+
+        for(int n = 0; n < nameCount; n++) fprintf(txtFile, "%s\n", names[n]);
+
+        free(names);
+        last = addr;
+
+    }
+
+    fprintf(txtFile, ".space #%u\n", BSS_START + BSS_LEN - last);
+
+}
+
+void writeCrossReferences(FILE* txtFile, uint8_t* data, size_t len) {
+    // Writes a comment line for each label listing the addresses of the jumps to it, found in a second pass over the
+    // instructions, so that the output can still be assembled
+
+    fprintf(txtFile, "\n// Cross references\n");
+
+    for(int l = 0; l < SYMBOL_COUNT; l++) {
+
+        char* name = malloc(MAX_INSTRUCTION_LEN * sizeof(char));
+        uint32_t jumpCount = 0;
+
+        strncpy(name, SYMBOL_TABLE[l].labelName, MAX_INSTRUCTION_LEN);
+        trimLabelColon(name);
+
+        fprintf(txtFile, "// %s:", name);
+
+        for(size_t i = 0; i < len / sizeof(uint32_t); i++) {
+
+            uint32_t instruction = getInstructionAt(data, i);
+
+            if(isDataAddress(i * 2) || !isJump(instruction) || getDestOrImmVal(instruction) != SYMBOL_TABLE[l].PCAddress) continue;
+
+            fprintf(txtFile, "%s 0x%.4zX", jumpCount++ ? "," : "", i * 2);
+
+        }
+
+        fprintf(txtFile, jumpCount ? "\n" : " no jumps\n");
+
+        free(name);
+
+    }
+
+}
+
+void printColoredLine(char* line) {
+    // Prints a line of the written file with each word colored by what kind of token it is, which is worked out from
+    // the finished line so that the file itself never holds any escape codes
+
+    char* comment = strstr(line, "//");
+    size_t codeLen = comment ? comment - line : strlen(line);
+    bool mnemonic = true;
+
+    for(size_t i = 0; i < codeLen;) {
+
+        char* token = line + i;
+        size_t len = strspn(token, " \n");
+
+        if(len) {
+
+            printf("%.*s", (int) (len < codeLen - i ? len : codeLen - i), token);
+            i += len;
+            continue;
+
+        }
+
+        len = strcspn(token, " \n");
+        if(len > codeLen - i) len = codeLen - i;
+
+        char* color;
+
+        if(token[len - 1] == ':') color = ANSI_LABEL;
+        else if(mnemonic && !strncmp(token, "0x", 2)) color = ANSI_COMMENT;
+        // The address and encoding written by --annotate are set apart like comments
+        else if(mnemonic) {
+
+            color = *token == '.' ? ANSI_DIRECTIVE : ANSI_MNEMONIC;
+            mnemonic = false;
+
+        } else if(*token == '#') color = ANSI_IMMEDIATE;
+        else if(isRegisterName(token, len)) color = ANSI_REGISTER;
+        else color = ANSI_LABEL;
+        // Any other operand is the name of a label, such as a jump target or a "&<label>" address
+
+        printf("%s%.*s%s", color, (int) len, token, ANSI_RESET);
+        i += len;
+
+    }
+
+    if(comment) {
+
+        size_t commentLen = strcspn(comment, "\n");
+        printf("%s%.*s%s%s", ANSI_COMMENT, (int) commentLen, comment, ANSI_RESET, comment + commentLen);
+
+    }
+
+}
+
+uint32_t getInstructionAt(uint8_t* data, size_t index) {
+    // Decodes the big-endian instruction with the given index directly from a loaded binary file
+
+    uint32_t instruction;
+    memcpy(&instruction, data + index * sizeof(uint32_t), sizeof(uint32_t));
+
+    return ntohl(instruction);
+
+}
+
+size_t checkBinaryHeader(uint8_t* data, size_t len, char* binfile) {
+    // Verifies the header of a loaded binary file against its contents by decoding the whole file, and takes the entry
+    // point and sections from it, returning the index of the first instruction
+
+    if(!len) return 0;
+
+    FILE* file = fmemopen(data, len, "rb");
+    DecodeStream stream;
+    uint16_t addr;
+    uint32_t instruction;
+    int result = DECODE_ERROR;
+
+    if(openDecodeStream(&stream, file, binfile)) while((result = decodeNext(&stream, &addr, &instruction)) == DECODE_INSTRUCTION);
+
+    fclose(file);
+
+    if(result == DECODE_ERROR) {
+
+        printf("%s\n", stream.error);
+        exit(-1);
+
+    }
+
+    ENTRY_POINT = stream.entryPoint;
+
+    for(uint32_t i = 0; i < stream.sectionCount; i++) {
+
+        DecodeSection section = stream.sections[i];
+
+        if(section.type == SECTION_TEXT) TEXT_LEN = section.length;
+        else if(section.type == SECTION_BSS) {
+
+            BSS_START = section.start;
+            BSS_LEN = section.length;
+
+        } else if(section.type == SECTION_DATA && !DATA_RANGE_SET && section.length) {
+
+            DATA_RANGES = realloc(DATA_RANGES, (DATA_RANGE_COUNT + 1) * sizeof(DataRange));
+            DATA_RANGES[DATA_RANGE_COUNT].start = section.start;
+            DATA_RANGES[DATA_RANGE_COUNT].length = section.length;
+            DATA_RANGE_COUNT++;
+            // Linked programs have a .data section for the data of each object file
+
+        }
+
+    }
+
+    closeDecodeStream(&stream);
+
+    return stream.headerWords;
+
+}
+
+void convertImage(char* infile, char* outfile) {
+    // Converts a memory image between the supported image formats
+
+    size_t len;
+    uint8_t* data = readImage(infile, &len);
+
+    writeImage(outfile, data, len);
+
+    printf("Converted %zu instructions from %s to %s.\n", len / sizeof(uint32_t), infile, outfile);
+
+    free(data);
+
+}
+
+int getImageFormat(char* file) {
+    // Gets the image format of a file from its extension
+
+    if(endsWith(file, ".bin")) return IMAGE_BIN;
+    if(endsWith(file, ".hex")) return IMAGE_INTEL_HEX;
+    if(endsWith(file, ".txt")) return IMAGE_TEXT_HEX;
+    if(endsWith(file, ".memh")) return IMAGE_MEMH;
+
+    printf("File %s does not have a recognized image extension (.bin, .hex, .txt, or .memh).\n", file);
+    printf(USAGE);
+    exit(-1);
+
+}
+
+uint8_t* readImage(char* file, size_t* len) {
+    // Reads a memory image in any of the supported formats
+    // Returns the instructions in the same big-endian layout as a raw binary file, without a header
+
+    int format = getImageFormat(file);
+
+    if(format == IMAGE_BIN) {
+
+        size_t fileLen;
+        uint8_t* fileData;
+
+        if(!(fileData = mapBinaryFile(file, &fileLen))) {
+
+            printf("File %s does not exist.\n", file);
+            printf(USAGE);
+            exit(-1);
+
+        }
+
+        size_t headerLen = checkBinaryHeader(fileData, fileLen, file) * sizeof(uint32_t);
+
+        *len = fileLen - headerLen;
+        uint8_t* data = malloc(*len + 1);
+        memcpy(data, fileData + headerLen, *len);
+
+        unmapBinaryFile(fileData, fileLen);
+
+        return data;
+
+    }
+
+    FILE* imageFile;
+
+    if(!(imageFile = fopen(file, "r"))) {
+
+        printf("File %s does not exist.\n", file);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    uint8_t* data;
+
+    if(format == IMAGE_INTEL_HEX) data = readIntelHex(imageFile, file, len);
+    else data = readHexWords(imageFile, file, format == IMAGE_MEMH ? sizeof(uint16_t) : sizeof(uint32_t), len);
+
+    fclose(imageFile);
+
+    if(*len % sizeof(uint32_t)) {
+
+        printf("File %s does not contain a whole number of instructions.\n", file);
+        exit(-1);
+
+    }
+
+    return data;
+
+}
+
+uint8_t* readIntelHex(FILE* file, char* filename, size_t* len) {
+    // Reads the data records of an Intel HEX file into a memory image, where any gaps between records are zero-filled
+    // Extended segment and extended linear address records are supported, since a full image is larger than 64 KiB
+
+    uint8_t* data = calloc(MAX_IMAGE_LEN, sizeof(uint8_t));
+    char line[MAX_STRING_LEN];
+    uint32_t baseAddr = 0;
+    int lineNumber = 0;
+    bool ended = false;
+
+    *len = 0;
+
+    while(!ended && fgets(line, MAX_STRING_LEN, file)) {
+
+        unsigned int bytes[MAX_STRING_LEN / 2];
+        int byteCount = 0;
+        uint8_t checksum = 0;
+
+        lineNumber++;
+        line[strcspn(line, "\r\n")] = '\0';
+
+        if(isEmpty(line)) continue;
+
+        bool valid = line[0] == ':' && strlen(line) % 2 == 1;
+
+        for(char* byteStr = line + 1; valid && *byteStr; byteStr += 2) {
+
+            valid = isxdigit(byteStr[0]) && isxdigit(byteStr[1]) && sscanf(byteStr, "%2x", &bytes[byteCount]) == 1;
+            if(valid) checksum += bytes[byteCount++];
+
+        }
+
+        // Each record is a byte count, a 16-bit address, a record type, the data, and a checksum which brings the sum of
+        // every byte in the record to zero
+        valid = valid && byteCount >= 5 && bytes[0] == byteCount - 5 && !checksum;
+
+        uint32_t addr = valid ? baseAddr + (bytes[1] << 8 | bytes[2]) : 0;
+        uint8_t type = valid ? bytes[3] : 0xFF;
+
+        if(type == 0x00 && addr + bytes[0] <= MAX_IMAGE_LEN) {
+
+            for(int i = 0; i < bytes[0]; i++) data[addr + i] = bytes[4 + i];
+            if(addr + bytes[0] > *len) *len = addr + bytes[0];
+
+        } else if(type == 0x01) ended = true;
+        else if(type == 0x02 && bytes[0] == 2) baseAddr = (bytes[4] << 8 | bytes[5]) << 4;
+        else if(type == 0x04 && bytes[0] == 2) baseAddr = (bytes[4] << 8 | bytes[5]) << 16;
+        else if(type != 0x03 && type != 0x05) {
+            // Start address records do not affect the image
+
+            printf("Invalid Intel HEX record at line %i of %s\n", lineNumber, filename);
+            printf("Record: %s\n", line);
+            exit(-1);
+
+        }
+
+    }
+
+    if(!ended) {
+
+        printf("File %s is truncated (it has no end-of-file record).\n", filename);
+        exit(-1);
+
+    }
+
+    return data;
+
+}
+
+uint8_t* readHexWords(FILE* file, char* filename, int wordLen, size_t* len) {
+    // Reads a text image of whitespace-separated hex words of the given size in bytes into a memory image
+    // As with Verilog's $readmemh, "@<address>" moves to the given word address and "//" starts a comment
+
+    uint8_t* data = calloc(MAX_IMAGE_LEN, sizeof(uint8_t));
+    char line[MAX_STRING_LEN];
+    uint32_t addr = 0;
+    int lineNumber = 0;
+
+    *len = 0;
+
+    while(fgets(line, MAX_STRING_LEN, file)) {
+
+        lineNumber++;
+
+        char* comment = strstr(line, "//");
+        if(comment) *comment = '\0';
+
+        for(char* word = strtok(line, " \t\r\n"); word; word = strtok(NULL, " \t\r\n")) {
+
+            bool isAddr = *word == '@';
+            char* digits = word + isAddr;
+            char* end;
+            unsigned long val = strtoul(digits, &end, 16);
+
+            if(!*digits || *end || !isxdigit(*digits) || (!isAddr && strlen(digits) > wordLen * 2) || (isAddr && val > MAX_IMAGE_LEN / wordLen)) {
+
+                printf("Invalid hex word at line %i of %s\n", lineNumber, filename);
+                printf("Word: %s\n", word);
+                exit(-1);
+
+            }
+
+            if(isAddr) {
+
+                addr = val * wordLen;
+                continue;
+
+            }
+
+            if(addr + wordLen > MAX_IMAGE_LEN) {
+
+                printf("File %s is larger than the SMIS address space.\n", filename);
+                exit(-1);
+
+            }
+
+            for(int i = 0; i < wordLen; i++) data[addr + i] = val >> ((wordLen - i - 1) * 8);
+
+            addr += wordLen;
+            if(addr > *len) *len = addr;
+
+        }
+
+    }
+
+    return data;
+
+}
+
+void writeImage(char* file, uint8_t* data, size_t len) {
+    // Writes a memory image in the format given by the extension of the output file
+    // Binary files are written with a header, the same as the assembler writes them by default
+
+    int format = getImageFormat(file);
+    FILE* imageFile = openOutputFile(file, format == IMAGE_BIN ? "wb" : "w");
+
+    if(format == IMAGE_BIN) {
+
+        uint32_t sectionCount = 1 + DATA_RANGE_COUNT + (BSS_LEN ? 1 : 0);
+        uint32_t* header = malloc((HEADER_WORDS + sectionCount * SECTION_ENTRY_WORDS) * sizeof(uint32_t));
+        uint32_t* section = header + HEADER_WORDS + SECTION_ENTRY_WORDS;
+
+        header[0] = htonl(HEADER_MAGIC);
+        header[1] = htonl(HEADER_VERSION);
+        header[2] = htonl(len / sizeof(uint32_t));
+        header[3] = htonl(crc32(data, len));
+        header[4] = htonl(ENTRY_POINT);
+        header[5] = htonl(sectionCount);
+        header[6] = htonl(SECTION_TEXT);
+        header[7] = htonl(0);
+        header[8] = htonl(len / sizeof(uint16_t));
+        // Other image formats do not record sections, so the whole image is written as a single text section, along
+        // with the .data and .bss sections if the image was read from a binary which has them
+
+        for(int i = 0; i < DATA_RANGE_COUNT; i++) {
+
+            section[0] = htonl(SECTION_DATA);
+            section[1] = htonl(DATA_RANGES[i].start);
+            section[2] = htonl(DATA_RANGES[i].length);
+            section += SECTION_ENTRY_WORDS;
+
+        }
+
+        if(BSS_LEN) {
+
+            section[0] = htonl(SECTION_BSS);
+            section[1] = htonl(BSS_START);
+            section[2] = htonl(BSS_LEN);
+
+        }
+
+        fwrite(header, sizeof(uint32_t), HEADER_WORDS + sectionCount * SECTION_ENTRY_WORDS, imageFile);
+        fwrite(data, sizeof(uint8_t), len, imageFile);
+
+        free(header);
+
+    } else if(format == IMAGE_INTEL_HEX) {
+
+        for(size_t addr = 0; addr < len; addr += INTEL_HEX_RECORD_LEN) {
+
+            if(!(addr & 0xFFFF)) fprintf(imageFile, ":02000004%.4zX%.2X\n", addr >> 16, (uint8_t) -(0x06 + (addr >> 16)));
+            // An extended linear address record starts each 64 KiB block
+
+            size_t count = len - addr < INTEL_HEX_RECORD_LEN ? len - addr : INTEL_HEX_RECORD_LEN;
+            uint8_t checksum = count + (addr >> 8 & 0xFF) + (addr & 0xFF);
+
+            fprintf(imageFile, ":%.2zX%.4zX00", count, addr & 0xFFFF);
+
+            for(size_t i = 0; i < count; i++) {
+
+                fprintf(imageFile, "%.2X", data[addr + i]);
+                checksum += data[addr + i];
+
+            }
+
+            fprintf(imageFile, "%.2X\n", (uint8_t) -checksum);
+
+        }
+
+        fprintf(imageFile, ":00000001FF\n");
+
+    } else {
+
+        int wordLen = format == IMAGE_MEMH ? sizeof(uint16_t) : sizeof(uint32_t);
+
+        for(size_t addr = 0; addr < len; addr += wordLen) {
+
+            if(wordLen == sizeof(uint16_t)) fprintf(imageFile, "%.2X%.2X\n", data[addr], data[addr + 1]);
+            else fprintf(imageFile, "%.8X\n", getInstructionAt(data, addr / wordLen));
+
+        }
+
+    }
+
+    closeOutputFile(imageFile, file);
+
+}
+
+int diffBinaries(char* binA, char* binB) {
+    // Decodes two binary files and prints every address where their instructions differ, with the encoding and the
+    // disassembled instruction from each file, so that the changed instructions can be found without a hex dump
+    // Returns 0 if the instructions are identical, or 1 if they differ, like diff
+
+    char* files[2] = { binA, binB };
+    uint8_t* data[2];
+    size_t len[2], header[2], count[2];
+    DataRange* dataRanges[2];
+    uint32_t dataRangeCount[2];
+    uint16_t entry[2];
+
+    NUMERIC_TARGETS = true;
+    // Neither file has labels to name the jump targets after, and comparing the addresses themselves is more useful
+
+    for(int f = 0; f < 2; f++) {
+
+        if(!(data[f] = mapBinaryFile(files[f], &len[f]))) {
+
+            printf("File %s does not exist.\n", files[f]);
+            printf(USAGE);
+            exit(-1);
+
+        }
+
+        if(!DATA_RANGE_SET) {
+
+            DATA_RANGES = NULL;
+            DATA_RANGE_COUNT = 0;
+
+        }
+
+        ENTRY_POINT = 0;
+
+        header[f] = checkBinaryHeader(data[f], len[f], files[f]);
+        count[f] = len[f] / sizeof(uint32_t) - header[f];
+
+        dataRanges[f] = DATA_RANGES;
+        dataRangeCount[f] = DATA_RANGE_COUNT;
+        entry[f] = ENTRY_POINT;
+        // The sections and entry point of each file are kept, since reading the next header replaces them
+
+    }
+
+    uint32_t diffCount = 0;
+
+    if(entry[0] != entry[1]) {
+
+        printf("Entry point - 0x%.4X\n            + 0x%.4X\n", entry[0], entry[1]);
+        diffCount++;
+
+    }
+
+    for(size_t i = 0; i < count[0] || i < count[1]; i++) {
+
+        uint32_t words[2] = { 0, 0 };
+
+        for(int f = 0; f < 2; f++) if(i < count[f]) words[f] = getInstructionAt(data[f], header[f] + i);
+
+        if(i < count[0] && i < count[1] && words[0] == words[1]) continue;
+
+        INSTRUCTION_ADDR = i * 2;
+
+        for(int f = 0; f < 2; f++) {
+
+            if(f) printf("       +");
+            else printf("0x%.4zX -", i * 2);
+
+            if(i >= count[f]) {
+
+                printf(" (past the end of the program)\n");
+                continue;
+
+            }
+
+            DATA_RANGES = dataRanges[f];
+            DATA_RANGE_COUNT = dataRangeCount[f];
+
+            printf(" 0x%.8X  %s\n", words[f], describeWord(words[f], i * 2));
+
+        }
+
+        diffCount++;
+
+    }
+
+    if(diffCount) printf("%u difference(s) between %s and %s\n", diffCount, binA, binB);
+    else printf("Files %s and %s have identical instructions.\n", binA, binB);
+
+    for(int f = 0; f < 2; f++) unmapBinaryFile(data[f], len[f]);
+
+    return diffCount ? 1 : 0;
+
+}
+
+char* describeWord(uint32_t word, uint32_t addr) {
+    // Gets the line of code for a word compared by --diff, which is written as data if it lies in the .data section,
+    // and is described in parentheses if it is not an instruction at all
+
+    char* wordStr = malloc(MAX_INSTRUCTION_LEN * sizeof(char));
+    uint8_t opcode = getOpcode(word);
+
+    if(isDataAddress(addr)) snprintf(wordStr, MAX_INSTRUCTION_LEN, ".word #%u #%u", word >> 16, word & 0xFFFF);
+    else if(!word) snprintf(wordStr, MAX_INSTRUCTION_LEN, "(padding left by .org)");
+    else if((opcode < OP_SET || opcode > OP_HALT) && isEmpty(CType(word))) snprintf(wordStr, MAX_INSTRUCTION_LEN, "(unknown instruction)");
+    else return formatInstruction(disassembleInstruction(word));
+
+    return wordStr;
+
+}
+
+int roundTrip(char* txtfile) {
+    // Assembles the given ASM file, disassembles the result, and assembles the disassembly again, reporting whether
+    // the two binaries are identical, which catches instructions the assembler and disassembler disagree on
+    // Both assemblies write a symbol file, and the first one is used for the disassembly, so that the labels of the
+    // two programs can be compared as well
+    // Returns the exit code of the disassembler
+
+    char* firstBin = makeTempFile(".bin");
+    char* firstSym = makeTempFile(".sym");
+    char* asmFile = makeTempFile(".txt");
+    char* secondBin = makeTempFile(".bin");
+    char* secondSym = makeTempFile(".sym");
+    bool identical = false;
+    bool disassembled = false;
+
+    if(runAssembler(txtfile, firstBin, firstSym)) printf("Cannot assemble %s for the round trip.\n", txtfile);
+    else {
+
+        size_t len;
+        uint8_t* data = mapBinaryFile(firstBin, &len);
+        size_t headerLen = checkBinaryHeader(data, len, firstBin) * sizeof(uint32_t);
+
+        SYMBOL_TABLE = NULL;
+
+        readSymbolFile(firstSym);
+        SYMBOLIC_SET = true;
+
+        createLabels(data + headerLen, len - headerLen);
+        readInstructions(data + headerLen, len - headerLen, asmFile);
+
+        unmapBinaryFile(data, len);
+        disassembled = true;
+
+        if(runAssembler(asmFile, secondBin, secondSym)) printf("The disassembly of %s cannot be assembled again.\n", txtfile);
+        else identical = compareBinaries(firstBin, secondBin) && compareSymbols(firstSym, secondSym);
+
+    }
+
+    if(identical) printf("Round trip of %s passed, the reassembled binary and its labels are identical.\n", txtfile);
+    else if(disassembled) printf("Round trip of %s failed, the disassembly is kept at %s.\n", txtfile, asmFile);
+    else printf("Round trip of %s failed.\n", txtfile);
+
+    remove(firstBin);
+    remove(firstSym);
+    remove(secondBin);
+    remove(secondSym);
+    if(identical || !disassembled) remove(asmFile);
+
+    free(SYMBOL_TABLE);
+
+    return identical ? 0 : -1;
+
+}
+
+bool compareBinaries(char* binA, char* binB) {
+    // Compares the binary assembled from the original file with the one assembled from its disassembly, and prints
+    // the first place where they differ
+    // Returns true if the binaries are identical
+
+    size_t lenA, lenB;
+    uint8_t* a = mapBinaryFile(binA, &lenA);
+    uint8_t* b = mapBinaryFile(binB, &lenB);
+
+    size_t headerB = checkBinaryHeader(b, lenB, binB);
+    size_t headerA = checkBinaryHeader(a, lenA, binA);
+    // The header of the original binary is read last, so that its sections are the ones used to decode its words
+
+    size_t countA = lenA / sizeof(uint32_t) - headerA;
+    size_t countB = lenB / sizeof(uint32_t) - headerB;
+    bool identical = false;
+
+    for(size_t i = 0; i < countA && i < countB; i++) {
+
+        uint32_t wordA = getInstructionAt(a, headerA + i);
+        uint32_t wordB = getInstructionAt(b, headerB + i);
+
+        if(wordA == wordB) continue;
+
+        printf("The binaries differ at address 0x%.4zX, where 0x%.8X was reassembled as 0x%.8X\n", i * 2, wordA, wordB);
+        if(!isDataAddress(i * 2)) printf("Disassembled as: %s\n", disassembleInstruction(wordA));
+
+        goto done;
+
+    }
+
+    if(countA != countB) {
+
+        printf("The reassembled binary has %zu instructions instead of %zu\n", countB, countA);
+        goto done;
+
+    }
+
+    for(size_t i = 0; i < headerA || i < headerB; i++) {
+
+        if(i < headerA && i < headerB && getInstructionAt(a, i) == getInstructionAt(b, i)) continue;
+
+        printf("The headers differ at word %zu, which is %s\n", i, i == 4 ? "the entry point" : "part of the section table");
+        goto done;
+
+    }
+    // The instructions are identical at this point, so the checksums are too
+
+    identical = true;
+
+    done:
+
+    unmapBinaryFile(a, lenA);
+    unmapBinaryFile(b, lenB);
+
+    return identical;
+
+}
+
+bool compareSymbols(char* symA, char* symB) {
+    // Checks that every label of the original program has the same address and kind in the program assembled from its
+    // disassembly, and prints the first label which does not
+    // The disassembly may have more labels, since the disassembler adds one for every jump target without a label
+    // Returns true if every label was kept
+
+    FILE* fileA = fopen(symA, "r");
+    FILE* fileB = fopen(symB, "r");
+    char lineA[MAX_STRING_LEN];
+    char lineB[MAX_STRING_LEN];
+    bool kept = true;
+
+    while(kept && fgets(lineA, MAX_STRING_LEN, fileA)) {
+
+        unsigned int addrA, addrB;
+        char kindA[MAX_STRING_LEN], kindB[MAX_STRING_LEN];
+        char nameA[MAX_STRING_LEN], nameB[MAX_STRING_LEN];
+        bool found = false;
+
+        if(sscanf(lineA, "%x %s %*u %s", &addrA, kindA, nameA) != 3) continue;
+        // The header and comment lines do not describe a label
+
+        rewind(fileB);
+
+        while(!found && fgets(lineB, MAX_STRING_LEN, fileB)) {
 
-        printf("Incorrect number of arguments supplied.\n");
-        printf(USAGE);
-        exit(-1);
+            found = sscanf(lineB, "%x %s %*u %s", &addrB, kindB, nameB) == 3 && !strncmp(nameA, nameB, MAX_STRING_LEN);
 
-    }
+        }
 
-    if(!endsWith(argv[1], ".bin") || !endsWith(argv[2], ".txt")) {
+        if(!found) printf("Label %s at address 0x%.4X is missing from the reassembled program\n", nameA, addrA);
+        else if(addrA != addrB) printf("Label %s at address 0x%.4X was reassembled at address 0x%.4X\n", nameA, addrA, addrB);
+        else if(strncmp(kindA, kindB, MAX_STRING_LEN)) printf("Label %s is a %s but was reassembled as a %s\n", nameA, kindA, kindB);
+        else continue;
 
-        printf("One or both of the supplied files have incorrect extensions.\n");
-        printf(USAGE);
-        exit(-1);
+        kept = false;
 
     }
 
-    SYMBOL_TABLE = NULL;
+    fclose(fileA);
+    fclose(fileB);
 
-    createLabels(argv[1]);
-    readInstructions(argv[1], argv[2]);
+    return kept;
 
-    free(SYMBOL_TABLE);
-    
 }
 
-void createLabels(char* readfile) {
+int runAssembler(char* txtfile, char* binfile, char* symfile) {
+    // Runs the assembler on the given files in a child process, and prints everything it printed if it fails
+    // Returns the exit status of the assembler
 
-    FILE* binFile;
+    FILE* log = tmpfile();
 
-    if(!(binFile = fopen(readfile, "rb"))) {
+    fflush(NULL);
+    // Anything still buffered would otherwise be written a second time by the child
 
-        printf("File %s does not exist.\n", readfile);
-        printf(USAGE);
+    pid_t pid = fork();
+
+    if(pid < 0) {
+
+        printf("Cannot start a process to run the assembler.\n");
         exit(-1);
 
     }
 
-    uint32_t instruction;
-    
-    while(fread(&instruction, 4, 1, binFile)) {
+    if(!pid) {
 
-        instruction = ntohl(instruction);
-        
-        uint16_t addr = getDestOrImmVal(instruction);
+        dup2(fileno(log), STDOUT_FILENO);
+        dup2(fileno(log), STDERR_FILENO);
 
-        if(isJump(instruction)) {
-        
+        execlp(ASSEMBLER_PATH, ASSEMBLER_PATH, "--no-lint", "--symbols", symfile, txtfile, binfile, (char*) NULL);
+
+        printf("Cannot run the assembler %s, which can be given with --assembler.\n", ASSEMBLER_PATH);
+        fflush(stdout);
+        _exit(-1);
 
-            if(!labelExists(addr)) {
+    }
 
-                Label l;
-                l.labelName = generateLabelName(SYMBOL_COUNT);
-                l.PCAddress = addr;
+    int status;
+    waitpid(pid, &status, 0);
 
-                SYMBOL_TABLE = realloc(SYMBOL_TABLE, (SYMBOL_COUNT + 1) * sizeof(Label));
+    int code = WIFEXITED(status) ? WEXITSTATUS(status) : -1;
 
-                SYMBOL_TABLE[SYMBOL_COUNT] = l;
-                
-                SYMBOL_COUNT++;
+    if(code) {
 
-            }
+        int c;
 
-        }
+        rewind(log);
+        while((c = fgetc(log)) != EOF) putchar(c);
 
     }
 
-    fclose(binFile);
+    fclose(log);
+
+    return code;
 
 }
 
-void readInstructions(char* readfile, char* writefile) {
+char* findAssembler(char* disassembler) {
+    // Looks for the assembler in the Assembler directory next to the one the disassembler was run from, as laid out
+    // in this repository, or right next to the disassembler, and otherwise leaves it to be found on the PATH
 
-    FILE* binFile;
-    FILE* txtFile;
+    char* path = malloc(MAX_STRING_LEN * sizeof(char));
+    char* slash = strrchr(disassembler, '/');
 
-    if(!(binFile = fopen(readfile, "rb"))) {
+    if(!slash) return "smisasm";
 
-        printf("File %s does not exist.\n", readfile);
-        printf(USAGE);
-        exit(-1);
+    int dirLen = slash - disassembler;
 
-    }
+    snprintf(path, MAX_STRING_LEN, "%.*s/../Assembler/smisasm", dirLen, disassembler);
+    if(!access(path, X_OK)) return path;
 
-    if(!(txtFile = fopen(writefile, "w"))) {
+    snprintf(path, MAX_STRING_LEN, "%.*s/smisasm", dirLen, disassembler);
+    if(!access(path, X_OK)) return path;
 
-        printf("File %s does not exist.\n", writefile);
-        printf(USAGE);
-        exit(-1);
+    free(path);
 
-    }
+    return "smisasm";
 
-    uint32_t instruction;
-    
-    while(fread(&instruction, 4, 1, binFile)) {
+}
 
-        instruction = ntohl(instruction);
+char* makeTempFile(char* extension) {
+    // Creates an empty file with a unique name and the given extension in the temporary directory
 
-        if(labelExists(INSTRUCTION_ADDR)) {
+    char* dir = getenv("TMPDIR");
+    char* path = malloc(MAX_STRING_LEN * sizeof(char));
 
-            if(INSTRUCTION_ADDR != 0) fputc('\n', txtFile);
-            fprintf(txtFile, "%s\n", getLabelName(INSTRUCTION_ADDR));
+    snprintf(path, MAX_STRING_LEN, "%s/smis-roundtrip-XXXXXX%s", dir && *dir ? dir : "/tmp", extension);
 
-        }
+    int fd = mkstemps(path, strlen(extension));
 
-        fprintf(txtFile, "%s\n", disassembleInstruction(instruction));
+    if(fd < 0) {
 
-        INSTRUCTION_ADDR += 2;
+        printf("Cannot create a temporary file for the round trip.\n");
+        exit(-1);
 
     }
 
-    freopen(writefile, "r", txtFile);
-
-    char* instructionStr = malloc(MAX_INSTRUCTION_LEN * sizeof(char));
-    while(fgets(instructionStr, MAX_INSTRUCTION_LEN, txtFile)) printf("%s", instructionStr);
-    free(instructionStr);
-    // TODO: Possible refactor into separate function
+    close(fd);
 
-    fclose(binFile);
-    fclose(txtFile);
+    return path;
 
 }
 
@@ -261,13 +1969,24 @@ char* disassembleInstruction(uint32_t instruction) {
 
     char* instructionStr = malloc(MAX_INSTRUCTION_LEN * sizeof(char));
 
+    uint32_t reservedBits = instruction & getReservedBits(getOpcode(instruction));
+
+    if(STRICT_MODE && reservedBits) {
+
+        printf("Reserved bits 0x%.8X are set in instruction 0x%.8X at address 0x%.4X\n", reservedBits, instruction, INSTRUCTION_ADDR);
+        exit(-1);
+
+    }
+
     char* rStr = RType(instruction);
     char* iStr = IType(instruction);
     char* jStr = JType(instruction);
+    char* cStr = CType(instruction);
 
     if(!isEmpty(rStr)) instructionStr = rStr;
     else if(!isEmpty(iStr)) instructionStr = iStr;
     else if(!isEmpty(jStr)) instructionStr = jStr;
+    else if(!isEmpty(cStr)) instructionStr = cStr;
     else {
 
         printf("Unknown instruction 0x%.8X at instruction number %i\n", instruction, INSTRUCTION_NUMBER);
@@ -419,7 +2138,29 @@ char* IType(uint32_t instruction) {
 
     }
 
-    if(amountOfRegOperands == 1) {
+    uint16_t imm = getDestOrImmVal(instruction);
+
+    bool absolute = (opcode == OP_LOAD || opcode == OP_STORE) && getRegOperand(instruction, 2) == 0;
+    // A LOAD or STORE relative to RZR uses its immediate as an address
+    bool inProgram = imm < PROGRAM_END || (imm >= BSS_START && imm < BSS_START + BSS_LEN);
+    // Labels in the .bss section are written back out as well, even though the section is not part of the file
+
+    if(amountOfRegOperands == 1 && SYMBOLIC_SET && inProgram && labelExists(imm)) {
+
+        char* lblStr = getLabelName(imm, true);
+        trimLabelColon(lblStr);
+
+        snprintf(instructionStr, MAX_INSTRUCTION_LEN, "%s %s &%s", opStr, formatRegNum(getRegOperand(instruction, 1)), lblStr);
+
+    } else if(absolute && SYMBOLIC_SET && inProgram && labelExists(imm)) {
+
+        char* lblStr = getLabelName(imm, true);
+        trimLabelColon(lblStr);
+
+        snprintf(instructionStr, MAX_INSTRUCTION_LEN, "%s %s %s &%s", opStr,
+        formatRegNum(getRegOperand(instruction, 1)), formatRegNum(getRegOperand(instruction, 2)), lblStr);
+
+    } else if(amountOfRegOperands == 1) {
 
         snprintf(instructionStr, MAX_INSTRUCTION_LEN, "%s %s %s", opStr,
         formatRegNum(getRegOperand(instruction, 1)),
@@ -466,15 +2207,127 @@ char* JType(uint32_t instruction) {
 
     }
 
-    char* lblStr = getLabelName(getDestOrImmVal(instruction));
-    trimLabelColon(lblStr);
-
-    snprintf(instructionStr, MAX_INSTRUCTION_LEN, "%s %s", opStr, lblStr);
+    snprintf(instructionStr, MAX_INSTRUCTION_LEN, "%s %s", opStr, formatJumpTarget(getDestOrImmVal(instruction), opcode == OP_JUMP_LINK));
 
     return instructionStr;
 
 }
 
+char* CType(uint32_t instruction) {
+    // Converts an instruction registered through the custom opcode extension mechanism to a string
+    // If the given instruction is not a registered custom instruction, returns an empty string
+
+    uint8_t opcode = getOpcode(instruction);
+
+    for(int i = 0; i < CUSTOM_OPCODE_COUNT; i++) {
+
+        if(CUSTOM_OPCODES[i].opcode == opcode) return CUSTOM_OPCODES[i].disassemble(instruction);
+
+    }
+
+    return "";
+
+}
+
+void registerOpcode(uint8_t opcode, char* (*disassemble)(uint32_t instruction)) {
+    // Claims an unused opcode for a custom instruction, which is disassembled by the given handler
+    // Extensions call this from registerExtensions(), which is linked in alongside the disassembler
+
+    if(opcode < OP_CUSTOM_MIN) {
+
+        printf("Internal error: cannot register opcode %i, which is reserved for built-in instructions\n", opcode);
+        exit(-2);
+
+    }
+
+    for(int i = 0; i < CUSTOM_OPCODE_COUNT; i++) {
+
+        if(CUSTOM_OPCODES[i].opcode == opcode) {
+
+            printf("Internal error: cannot register opcode %i, which has already been registered\n", opcode);
+            exit(-2);
+
+        }
+
+    }
+
+    CustomOpcode c;
+    c.opcode = opcode;
+    c.disassemble = disassemble;
+
+    CUSTOM_OPCODES = realloc(CUSTOM_OPCODES, (CUSTOM_OPCODE_COUNT + 1) * sizeof(CustomOpcode));
+
+    CUSTOM_OPCODES[CUSTOM_OPCODE_COUNT] = c;
+
+    CUSTOM_OPCODE_COUNT++;
+
+}
+
+char* explainInstruction(uint32_t instruction) {
+    // Describes what a given instruction does in plain English, using the opcode's explanation template
+    // Returns NULL if there is no explanation for the opcode, and otherwise a string which has to be freed
+
+    uint8_t opcode = getOpcode(instruction);
+
+    if(opcode > OP_HALT || !OPCODE_EXPLANATIONS[opcode]) return NULL;
+
+    char* explanation = malloc(MAX_STRING_LEN * sizeof(char));
+    *explanation = '\0';
+
+    for(char* c = OPCODE_EXPLANATIONS[opcode]; *c; c++) {
+
+        char* part = NULL;
+        char single[2] = { *c, '\0' };
+
+        if(*c == '{' && c[1] && c[2] == '}') {
+
+            switch(c[1]) {
+
+                case 'd': part = formatRegNum(getRegOperand(instruction, 1)); break;
+                case 'a': part = formatRegNum(getRegOperand(instruction, 2)); break;
+                case 'b': part = formatRegNum(getRegOperand(instruction, 3)); break;
+                case 'i': part = formatImmediateVal(getDestOrImmVal(instruction)) + 1; break;
+                case 'l':
+                    part = formatJumpTarget(getDestOrImmVal(instruction), true);
+                    if(*part == '#') part++;
+                    break;
+
+            }
+
+        }
+
+        if(part) c += 2;
+        else part = single;
+
+        strncat(explanation, part, MAX_STRING_LEN - strnlen(explanation, MAX_STRING_LEN) - 1);
+
+    }
+
+    return explanation;
+
+}
+
+char* formatInstruction(char* instructionStr) {
+    // Applies the formatting options (indentation, operand alignment, and mnemonic case) to a line of code
+
+    char* formatted = malloc(MAX_STRING_LEN * sizeof(char));
+    char* operands = strchr(instructionStr, ' ');
+    int mnemonicLen = operands ? operands - instructionStr : strnlen(instructionStr, MAX_INSTRUCTION_LEN);
+    int len = snprintf(formatted, MAX_STRING_LEN, "%*s%.*s", INDENT_WIDTH, "", mnemonicLen, instructionStr);
+
+    if(LOWERCASE_MNEMONICS) for(int i = INDENT_WIDTH; i < len; i++) formatted[i] = tolower(formatted[i]);
+
+    if(operands) {
+
+        int padding = OPERAND_COLUMN > len ? OPERAND_COLUMN - len : 1;
+        snprintf(formatted + len, MAX_STRING_LEN - len, "%*s%s", padding, "", operands + 1);
+
+    }
+
+    return formatted;
+
+}
+
 char* formatRegNum(uint16_t regNum) {
     // Translates a register from numerical form to string form
 
@@ -527,6 +2380,26 @@ bool labelExists(uint16_t addr) {
 
 }
 
+bool isDataAddress(uint32_t addr) {
+    // Returns true if the word at the given address belongs to the data of the program rather than its instructions
+
+    return findDataRange(addr);
+
+}
+
+DataRange* findDataRange(uint32_t addr) {
+    // Returns the range of data words which the given address belongs to, or NULL if it holds an instruction
+
+    for(int i = 0; i < DATA_RANGE_COUNT; i++) {
+
+        if(addr >= DATA_RANGES[i].start && addr < DATA_RANGES[i].start + DATA_RANGES[i].length) return &DATA_RANGES[i];
+
+    }
+
+    return NULL;
+
+}
+
 uint8_t getOpcode(uint32_t instruction) {
     // Gets the opcode of a given instruction
 
@@ -558,21 +2431,27 @@ uint16_t getDestOrImmVal(uint32_t instruction) {
 
 }
 
-char* getLabelName(uint16_t addr) {
-    // Gets the label name associated with a given address
+char* getLabelName(uint16_t addr, bool isCall) {
+    // Gets the label name associated with a given address, which is used wherever the address is jumped to
+    // If several labels share the address, the first function label is chosen for a call and the first other label
+    // for anything else, or otherwise the first label added
+
+    Label* chosen = NULL;
 
     for(int i = 0; i < SYMBOL_COUNT; i++) {
 
-        Label l = SYMBOL_TABLE[i];
+        Label* l = &SYMBOL_TABLE[i];
 
-        if(addr == l.PCAddress) {
-            
-            char* lblName = malloc(MAX_INSTRUCTION_LEN * sizeof(char));
-            strncpy(lblName, l.labelName, MAX_INSTRUCTION_LEN);
-            
-            return lblName;
-        
-        }
+        if(addr == l->PCAddress && (!chosen || (l->isFunction == isCall && chosen->isFunction != isCall))) chosen = l;
+
+    }
+
+    if(chosen) {
+
+        char* lblName = malloc(MAX_INSTRUCTION_LEN * sizeof(char));
+        strncpy(lblName, chosen->labelName, MAX_INSTRUCTION_LEN);
+
+        return lblName;
 
     }
 
@@ -581,16 +2460,76 @@ char* getLabelName(uint16_t addr) {
 
 }
 
-char* generateLabelName(uint16_t labelNum) {
-    // Generates a generic label name with a given number
+char* formatJumpTarget(uint16_t addr, bool isCall) {
+    // Gets the operand of a jump to the given address, which is the name of its label, or the address itself with
+    // --numeric-targets or if there is no instruction at the address to put a label in front of
+
+    if(NUMERIC_TARGETS || !isValidJumpTarget(addr)) {
+
+        char* addrStr = malloc(MAX_INSTRUCTION_LEN * sizeof(char));
+        snprintf(addrStr, MAX_INSTRUCTION_LEN, "#0x%.4X", addr);
+
+        return addrStr;
+
+    }
+
+    char* lblStr = getLabelName(addr, isCall);
+    trimLabelColon(lblStr);
+
+    return lblStr;
+
+}
+
+uint32_t findLabelNames(uint16_t addr, char*** names) {
+    // Gets all label names associated with a given address, in the order they were added to the symbol table
+    // Returns the number of names, which are placed in a newly allocated array
+
+    uint32_t count = 0;
+
+    *names = malloc((SYMBOL_COUNT + 1) * sizeof(char*));
+
+    for(int i = 0; i < SYMBOL_COUNT; i++) if(SYMBOL_TABLE[i].PCAddress == addr) (*names)[count++] = SYMBOL_TABLE[i].labelName;
+
+    return count;
+
+}
+
+void addLabel(char* labelName, uint16_t addr, bool isFunction) {
+    // Adds a label to the symbol table, where the name must include its trailing colon
+    // Any number of labels may share the same address
+
+    Label l;
+    l.labelName = labelName;
+    l.PCAddress = addr;
+    l.isFunction = isFunction;
+
+    SYMBOL_TABLE = realloc(SYMBOL_TABLE, (SYMBOL_COUNT + 1) * sizeof(Label));
+
+    SYMBOL_TABLE[SYMBOL_COUNT] = l;
+
+    SYMBOL_COUNT++;
+
+}
+
+char* generateLabelName(char* prefix, uint16_t labelNum) {
+    // Generates a generic label name with a given prefix and number
 
     char* name = malloc(14 * sizeof(char));
-    snprintf(name, 14, "Label_%i:", labelNum);
+    snprintf(name, 14, "%s_%i:", prefix, labelNum);
 
     return name;
 
 }
 
+bool isFunctionStart(uint16_t addr) {
+    // Returns true if the label at a given address is the target of a JUMP-LINK
+
+    for(int i = 0; i < SYMBOL_COUNT; i++) if(SYMBOL_TABLE[i].PCAddress == addr && SYMBOL_TABLE[i].isFunction) return true;
+
+    return false;
+
+}
+
 bool isJump(uint32_t instruction) {
     // Returns true if a given instruction is J-Type
 
@@ -600,6 +2539,62 @@ bool isJump(uint32_t instruction) {
 
 }
 
+bool isRegisterName(char* str, size_t len) {
+    // Returns true if the first len characters of the given string are a register name as written by formatRegNum()
+
+    if(len == 3 && (!strncmp(str, "RZR", 3) || !strncmp(str, "RSP", 3) || !strncmp(str, "RBP", 3) || !strncmp(str, "RLR", 3))) return true;
+
+    if(len < 2 || len > 3 || *str != 'R') return false;
+
+    for(size_t i = 1; i < len; i++) if(!isdigit(str[i])) return false;
+
+    return true;
+
+}
+
+bool isValidJumpTarget(uint16_t addr) {
+    // Returns true if the given address is the start of an instruction within the program, or at least of a
+    // label from the symbol file
+
+    return (addr % 2 == 0 && addr < PROGRAM_END) || labelExists(addr);
+
+}
+
+uint32_t getReservedBits(uint8_t opcode) {
+    // Gets the mask of encoding bits which are unused by the given opcode, and therefore must be zero
+
+    switch(opcode) {
+
+        case OP_COPY:
+        case OP_NOT:
+            return 0x0000FFFF;
+
+        case OP_COMPARE:
+            return 0x00F00FFF;
+
+        case OP_ADD: case OP_SUBTRACT: case OP_MULTIPLY: case OP_DIVIDE: case OP_MODULO:
+        case OP_SHIFT_LEFT: case OP_SHIFT_RIGHT:
+        case OP_AND: case OP_OR: case OP_XOR: case OP_NAND: case OP_NOR:
+            return 0x00000FFF;
+
+        case OP_SET:
+            return 0x000F0000;
+
+        case OP_COMPARE_IMM:
+            return 0x00F00000;
+
+        case OP_JUMP: case OP_JUMP_IF_ZERO: case OP_JUMP_IF_NOTZERO: case OP_JUMP_LINK:
+            return 0x00FF0000;
+
+        case OP_HALT:
+            return 0x00FFFFFF;
+
+    }
+
+    return 0;
+
+}
+
 bool isEmpty(char* str) {
     // Checks if a given string is empty (starts with null terminator)
 
@@ -607,6 +2602,35 @@ bool isEmpty(char* str) {
 
 }
 
+int parseNumberArg(char** argv, int argc, int* i) {
+    // Parses the number following a formatting option, advancing past it
+
+    char* end;
+
+    if(*i + 1 >= argc) {
+
+        printf("No value supplied for %s.\n", argv[*i]);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    char* option = argv[*i];
+    char* value = argv[++(*i)];
+    long num = strtol(value, &end, 10);
+
+    if(*end || end == value || num < 0 || num > MAX_FORMAT_WIDTH) {
+
+        printf("Value %s for %s must be a number from 0 to %i.\n", value, option, MAX_FORMAT_WIDTH);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    return num;
+
+}
+
 bool endsWith(char* str, char* substr) {
     // Checks if a given string ends with a given substring
 
@@ -653,4 +2677,67 @@ void trimChar(char* str, char c) {
 
     }
 
-}
\ No newline at end of file
+}
+
+FILE* openOutputFile(char* file, char* mode) {
+    // Opens a temporary file next to the given output file, which only takes its place once closeOutputFile() is
+    // called, so that an error part of the way through never leaves a half-written output file behind
+
+    char* tmpfile = malloc(MAX_STRING_LEN * sizeof(char));
+    snprintf(tmpfile, MAX_STRING_LEN, "%s.%i.tmp", file, (int) getpid());
+
+    FILE* out;
+
+    if(!(out = fopen(tmpfile, mode))) {
+
+        printf("Cannot output to file %s.\n", file);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    UNFINISHED_OUTPUT = tmpfile;
+
+    return out;
+
+}
+
+void closeOutputFile(FILE* out, char* file) {
+    // Closes an output file opened by openOutputFile(), and renames the temporary file to replace the output file
+
+    bool failed = ferror(out);
+
+    if(fclose(out) || failed || rename(UNFINISHED_OUTPUT, file)) {
+
+        printf("Cannot output to file %s.\n", file);
+        exit(-1);
+
+    }
+
+    free(UNFINISHED_OUTPUT);
+    UNFINISHED_OUTPUT = NULL;
+
+}
+
+void removeUnfinishedOutput() {
+    // Removes the temporary file of an output file which was not finished, when the program stops with an error
+
+    if(UNFINISHED_OUTPUT) remove(UNFINISHED_OUTPUT);
+
+}
+
+void checkNotInputFile(char* output, char* input) {
+    // Terminates the program if an output file is the same file as an input file, which would overwrite the input
+    // Both paths are compared by the file they point to, so different spellings of the same path are caught as well
+
+    struct stat outputStat;
+    struct stat inputStat;
+
+    if(stat(output, &outputStat) || stat(input, &inputStat)) return;
+    if(outputStat.st_dev != inputStat.st_dev || outputStat.st_ino != inputStat.st_ino) return;
+
+    printf("Output file %s is the same file as input file %s, which would be overwritten.\n", output, input);
+    printf(USAGE);
+    exit(-1);
+
+}
@@ -1,13 +1,86 @@
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
+#include <strings.h>
 #include <stdint.h>
 #include <stdbool.h>
+#include <stdarg.h>
+#include <limits.h>
 #include <arpa/inet.h>
-
-
-#define USAGE "Usage: ./smisem <executable .bin file>\n"
+#include <netdb.h>
+#include <unistd.h>
+#include <sys/socket.h>
+#include <sys/stat.h>
+#include <sys/wait.h>
+#include <dirent.h>
+#include <errno.h>
+#include <time.h>
+#include <pthread.h>
+
+#include "../Common/smisdecode.h"
+// Binary decoder shared with the disassembler, which memory-maps input binaries and decodes them from the mapping
+// when built with -DSMIS_MMAP
+
+#ifdef SMIS_DISPLAY
+#include <SDL2/SDL.h>
+#endif
+// Build with -DSMIS_DISPLAY (and link with -lSDL2) to enable the --display window
+
+#ifdef SMIS_LUA
+#include <lua.h>
+#include <lauxlib.h>
+#include <lualib.h>
+#endif
+// Build with -DSMIS_LUA (and link with -llua) to enable --script
+
+
+#define USAGE "Usage: ./smisem [options] <executable .bin file>\n" \
+    "       ./smisem [options] --grade <spec .json file> [--grade-report <report .json file>]\n" \
+    "       ./smisem --trace-diff <trace .json file> <trace .json file>\n" \
+    "       ./smisem --inspect-dump <crash dump file> [--inspect-start <addr>] [--inspect-words <n>]\n" \
+    "Options:\n" \
+    "  --serial <[host]:port>       Serve the console over a TCP connection\n" \
+    "  --async-input                Read console input in the background so programs can poll for it\n" \
+    "  --events                     Print execution events to stderr\n" \
+    "  --opcode-stats               Print how often each opcode was executed\n" \
+    "  --dump-registers             Print the registers, flags, and PC once the program halts\n" \
+    "  --dump-memory <file>         Write the final memory to a file once the program halts, as hex if it ends in .hex\n" \
+    "  --dump-start <addr>          First address written by --dump-memory (default 0)\n" \
+    "  --dump-words <n>             Number of words written by --dump-memory (default all of memory)\n" \
+    "  --dual-core                  Run the program on two cores sharing memory\n" \
+    "  --strict                     Reject instructions with reserved bits set\n" \
+    "  --force                      Accept an executable file with any extension\n" \
+    "  --reference <trace .json>    Compare execution against a reference trace\n" \
+    "  --trace <trace .json>        Record every executed instruction in a trace\n" \
+    "  --trace-format <json|jsonl>  Write the trace as a JSON array (default), or as one line per instruction\n" \
+    "  --stack-top <addr>           Initialize RSP and RBP to this address (default 0xFF00)\n" \
+    "  --stack-size <words>         Reserve this many words below the stack top (default 0x1000)\n" \
+    "  --stack-poison <pattern>     Fill the stack region with a 16-bit pattern\n" \
+    "  --warn-self-modify           Warn when the program overwrites its own instructions\n" \
+    "  --checkpoint-every <n>       Save the emulator state every n instructions\n" \
+    "  --checkpoint-dir <dir>       Directory for checkpoints (default .)\n" \
+    "  --resume                     Continue from the latest checkpoint in the checkpoint directory\n" \
+    "  --crash-dump <file>          Save the registers and memory to a crash dump on runtime errors\n" \
+    "  --symbols <.sym file>        Show label names from the assembler's symbol file in errors and events\n" \
+    "  --debug-info <.dbg file>     Show the source line of the failing instruction in errors and crash dumps\n" \
+    "  --display                    Show the framebuffer at 0xFD00 in a window (needs -DSMIS_DISPLAY)\n" \
+    "  --script <.lua file>         Run the hooks defined by a Lua script during execution (needs -DSMIS_LUA)\n" \
+    "  --debug                      Start paused at a debugger prompt, which reads commands from stdin\n" \
+    "  --step                       Print the next instruction and the registers, and wait for Enter before each step\n"
 #define MAX_STRING_LEN 500
+#define MEMORY_SIZE 0x10000
+// Memory spans the entire 16-bit address space
+#define DUMP_WORDS_PER_ROW 8
+#define DUMP_REGISTERS_PER_ROW 4
+#define INVALID_REGISTER 0xFF
+// Returned by parseRegisterName() for names which are not registers
+
+#define BREAK_INSTRUCTION   0
+#define WATCH_WRITE         1
+#define WATCH_READ          2
+#define WATCH_ACCESS        3
+#define WATCH_REGISTER      4
+// Kinds of debugger breakpoints, where the kinds of memory watchpoints are flags of the accesses they stop on
 
 #define MEM MEMORY
 #define REG REGISTERS
@@ -22,6 +95,32 @@
 #define ZF ZERO_FLAG
 #define SF SIGN_FLAG
 
+#define REGISTERS CORE->registers
+#define PROGRAM_COUNTER CORE->programCounter
+#define INSTRUCTION_REGISTER CORE->instructionRegister
+#define ZERO_FLAG CORE->zeroFlag
+#define SIGN_FLAG CORE->signFlag
+// All architectural state except memory belongs to the core which is currently executing
+
+#define MAX_CORES 2
+
+#define DEFAULT_STACK_SIZE 0x1000
+
+#define CHECKPOINT_MAGIC    0x534D434B
+// "SMCK" in ASCII, marks an emulator checkpoint file
+#define CHECKPOINT_VERSION  1
+#define CHECKPOINTS_KEPT    3
+// Older checkpoints are deleted, so that only the most recent ones are kept
+
+#define CRASH_DUMP_MAGIC    0x534D4344
+// "SMCD" in ASCII, marks a crash dump file
+#define CRASH_DUMP_VERSION  1
+
+#define SYMBOL_FILE_VERSION 1
+// Version of the .sym format written by the assembler with --symbols
+#define DEBUG_FILE_VERSION  1
+// Version of the .dbg format written by the assembler with --debug-info
+
 #define OP_SET              1
 #define OP_COPY             2
 
@@ -66,196 +165,3710 @@
 #define OP_JUMP_IF_NOTZERO  34
 #define OP_JUMP_LINK        35
 
-#define OP_HALT             36
+#define OP_HALT             36
+
+#define OP_CUSTOM_MIN       37
+// Opcodes from here up to 255 may be claimed by custom instructions (see registerOpcode())
+
+#define MMIO_BASE           0xFF00
+// Addresses at or above this point are reserved for memory-mapped devices
+#define CONSOLE_DATA        0xFF00
+// Storing to this address prints a character to the console, loading from it reads one (0xFFFF on end of input)
+#define CONSOLE_STATUS      0xFF01
+// Loading from this address gives the number of input characters waiting to be read (0xFFFF on end of input)
+#define CORE_ID             0xFF10
+// Loading from this address gives the number of the executing core
+#define TEST_AND_SET        0xFF11
+// Loading from this address returns its value and sets it to 1 in one step, storing 0 to it releases it
+
+#define DISPLAY_BASE        0xFD00
+// Framebuffer of the display, where each row of pixels is 8 words and the highest bit of a word is its leftmost pixel
+#define DISPLAY_WIDTH       128
+#define DISPLAY_HEIGHT      64
+#define DISPLAY_SCALE       6
+// Each emulated pixel is drawn as a square of this many screen pixels
+
+#define CONSOLE_BUFFER_SIZE 4096
+#define INPUT_QUEUE_SIZE 4096
+// Largest number of characters which --async-input reads ahead of the program
+#define CONSOLE_FLUSH_INTERVAL_MS 50
+// Console output is buffered, and flushed on newlines, input requests, HALT, and after this many milliseconds
+#define DISPLAY_REFRESH_INTERVAL_MS 16
+// The display is redrawn at most this many milliseconds apart, which is about 60 times a second
+
+
+typedef enum EventType {
+
+    EVENT_INSTRUCTION_EXECUTED,
+    EVENT_MEMORY_WRITE,
+    EVENT_MEMORY_READ,
+    EVENT_REGISTER_WRITE,
+    EVENT_JUMP,
+    EVENT_HALT,
+    EVENT_DEVICE_IO
+
+} EventType;
+
+typedef struct Event {
+
+    EventType type;
+    uint16_t PCAddress;
+    // Address of the instruction which caused the event
+    uint8_t core;
+    uint32_t instruction;
+    uint16_t target;
+    // Memory address, register number, jump destination, or device address, depending on the event type
+    uint16_t oldVal;
+    uint16_t newVal;
+
+} Event;
+
+typedef void (*EventHandler)(Event* e);
+
+typedef struct Core {
+
+    uint16_t registers[0x10];
+    uint16_t programCounter;
+    uint32_t instructionRegister;
+    bool zeroFlag;
+    bool signFlag;
+    bool halted;
+
+} Core;
+
+typedef struct CustomOpcode {
+
+    uint8_t opcode;
+    void (*execute)(uint32_t instruction);
+
+} CustomOpcode;
+
+typedef struct Symbol {
+
+    char* name;
+    uint16_t PCAddress;
+
+} Symbol;
+
+typedef struct SourceLine {
+
+    uint16_t PCAddress;
+    char* fileName;
+    uint32_t lineNumber;
+    char* text;
+
+} SourceLine;
+
+typedef struct Breakpoint {
+
+    uint32_t number;
+    // Number which the debugger commands refer to the breakpoint by, which stays the same when others are deleted
+    uint8_t kind;
+    uint16_t addr;
+    uint32_t length;
+    // Address of the instruction to stop before, the range of memory words a watchpoint stops on accesses to, or the
+    // number of the register a watchpoint stops on changes to
+    bool enabled;
+
+} Breakpoint;
+
+typedef enum HaltReason {
+
+    RUN_HALTED,
+    // Every core reached a HALT instruction
+    RUN_STOPPED,
+    // Every core stopped on an all-zero instruction word without reaching a HALT instruction
+    RUN_LIMIT_REACHED,
+    // The program did not halt within its instruction limit
+    RUN_ERROR,
+    // The emulator stopped with a runtime error, such as a division by zero
+    RUN_CRASHED
+
+} HaltReason;
+
+typedef struct RunResult {
+
+    HaltReason haltReason;
+    uint16_t registers[0x10];
+    uint16_t programCounter;
+    // Final state of the first core
+    uint16_t* memory;
+    // Final contents of memory, or NULL if the run ended with an error or crash
+    uint64_t instructionCount;
+    char* output;
+    // Everything the program wrote to the console
+    char* error;
+    // First line of the runtime error, or a description of the crash
+
+} RunResult;
+
+typedef enum JsonType {
+
+    JSON_NULL,
+    JSON_BOOL,
+    JSON_NUMBER,
+    JSON_STRING,
+    JSON_ARRAY,
+    JSON_OBJECT
+
+} JsonType;
+
+typedef struct JsonValue {
+
+    JsonType type;
+    double number;
+    // Also holds the value of booleans
+    char* string;
+    struct JsonValue* items;
+    char** keys;
+    // Arrays and objects store their elements in items, and objects also store the matching keys
+    uint32_t count;
+
+} JsonValue;
+
+
+uint16_t* MEMORY;
+// Memory is allocated on the heap when the program is loaded
+Core CORES[MAX_CORES];
+// Stores the registers, PC, and flags of each core
+Core* CORE = &CORES[0];
+// The core which is currently executing
+uint8_t CORE_COUNT = 1;
+// Cores are stepped one instruction at a time in round-robin order
+
+bool STRICT_MODE = false;
+// Rejects instructions which have any of their reserved encoding bits set
+bool FORCE_EXTENSIONS = false;
+// Accepts an executable file with any extension instead of requiring .bin
+
+uint16_t TEST_AND_SET_LOCK = 0;
+// Value of the test-and-set device
+
+uint16_t STACK_TOP = MMIO_BASE;
+// RSP and RBP start here, and the stack grows downwards from it
+uint16_t STACK_SIZE = DEFAULT_STACK_SIZE;
+// Number of words reserved for the stack of each core
+bool STACK_POISON_ENABLED = false;
+uint16_t STACK_POISON = 0;
+// Pattern which unused stack words are filled with, so stack corruption is visible in dumps
+
+FILE* CONSOLE_IN;
+FILE* CONSOLE_OUT;
+// The guest console streams, which are stdin/stdout unless redirected to a serial connection
+struct timespec LAST_CONSOLE_FLUSH;
+// Time of the last console flush, used to periodically flush partial lines
+
+bool ASYNC_INPUT = false;
+// Reads console input on a separate thread, so that programs can poll CONSOLE_STATUS instead of waiting on input
+char INPUT_QUEUE[INPUT_QUEUE_SIZE];
+uint32_t INPUT_QUEUE_START = 0;
+uint32_t INPUT_QUEUE_LEN = 0;
+// Ring buffer of characters which have been read by the input thread but not yet by the program
+bool INPUT_ENDED = false;
+// Set by the input thread once the end of the console input has been reached
+pthread_mutex_t INPUT_LOCK = PTHREAD_MUTEX_INITIALIZER;
+pthread_cond_t INPUT_CHANGED = PTHREAD_COND_INITIALIZER;
+// Guards the input queue, and signals both the program and the input thread when it changes
+
+#ifdef SMIS_LUA
+
+lua_State* SCRIPT;
+// Interpreter state of the script given with --script
+
+#endif
+
+bool DISPLAY_ENABLED = false;
+// Shows the framebuffer in a window, which is redrawn at a fixed rate while the program runs
+struct timespec LAST_DISPLAY_REFRESH;
+// Time of the last redraw of the display window
+
+#ifdef SMIS_DISPLAY
+SDL_Window* DISPLAY_WINDOW;
+SDL_Renderer* DISPLAY_RENDERER;
+SDL_Texture* DISPLAY_TEXTURE;
+#endif
+
+uint16_t INSTRUCTION_PC = 0;
+// Address of the instruction currently being executed, since PC is incremented before execution
+
+bool DEBUGGER_ENABLED = false;
+// Runs the program under an interactive prompt instead of all at once, which keeps the final state to be inspected
+Breakpoint* BREAKPOINTS = NULL;
+// Stores the breakpoints set in the debugger, in the order they were set
+uint32_t BREAKPOINT_COUNT = 0;
+// Stores the amount of breakpoints
+uint32_t NEXT_BREAKPOINT_NUMBER = 1;
+// Number given to the next breakpoint which is set
+bool STEP_MODE = false;
+// Waits for Enter before executing each instruction, printing the instruction and the registers
+bool DEBUGGER_STOP_REQUESTED = false;
+// Set by a watchpoint during an instruction, so that the debugger stops once the current round is finished
+uint16_t PROGRAM_END = 0;
+// Address just past the loaded program image
+uint16_t ENTRY_POINT = 0;
+// Address where every core starts executing, which is read from the header
+uint32_t BSS_START = 0;
+uint32_t BSS_LEN = 0;
+// Address and length of the .bss section, which is cleared when the program is loaded
+uint32_t PROGRAM_CHECKSUM = 0;
+// CRC-32 of the loaded binary file, used to make sure checkpoints are only resumed with the same program
+uint64_t INSTRUCTION_COUNT = 0;
+// Total number of instructions executed by all cores
+bool EXECUTED_WORDS[MEMORY_SIZE];
+// Marks the memory words which have been fetched as part of an instruction, used to detect self-modifying code
+
+CustomOpcode* CUSTOM_OPCODES = NULL;
+// Stores all instructions registered by extensions
+uint32_t CUSTOM_OPCODE_COUNT = 0;
+// Stores the amount of custom instructions
+
+char* OPCODE_NAMES[OP_HALT + 1] = {
+
+    [OP_SET] = "SET", [OP_COPY] = "COPY",
+    [OP_ADD] = "ADD", [OP_SUBTRACT] = "SUBTRACT", [OP_MULTIPLY] = "MULTIPLY", [OP_DIVIDE] = "DIVIDE", [OP_MODULO] = "MODULO",
+    [OP_COMPARE] = "COMPARE",
+    [OP_SHIFT_LEFT] = "SHIFT-LEFT", [OP_SHIFT_RIGHT] = "SHIFT-RIGHT",
+    [OP_AND] = "AND", [OP_OR] = "OR", [OP_XOR] = "XOR", [OP_NAND] = "NAND", [OP_NOR] = "NOR", [OP_NOT] = "NOT",
+    [OP_ADD_IMM] = "ADD-IMM", [OP_SUBTRACT_IMM] = "SUBTRACT-IMM", [OP_MULTIPLY_IMM] = "MULTIPLY-IMM",
+    [OP_DIVIDE_IMM] = "DIVIDE-IMM", [OP_MODULO_IMM] = "MODULO-IMM",
+    [OP_COMPARE_IMM] = "COMPARE-IMM", [OP_SHIFT_LEFT_IMM] = "SHIFT-LEFT-IMM", [OP_SHIFT_RIGHT_IMM] = "SHIFT-RIGHT-IMM",
+    [OP_AND_IMM] = "AND-IMM", [OP_OR_IMM] = "OR-IMM", [OP_XOR_IMM] = "XOR-IMM", [OP_NAND_IMM] = "NAND-IMM", [OP_NOR_IMM] = "NOR-IMM",
+    [OP_LOAD] = "LOAD", [OP_STORE] = "STORE",
+    [OP_JUMP] = "JUMP", [OP_JUMP_IF_ZERO] = "JUMP-IF-ZERO", [OP_JUMP_IF_NOTZERO] = "JUMP-IF-NOTZERO", [OP_JUMP_LINK] = "JUMP-LINK",
+    [OP_HALT] = "HALT"
+
+};
+// Mnemonic of each built-in opcode
+
+uint64_t OPCODE_COUNTS[0x100];
+// Number of times each opcode has been executed
+
+JsonValue* REFERENCE_TRACE = NULL;
+// Reference trace which the execution is compared against in co-simulation mode
+uint32_t REFERENCE_STEP = 0;
+// Index of the reference trace entry for the instruction currently being executed
+uint16_t COSIM_WRITTEN_REGISTERS = 0;
+// Bitmask of the registers changed by the current instruction
+uint16_t* COSIM_WRITTEN_ADDRS = NULL;
+uint32_t COSIM_WRITTEN_ADDR_COUNT = 0;
+// Memory addresses written by the current instruction
+
+FILE* TRACE_FILE = NULL;
+// Receives the trace written by --trace, in the same format as reference traces
+bool TRACE_JSONL = false;
+// Writes the trace as one JSON object per line holding the whole state after each instruction, instead of an array
+uint64_t TRACE_STEP_COUNT = 0;
+// Number of instructions written to the trace
+uint16_t TRACE_WRITTEN_REGISTERS = 0;
+uint16_t* TRACE_WRITTEN_ADDRS = NULL;
+uint32_t TRACE_WRITTEN_ADDR_COUNT = 0;
+// Registers and memory addresses changed by the current instruction
+
+FILE* RUN_STATE = NULL;
+// Receives the final state of a program run by runWithLimits(), written by the (forked) emulator process running it
+uint64_t RUN_INSTRUCTION_LIMIT = 0;
+// Number of instructions the program run by runWithLimits() may execute (0 if there is no limit)
+
+uint64_t CHECKPOINT_INTERVAL = 0;
+// Number of instructions between checkpoints, or 0 if checkpointing is disabled
+uint64_t NEXT_CHECKPOINT = 0;
+// Instruction count at which the next checkpoint is taken
+char* CHECKPOINT_DIR = ".";
+// Directory which checkpoints are written to and resumed from
+char* CHECKPOINT_PATHS[CHECKPOINTS_KEPT];
+uint32_t CHECKPOINT_COUNT = 0;
+// Paths of the most recent checkpoints written by this run, in a ring buffer
+
+char* CRASH_DUMP_FILE = NULL;
+// File which the state of the emulator is written to if the program stops with a runtime error
+char* MEMORY_DUMP_FILE = NULL;
+// File which the final memory is written to once the program halts
+uint16_t MEMORY_DUMP_START = 0;
+uint32_t MEMORY_DUMP_WORDS = MEMORY_SIZE;
+// Window of memory written to the memory dump, which is all of memory by default
+
+Symbol* SYMBOL_TABLE = NULL;
+// Stores the labels loaded from a symbol file with --symbols
+uint32_t SYMBOL_COUNT = 0;
+// Stores the amount of symbols
+
+SourceLine* SOURCE_LINES = NULL;
+// Stores the source line of each instruction, loaded from a debug info file with --debug-info
+uint32_t SOURCE_LINE_COUNT = 0;
+// Stores the amount of source lines
+
+EventHandler* EVENT_HANDLERS = NULL;
+// Stores all functions which are notified of execution events
+uint32_t EVENT_HANDLER_COUNT = 0;
+// Stores the amount of event handlers
+
+
+void openSerialConsole(char* addr);
+void loadProgram(char* binfile);
+void initializeStack(uint32_t programEnd);
+void executeProgram();
+bool executeRound();
+void stepInstruction();
+void executeInstruction();
+void grabNextInstruction();
+// Program control functions
+
+void addEventHandler(EventHandler handler);
+void emitEvent(EventType type, uint16_t target, uint16_t oldVal, uint16_t newVal);
+void printEvent(Event* e);
+void selfModifyEvent(Event* e);
+void dumpRegistersEvent(Event* e);
+char* getEventName(EventType type);
+// Execution event functions
+
+void loadReferenceTrace(char* tracefile);
+void cosimEvent(Event* e);
+void checkReferenceStep();
+void reportDivergence(char* reason);
+// Co-simulation functions
+
+void openTrace(char* tracefile);
+void traceEvent(Event* e);
+void writeTraceLine(Event* e);
+void writeTraceOperands(uint32_t instruction);
+void closeTrace();
+int diffTraces(char* tracefileA, char* tracefileB);
+char* compareTraceSteps(JsonValue* stepA, JsonValue* stepB);
+char* compareTraceChanges(JsonValue* changesA, JsonValue* changesB, bool registers);
+void printTraceStep(char* tracefile, JsonValue* step);
+// Trace functions
+
+void writeCheckpoint();
+void resumeCheckpoint();
+char* getCheckpointPath(uint64_t instructionCount);
+// Checkpoint functions
+
+void runtimeError(char* format, ...) __attribute__((noreturn, format(printf, 1, 2)));
+void writeCrashDump(char* description);
+void inspectCrashDump(char* dumpfile, uint16_t memStart, uint32_t memLen);
+void memoryDumpEvent(Event* e);
+void writeMemoryDump();
+// Crash dump functions
+
+void readSymbolFile(char* symfile);
+char* getSymbolLocation(uint16_t addr);
+bool findSymbolAddress(char* name, uint16_t* addr);
+void readDebugInfo(char* debugfile);
+SourceLine* getSourceLine(uint16_t addr);
+// Symbol functions
+
+void loadScript(char* scriptfile);
+void scriptEvent(Event* e);
+// Scripting functions
+
+void runDebugger();
+void runSingleSteps();
+void printRegisters();
+bool parseDebuggerArg(char* arg, long max, long* val);
+bool parseDebuggerAddress(char* arg, uint16_t* addr);
+bool parseDebuggerRegister(char* arg, uint8_t* reg);
+void runUntilBreakpoint(long rounds);
+void addBreakpoint(uint8_t kind, uint16_t addr, uint32_t length);
+void debuggerEvent(Event* e);
+Breakpoint* findBreakpoint(uint32_t number);
+void deleteBreakpoint(uint32_t number);
+void listBreakpoints();
+void printDebuggerLocation();
+void printDebuggerHelp();
+// Debugger functions
+
+RunResult* runWithLimits(char* binfile, uint64_t instructionLimit, char* input);
+void freeRunResult(RunResult* result);
+void runEvent(Event* e);
+void finishRun(HaltReason reason);
+// Program runner functions
+
+int gradeSpec(char* specfile, char* reportfile);
+bool runGradeCase(JsonValue* testCase, FILE* failures);
+bool checkGradeExpectations(JsonValue* testCase, RunResult* result, FILE* failures);
+// Grading functions
+
+JsonValue* parseJsonFile(char* filename);
+JsonValue* parseJsonValue(char** json);
+char* parseJsonString(char** json);
+void skipJsonWhitespace(char** json);
+JsonValue* getJsonMember(JsonValue* object, char* key);
+void checkRegisterKeys(JsonValue* array, char* filename, char* itemName);
+void printJsonString(FILE* out, char* str);
+// JSON functions
+
+void setFlags(uint16_t result);
+uint16_t getRegister(uint8_t reg);
+void setRegister(uint8_t reg, uint16_t val);
+void consoleWrite(char c);
+void flushConsole();
+void flushConsoleIfStale();
+void startInputThread();
+void* readInputThread(void* arg);
+uint16_t readConsole();
+uint16_t getConsoleStatus();
+void openDisplay();
+void refreshDisplay();
+void refreshDisplayIfStale();
+void waitForDisplayClose();
+void closeDisplay();
+uint16_t readMemory(uint16_t addr);
+void writeMemory(uint16_t addr, uint16_t val);
+
+bool RType(uint32_t instruction);
+bool IType(uint32_t instruction);
+bool JType(uint32_t instruction);
+bool CType(uint32_t instruction);
+
+void registerOpcode(uint8_t opcode, void (*execute)(uint32_t instruction));
+void registerExtensions() __attribute__((weak));
+
+void SET(uint8_t rDest, uint16_t iVal);
+void COPY(uint8_t rDest, uint8_t rSrc);
+
+void ADD(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+void SUBTRACT(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+void MULTIPLY(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+void DIVIDE(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+void MODULO(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+
+void COMPARE(uint8_t rOp1, uint8_t rOp2);
+
+void SHIFT_LEFT(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+void SHIFT_RIGHT(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+
+void AND(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+void OR(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+void XOR(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+void NAND(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+void NOR(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+void NOT(uint8_t rDest, uint8_t rOp);
+
+void ADD_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+void SUBTRACT_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+void MULTIPLY_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+void DIVIDE_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+void MODULO_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+
+void COMPARE_IMM(uint8_t rOp1, uint16_t iOp2);
+
+void SHIFT_LEFT_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+void SHIFT_RIGHT_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+
+void AND_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+void OR_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+void XOR_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+void NAND_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+void NOR_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+
+void LOAD(uint8_t rDest, uint8_t rBase, uint16_t iOffset);
+void STORE(uint8_t rSrc, uint8_t rBase, uint16_t iOffset);
+
+void JUMP(uint16_t destAddr);
+void JUMP_IF_ZERO(uint16_t destAddr);
+void JUMP_IF_NOTZERO(uint16_t destAddr);
+void JUMP_LINK(uint16_t destAddr);
+
+void HALT();
+// Instruction execution functions
+
+uint8_t getOpcode(uint32_t instruction);
+uint16_t getInstructionHalf1(uint32_t instruction);
+uint16_t getInstructionHalf2(uint32_t instruction);
+uint8_t getRegOperand(uint32_t instruction, uint8_t opNum);
+uint16_t getDestOrImmVal(uint32_t instruction);
+void dumpState(FILE* out, uint16_t memStart, uint32_t memLen);
+void dumpMemory(FILE* out, uint16_t memStart, uint32_t memLen);
+void printOpcodeStats();
+char* getRegisterName(uint8_t regNum);
+uint8_t parseRegisterName(char* name);
+char* getOpcodeName(uint8_t opcode);
+uint32_t getReservedBits(uint8_t opcode);
+uint16_t parseNumberArg(char** argv, int argc, int* i);
+long millisecondsSince(struct timespec* then);
+// Emulator utility functions
+
+bool endsWith(char* str, char* substr);
+// General utility functions
+
+
+int main(int argc, char** argv) {
+
+    char* binfile = NULL;
+    char* serialAddr = NULL;
+    char* gradeFile = NULL;
+    char* gradeReportFile = NULL;
+    bool stackTopSet = false;
+    bool resume = false;
+    char* scriptFile = NULL;
+    char* traceFile = NULL;
+    char* traceDiffFiles[2] = {NULL, NULL};
+    char* inspectFile = NULL;
+    uint16_t inspectStart = 0;
+    uint32_t inspectWords = 0;
+
+    for(int i = 1; i < argc; i++) {
+
+        if(!strncmp(argv[i], "--serial", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No address supplied for --serial.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            serialAddr = argv[i];
+
+        } else if(!strncmp(argv[i], "--reference", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No trace file supplied for --reference.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            loadReferenceTrace(argv[i]);
+
+        } else if(!strncmp(argv[i], "--trace", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No trace file supplied for --trace.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            traceFile = argv[i];
+
+        } else if(!strncmp(argv[i], "--trace-format", MAX_STRING_LEN)) {
+
+            if(++i >= argc || (strncmp(argv[i], "json", MAX_STRING_LEN) && strncmp(argv[i], "jsonl", MAX_STRING_LEN))) {
+
+                printf("--trace-format needs a format of json or jsonl.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            TRACE_JSONL = !strncmp(argv[i], "jsonl", MAX_STRING_LEN);
+
+        } else if(!strncmp(argv[i], "--trace-diff", MAX_STRING_LEN)) {
+
+            if(i + 2 >= argc) {
+
+                printf("Two trace files must be supplied for --trace-diff.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            traceDiffFiles[0] = argv[++i];
+            traceDiffFiles[1] = argv[++i];
+
+        } else if(!strncmp(argv[i], "--grade", MAX_STRING_LEN) || !strncmp(argv[i], "--grade-report", MAX_STRING_LEN)) {
+
+            if(i + 1 >= argc) {
+
+                printf("No spec file supplied for %s.\n", argv[i]);
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            if(!strncmp(argv[i], "--grade", MAX_STRING_LEN)) gradeFile = argv[++i];
+            else gradeReportFile = argv[++i];
+
+        } else if(!strncmp(argv[i], "--events", MAX_STRING_LEN)) addEventHandler(printEvent);
+        else if(!strncmp(argv[i], "--opcode-stats", MAX_STRING_LEN)) atexit(printOpcodeStats);
+        else if(!strncmp(argv[i], "--dump-registers", MAX_STRING_LEN)) addEventHandler(dumpRegistersEvent);
+        else if(!strncmp(argv[i], "--dual-core", MAX_STRING_LEN)) CORE_COUNT = 2;
+        else if(!strncmp(argv[i], "--strict", MAX_STRING_LEN)) STRICT_MODE = true;
+        else if(!strncmp(argv[i], "--force", MAX_STRING_LEN) || !strncmp(argv[i], "--no-ext-check", MAX_STRING_LEN)) FORCE_EXTENSIONS = true;
+        else if(!strncmp(argv[i], "--display", MAX_STRING_LEN)) DISPLAY_ENABLED = true;
+        else if(!strncmp(argv[i], "--async-input", MAX_STRING_LEN)) ASYNC_INPUT = true;
+        else if(!strncmp(argv[i], "--debug", MAX_STRING_LEN)) DEBUGGER_ENABLED = true;
+        else if(!strncmp(argv[i], "--step", MAX_STRING_LEN)) STEP_MODE = true;
+        else if(!strncmp(argv[i], "--warn-self-modify", MAX_STRING_LEN)) addEventHandler(selfModifyEvent);
+        else if(!strncmp(argv[i], "--resume", MAX_STRING_LEN)) resume = true;
+        else if(!strncmp(argv[i], "--checkpoint-dir", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No directory supplied for --checkpoint-dir.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            CHECKPOINT_DIR = argv[i];
+
+        } else if(!strncmp(argv[i], "--crash-dump", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No file supplied for --crash-dump.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            CRASH_DUMP_FILE = argv[i];
+
+        } else if(!strncmp(argv[i], "--dump-memory", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No file supplied for --dump-memory.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            MEMORY_DUMP_FILE = argv[i];
+            addEventHandler(memoryDumpEvent);
+
+        } else if(!strncmp(argv[i], "--dump-start", MAX_STRING_LEN)) MEMORY_DUMP_START = parseNumberArg(argv, argc, &i);
+        else if(!strncmp(argv[i], "--dump-words", MAX_STRING_LEN)) MEMORY_DUMP_WORDS = parseNumberArg(argv, argc, &i);
+        else if(!strncmp(argv[i], "--symbols", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No symbol file supplied for --symbols.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            readSymbolFile(argv[i]);
+
+        } else if(!strncmp(argv[i], "--debug-info", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No debug info file supplied for --debug-info.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            readDebugInfo(argv[i]);
+
+        } else if(!strncmp(argv[i], "--inspect-dump", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No crash dump supplied for --inspect-dump.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            inspectFile = argv[i];
+
+        } else if(!strncmp(argv[i], "--inspect-start", MAX_STRING_LEN)) inspectStart = parseNumberArg(argv, argc, &i);
+        else if(!strncmp(argv[i], "--inspect-words", MAX_STRING_LEN)) inspectWords = parseNumberArg(argv, argc, &i);
+        else if(!strncmp(argv[i], "--script", MAX_STRING_LEN)) {
+
+            if(++i >= argc) {
+
+                printf("No script file supplied for --script.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            scriptFile = argv[i];
+
+        } else if(!strncmp(argv[i], "--checkpoint-every", MAX_STRING_LEN)) {
+
+            char* end = NULL;
+
+            if(++i >= argc || !(CHECKPOINT_INTERVAL = strtoull(argv[i], &end, 10)) || *end) {
+
+                printf("--checkpoint-every needs a positive number of instructions.\n");
+                printf(USAGE);
+                exit(-1);
+
+            }
+
+            NEXT_CHECKPOINT = CHECKPOINT_INTERVAL;
+
+        }
+        else if(!strncmp(argv[i], "--stack-top", MAX_STRING_LEN)) {
+
+            STACK_TOP = parseNumberArg(argv, argc, &i);
+            stackTopSet = true;
+
+        } else if(!strncmp(argv[i], "--stack-size", MAX_STRING_LEN)) STACK_SIZE = parseNumberArg(argv, argc, &i);
+        else if(!strncmp(argv[i], "--stack-poison", MAX_STRING_LEN)) {
+
+            STACK_POISON = parseNumberArg(argv, argc, &i);
+            STACK_POISON_ENABLED = true;
+
+        } else if(!binfile) binfile = argv[i];
+        else {
+
+            printf("Incorrect number of arguments supplied.\n");
+            printf(USAGE);
+            exit(-1);
+
+        }
+
+    }
+
+    if(traceFile) openTrace(traceFile);
+    // The trace is only opened once every option has been read, since its format may be given after it
+
+    if(DISPLAY_ENABLED && !stackTopSet) STACK_TOP = DISPLAY_BASE;
+    // The stack is moved below the framebuffer so the two cannot overwrite each other
+
+    if(registerExtensions) registerExtensions();
+
+    if(gradeFile && !binfile) exit(gradeSpec(gradeFile, gradeReportFile));
+    if(traceDiffFiles[0] && !binfile) exit(diffTraces(traceDiffFiles[0], traceDiffFiles[1]));
+    if(inspectFile && !binfile) {
+
+        inspectCrashDump(inspectFile, inspectStart, inspectWords);
+        exit(0);
+
+    }
+
+    if(!binfile || gradeFile || gradeReportFile) {
+
+        printf("Incorrect number of arguments supplied.\n");
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    if(!FORCE_EXTENSIONS && !endsWith(binfile, ".bin")) {
+
+        printf("The supplied file does not have the correct extension.\n");
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    CONSOLE_IN = stdin;
+    CONSOLE_OUT = stdout;
+    setvbuf(CONSOLE_OUT, NULL, _IOFBF, CONSOLE_BUFFER_SIZE);
+    // The buffer has to be set before anything is written to the stream
+
+    loadProgram(binfile);
+    if(resume) resumeCheckpoint();
+    if(scriptFile) loadScript(scriptFile);
+    if(serialAddr) openSerialConsole(serialAddr);
+    if(ASYNC_INPUT) startInputThread();
+    if(DISPLAY_ENABLED) openDisplay();
+
+    clock_gettime(CLOCK_MONOTONIC, &LAST_CONSOLE_FLUSH);
+
+    if(DEBUGGER_ENABLED) runDebugger();
+    else if(STEP_MODE) runSingleSteps();
+    else executeProgram();
+
+    return 0;
+    
+}
+
+void openSerialConsole(char* addr) {
+    // Waits for a TCP client on the given "[host]:port" address and redirects the guest console to it
+
+    char* host = strndup(addr, MAX_STRING_LEN);
+    char* port = strrchr(host, ':');
+
+    if(!port) {
+
+        printf("Serial address %s must be in the format [host]:port.\n", addr);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    *port = '\0';
+    port++;
+
+    struct addrinfo hints;
+    struct addrinfo* info;
+
+    memset(&hints, 0, sizeof(hints));
+    hints.ai_family = AF_INET;
+    hints.ai_socktype = SOCK_STREAM;
+    hints.ai_flags = AI_PASSIVE;
+
+    if(getaddrinfo(*host ? host : NULL, port, &hints, &info)) {
+
+        printf("Cannot resolve serial address %s.\n", addr);
+        exit(-1);
+
+    }
+
+    int listener = socket(info->ai_family, info->ai_socktype, info->ai_protocol);
+    int reuse = 1;
+
+    if(listener < 0 || setsockopt(listener, SOL_SOCKET, SO_REUSEADDR, &reuse, sizeof(reuse))
+        || bind(listener, info->ai_addr, info->ai_addrlen) || listen(listener, 1)) {
+
+        printf("Cannot listen for serial connections on %s.\n", addr);
+        exit(-1);
+
+    }
+
+    freeaddrinfo(info);
+    free(host);
+
+    printf("Waiting for serial connection on %s...\n", addr);
+    fflush(stdout);
+
+    int client = accept(listener, NULL, NULL);
+
+    if(client < 0) {
+
+        printf("Failed to accept serial connection on %s.\n", addr);
+        exit(-1);
+
+    }
+
+    close(listener);
+
+    CONSOLE_IN = fdopen(client, "r");
+    CONSOLE_OUT = fdopen(dup(client), "w");
+    setvbuf(CONSOLE_OUT, NULL, _IOFBF, CONSOLE_BUFFER_SIZE);
+
+    printf("Serial console connected.\n");
+
+}
+
+void loadProgram(char* binfile) {
+    // Reads the binary file one instruction at a time and places it in the memory array
+
+    FILE* file;
+
+#ifdef SMIS_MMAP
+
+    size_t len;
+    uint8_t* data = mapBinaryFile(binfile, &len);
+
+    file = data ? fmemopen(data, len, "rb") : NULL;
+    // The instructions are decoded straight from the mapped file, the same as the disassembler does
+
+#else
+
+    file = fopen(binfile, "rb");
+
+#endif
+
+    if(!file) {
+
+        printf("File %s does not exist.\n", binfile);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    if(!(MEM = calloc(MEMORY_SIZE, sizeof(uint16_t)))) {
+
+        printf("Cannot allocate %i words of emulator memory.\n", MEMORY_SIZE);
+        exit(-1);
+
+    }
+
+    DecodeStream stream;
+    uint32_t storeAddr = 0;
+    uint16_t addr;
+    uint32_t instruction;
+    int result = DECODE_ERROR;
+
+    if(openDecodeStream(&stream, file, binfile)) {
+
+        while((result = decodeNext(&stream, &addr, &instruction)) == DECODE_INSTRUCTION) {
+
+            MEM[addr] = getInstructionHalf1(instruction);
+            MEM[addr + 1] = getInstructionHalf2(instruction);
+            // Split the instruction into two 16-bit segments to put in memory
+
+            storeAddr = addr + 2;
+
+        }
+
+    }
+
+    fclose(file);
+
+#ifdef SMIS_MMAP
+
+    unmapBinaryFile(data, len);
+
+#endif
+
+    if(result == DECODE_ERROR) {
+
+        printf("%s\n", stream.error);
+        exit(-1);
+
+    }
+
+    ENTRY_POINT = stream.entryPoint;
+
+    for(uint32_t i = 0; i < stream.sectionCount; i++) {
+
+        if(stream.sections[i].type != SECTION_BSS) continue;
+
+        BSS_START = stream.sections[i].start;
+        BSS_LEN = stream.sections[i].length;
+
+    }
+
+    closeDecodeStream(&stream);
+    
+    memset(MEM + BSS_START, 0, BSS_LEN * sizeof(uint16_t));
+    // The .bss section is not stored in the file, so its words are cleared here instead of being copied
+
+    if((!BSS_LEN || storeAddr < BSS_START) && storeAddr < MEMORY_SIZE) MEM[storeAddr] = OP_HALT << 8;
+    // Add a HALT to the end, in case the ASM programmer forgot to do so, unless the .bss section starts right there
+    // or the program fills the rest of memory
+
+    PROGRAM_CHECKSUM = ~stream.fileCrc;
+
+    PROGRAM_END = storeAddr + 2;
+
+    initializeStack(BSS_LEN ? BSS_START + BSS_LEN : storeAddr + 2);
+
+    for(int c = 0; c < CORE_COUNT; c++) CORES[c].programCounter = ENTRY_POINT;
+
+}
+
+void initializeStack(uint32_t programEnd) {
+    // Points RSP and RBP of each core at its own stack, which are placed directly below each other under the stack top
+
+    uint32_t stackBottom = STACK_TOP - (uint32_t) STACK_SIZE * CORE_COUNT;
+    uint16_t deviceBase = DISPLAY_ENABLED ? DISPLAY_BASE : MMIO_BASE;
+
+    if(STACK_TOP > deviceBase || (uint32_t) STACK_SIZE * CORE_COUNT > STACK_TOP) {
+
+        printf("The stack region must fit below the device region at 0x%.4X.\n", deviceBase);
+        exit(-1);
+
+    }
+
+    if(programEnd > stackBottom) {
+
+        printf("The program (ending at 0x%.4X) overlaps the stack region 0x%.4X-0x%.4X.\n", programEnd, stackBottom, STACK_TOP - 1);
+        exit(-1);
+
+    }
+
+    if(STACK_POISON_ENABLED) {
+
+        for(uint32_t addr = stackBottom; addr < STACK_TOP; addr++) MEM[addr] = STACK_POISON;
+
+    }
+
+    for(int c = 0; c < CORE_COUNT; c++) {
+
+        CORES[c].registers[0xF] = STACK_TOP - STACK_SIZE * c;
+        CORES[c].registers[0xE] = STACK_TOP - STACK_SIZE * c;
+
+    }
+
+}
+
+void executeProgram() {
+    // Steps each core in turn until all of them have reached a HALT signal
+
+    while(executeRound());
+
+}
+
+bool executeRound() {
+    // Steps each core which has not halted yet by one instruction
+    // Returns false if every core had already halted
+
+    bool running = false;
+
+    if(CHECKPOINT_INTERVAL && INSTRUCTION_COUNT >= NEXT_CHECKPOINT) writeCheckpoint();
+    // Checkpoints are only taken between rounds, so that every core has executed the same number of steps
+
+    for(int c = 0; c < CORE_COUNT; c++) {
+
+        CORE = &CORES[c];
+
+        if(CORE->halted) continue;
+
+        stepInstruction();
+        running = true;
+
+    }
+
+    return running;
+
+}
+
+void stepInstruction() {
+    // Executes the next instruction of the current core
+
+    uint16_t oldRegisters[0x10];
+    memcpy(oldRegisters, REG, sizeof(oldRegisters));
+
+    INSTRUCTION_PC = PC;
+
+    grabNextInstruction();
+    OPCODE_COUNTS[getOpcode(IR)]++;
+    INSTRUCTION_COUNT++;
+    PC += 2;
+    // PC is incremented prior to executing instruction so it does not interfere with J-Type instructions
+    executeInstruction();
+
+    if(PC & 1) {
+
+        runtimeError("Instruction 0x%.8X at PC address 0x%.4X transferred control to odd address 0x%.4X, "
+            "but instructions must start at even addresses", IR, INSTRUCTION_PC, PC);
+
+    }
+    // Fetching from an odd address would combine the halves of two unrelated words into one instruction
+
+    RZR = 0x0000;
+    // Custom instructions may write REG directly, so RZR is still cleared after every instruction
+
+    if(EVENT_HANDLER_COUNT) {
+
+        for(int r = 0; r < 0x10; r++) if(REG[r] != oldRegisters[r]) emitEvent(EVENT_REGISTER_WRITE, r, oldRegisters[r], REG[r]);
+
+        if(PC != INSTRUCTION_PC + 2) emitEvent(EVENT_JUMP, PC, INSTRUCTION_PC, PC);
+
+        emitEvent(EVENT_INSTRUCTION_EXECUTED, INSTRUCTION_PC, 0, 0);
+
+    }
+
+    flushConsoleIfStale();
+    if(DISPLAY_ENABLED) refreshDisplayIfStale();
+
+    if(IR == 0x00000000) CORE->halted = true;
+
+}
+
+void executeInstruction() {
+    // Executes the instruction held in the instruction register
+
+    uint32_t reservedBits = IR & getReservedBits(getOpcode(IR));
+
+    if(STRICT_MODE && reservedBits) {
+
+        runtimeError("Reserved bits 0x%.8X are set in instruction 0x%.8X at PC address 0x%.4X", reservedBits, IR, INSTRUCTION_PC);
+
+    }
+
+    if(RType(IR)) return;
+    else if(IType(IR)) return;
+    else if(JType(IR)) return;
+    else if(CType(IR)) return;
+
+    runtimeError("Unknown instruction 0x%.8X at PC address 0x%.4X", IR, PC);
+
+}
+
+void grabNextInstruction() {
+    // Gets the next instruction from memory and places it in the instruction register
+
+    IR = 0;
+
+    IR ^= MEM[PC] << 16;
+    IR ^= MEM[PC + 1];
+
+}
+
+void setFlags(uint16_t result) {
+    // Sets flags according to the given value, usually the result of an arithmetic operation
+
+    if(result == 0x0000) ZF = true;
+    else ZF = false;
+
+    if(result >> 15 == 0x1) SF = true;
+    else SF = false;
+
+}
+
+uint16_t getRegister(uint8_t reg) {
+    // Reads a register of the current core, where RZR always reads as zero
+
+    if(reg == 0x0) return 0x0000;
+
+    return REG[reg];
+
+}
+
+void setRegister(uint8_t reg, uint16_t val) {
+    // Writes a register of the current core, where writes to RZR are discarded
+
+    if(reg == 0x0) return;
+
+    REG[reg] = val;
+
+}
+
+uint16_t readMemory(uint16_t addr) {
+    // Reads a word from memory, forwarding reads in the device region to the corresponding device
+
+    if(addr == CONSOLE_DATA) {
+
+        uint16_t val = readConsole();
+
+        emitEvent(EVENT_DEVICE_IO, addr, 0, val);
+
+        return val;
+
+    }
+
+    if(addr == CONSOLE_STATUS) return getConsoleStatus();
+
+    if(addr == CORE_ID) return CORE - CORES;
+
+    if(addr == TEST_AND_SET) {
+
+        uint16_t val = TEST_AND_SET_LOCK;
+        TEST_AND_SET_LOCK = 1;
+
+        emitEvent(EVENT_DEVICE_IO, addr, val, 1);
+
+        return val;
+
+    }
+
+    emitEvent(EVENT_MEMORY_READ, addr, MEM[addr], MEM[addr]);
+
+    return MEM[addr];
+
+}
+
+void writeMemory(uint16_t addr, uint16_t val) {
+    // Writes a word to memory, forwarding writes in the device region to the corresponding device
+
+    if(addr == CONSOLE_DATA) {
+
+        consoleWrite(val & 0xFF);
+        emitEvent(EVENT_DEVICE_IO, addr, 0, val);
+
+        return;
+
+    }
+
+    if(addr == TEST_AND_SET) {
+
+        emitEvent(EVENT_DEVICE_IO, addr, TEST_AND_SET_LOCK, val);
+        TEST_AND_SET_LOCK = val;
+
+        return;
+
+    }
+
+    emitEvent(EVENT_MEMORY_WRITE, addr, MEM[addr], val);
+
+    MEM[addr] = val;
+
+}
+
+void addEventHandler(EventHandler handler) {
+    // Registers a function to be called for every execution event
+
+    EVENT_HANDLERS = realloc(EVENT_HANDLERS, (EVENT_HANDLER_COUNT + 1) * sizeof(EventHandler));
+
+    EVENT_HANDLERS[EVENT_HANDLER_COUNT] = handler;
+
+    EVENT_HANDLER_COUNT++;
+
+}
+
+void emitEvent(EventType type, uint16_t target, uint16_t oldVal, uint16_t newVal) {
+    // Sends an event caused by the current instruction to all registered event handlers
+
+    if(!EVENT_HANDLER_COUNT) return;
+
+    Event e;
+    e.type = type;
+    e.PCAddress = INSTRUCTION_PC;
+    e.core = CORE - CORES;
+    e.instruction = IR;
+    e.target = target;
+    e.oldVal = oldVal;
+    e.newVal = newVal;
+
+    for(int i = 0; i < EVENT_HANDLER_COUNT; i++) EVENT_HANDLERS[i](&e);
+
+}
+
+void selfModifyEvent(Event* e) {
+    // Event handler which warns about stores into instructions that have been executed or are part of the program image
+    // Instructions are always fetched straight from memory, so modified instructions take effect the next time they run
+
+    switch(e->type) {
+
+        case EVENT_INSTRUCTION_EXECUTED:
+            EXECUTED_WORDS[e->PCAddress] = true;
+            EXECUTED_WORDS[e->PCAddress + 1] = true;
+            break;
+
+        case EVENT_MEMORY_WRITE:
+            if(EXECUTED_WORDS[e->target] || e->target < PROGRAM_END) {
+
+                fprintf(stderr, "Warning: the instruction at PC 0x%.4X overwrote memory[0x%.4X], which is part of the %s instruction at PC 0x%.4X\n",
+                    e->PCAddress, e->target, EXECUTED_WORDS[e->target] ? "already executed" : "not yet executed", e->target & ~1);
+
+            }
+            break;
+
+        default: break;
+
+    }
+
+}
+
+void dumpRegistersEvent(Event* e) {
+    // Event handler which prints the final registers, flags, and PC of each core once the program halts
+
+    if(e->type == EVENT_HALT) printRegisters();
+
+}
+
+void printEvent(Event* e) {
+    // Event handler which prints each event as a single line to stderr
+
+    fprintf(stderr, "%s pc=0x%.4X instruction=0x%.8X", getEventName(e->type), e->PCAddress, e->instruction);
+
+    if(CORE_COUNT > 1) fprintf(stderr, " core=%i", e->core);
+
+    char* location = getSymbolLocation(e->PCAddress);
+    if(location) fprintf(stderr, " label=%s", location);
+    free(location);
+
+    switch(e->type) {
+
+        case EVENT_MEMORY_WRITE:
+            fprintf(stderr, " addr=0x%.4X old=0x%.4X new=0x%.4X", e->target, e->oldVal, e->newVal); break;
+        case EVENT_MEMORY_READ:
+            fprintf(stderr, " addr=0x%.4X val=0x%.4X", e->target, e->newVal); break;
+        case EVENT_REGISTER_WRITE:
+            fprintf(stderr, " reg=R%i old=0x%.4X new=0x%.4X", e->target, e->oldVal, e->newVal); break;
+        case EVENT_JUMP:
+            fprintf(stderr, " dest=0x%.4X", e->target);
+            location = getSymbolLocation(e->target);
+            if(location) fprintf(stderr, " dest-label=%s", location);
+            free(location);
+            break;
+        case EVENT_DEVICE_IO:
+            fprintf(stderr, " device=0x%.4X val=0x%.4X", e->target, e->newVal); break;
+        default: break;
+
+    }
+
+    fputc('\n', stderr);
+
+}
+
+char* getEventName(EventType type) {
+    // Gets the display name of a given event type
+
+    switch(type) {
+
+        case EVENT_INSTRUCTION_EXECUTED: return "INSTRUCTION-EXECUTED";
+        case EVENT_MEMORY_WRITE: return "MEMORY-WRITE";
+        case EVENT_MEMORY_READ: return "MEMORY-READ";
+        case EVENT_REGISTER_WRITE: return "REGISTER-WRITE";
+        case EVENT_JUMP: return "JUMP";
+        case EVENT_HALT: return "HALT";
+        case EVENT_DEVICE_IO: return "DEVICE-IO";
+
+    }
+
+    return "UNKNOWN";
+
+}
+
+void loadReferenceTrace(char* tracefile) {
+    // Loads a reference trace for co-simulation, which must be a JSON array with one object per executed instruction
+    // Each object holds the "pc" of the instruction, and optionally the values of all "registers" and "memory"
+    // addresses changed by it, e.g. { "pc": 4, "registers": { "R1": 5 }, "memory": { "0x1000": 5 } }
+
+    REFERENCE_TRACE = parseJsonFile(tracefile);
+
+    if(REFERENCE_TRACE->type != JSON_ARRAY) {
+
+        printf("Reference trace %s must be a JSON array of executed instructions.\n", tracefile);
+        exit(-1);
+
+    }
+
+    checkRegisterKeys(REFERENCE_TRACE, tracefile, "step");
+
+    addEventHandler(cosimEvent);
+
+}
+
+void cosimEvent(Event* e) {
+    // Event handler which collects the state changes of each instruction and compares them against the reference trace
+
+    switch(e->type) {
+
+        case EVENT_REGISTER_WRITE:
+            COSIM_WRITTEN_REGISTERS |= 1 << e->target;
+            break;
+
+        case EVENT_MEMORY_WRITE:
+            COSIM_WRITTEN_ADDRS = realloc(COSIM_WRITTEN_ADDRS, (COSIM_WRITTEN_ADDR_COUNT + 1) * sizeof(uint16_t));
+            COSIM_WRITTEN_ADDRS[COSIM_WRITTEN_ADDR_COUNT++] = e->target;
+            break;
+
+        case EVENT_INSTRUCTION_EXECUTED:
+            checkReferenceStep();
+            break;
+
+        case EVENT_HALT:
+            checkReferenceStep();
+
+            if(REFERENCE_STEP < REFERENCE_TRACE->count) reportDivergence("the program halted, but the reference trace continues");
+
+            printf("Co-simulation matched all %i steps of the reference trace\n", REFERENCE_STEP);
+            break;
+
+        default: break;
+
+    }
+
+}
+
+void checkReferenceStep() {
+    // Compares the state changes of the instruction that was just executed against the next reference trace entry
+
+    char reason[MAX_STRING_LEN];
+
+    if(REFERENCE_STEP >= REFERENCE_TRACE->count) reportDivergence("the reference trace ended, but the program is still running");
+
+    JsonValue* step = &REFERENCE_TRACE->items[REFERENCE_STEP];
+    JsonValue* pc = getJsonMember(step, "pc");
+    JsonValue* registers = getJsonMember(step, "registers");
+    JsonValue* memory = getJsonMember(step, "memory");
+
+    if(pc && (uint16_t) pc->number != INSTRUCTION_PC) {
+
+        snprintf(reason, MAX_STRING_LEN, "expected the instruction at PC 0x%.4X", (uint16_t) pc->number);
+        reportDivergence(reason);
+
+    }
+
+    uint16_t expectedRegisters = 0;
+
+    for(int i = 0; registers && i < registers->count; i++) {
+
+        uint8_t r = parseRegisterName(registers->keys[i]);
+
+        expectedRegisters |= 1 << r;
+
+        if(REG[r] != (uint16_t) registers->items[i].number) {
+
+            snprintf(reason, MAX_STRING_LEN, "expected %s = 0x%.4X, but the emulator has 0x%.4X",
+                getRegisterName(r), (uint16_t) registers->items[i].number, REG[r]);
+            reportDivergence(reason);
+
+        }
+
+    }
+
+    for(int r = 0; r < 0x10; r++) {
+
+        if((COSIM_WRITTEN_REGISTERS & ~expectedRegisters) & (1 << r)) {
+
+            snprintf(reason, MAX_STRING_LEN, "the emulator changed %s to 0x%.4X, but the reference did not", getRegisterName(r), REG[r]);
+            reportDivergence(reason);
+
+        }
+
+    }
+
+    for(int i = 0; memory && i < memory->count; i++) {
+
+        uint16_t addr = strtol(memory->keys[i], NULL, 0);
+
+        if(MEM[addr] != (uint16_t) memory->items[i].number) {
+
+            snprintf(reason, MAX_STRING_LEN, "expected memory[0x%.4X] = 0x%.4X, but the emulator has 0x%.4X",
+                addr, (uint16_t) memory->items[i].number, MEM[addr]);
+            reportDivergence(reason);
+
+        }
+
+    }
+
+    for(int i = 0; i < COSIM_WRITTEN_ADDR_COUNT; i++) {
+
+        bool expected = false;
+
+        for(int j = 0; memory && j < memory->count; j++) if(strtol(memory->keys[j], NULL, 0) == COSIM_WRITTEN_ADDRS[i]) expected = true;
+
+        if(!expected) {
+
+            snprintf(reason, MAX_STRING_LEN, "the emulator wrote 0x%.4X to memory[0x%.4X], but the reference did not",
+                MEM[COSIM_WRITTEN_ADDRS[i]], COSIM_WRITTEN_ADDRS[i]);
+            reportDivergence(reason);
+
+        }
+
+    }
+
+    COSIM_WRITTEN_REGISTERS = 0;
+    COSIM_WRITTEN_ADDR_COUNT = 0;
+    REFERENCE_STEP++;
+
+}
+
+void reportDivergence(char* reason) {
+    // Reports the first point at which the emulator and the reference trace disagree, and terminates the program
+
+    flushConsole();
+
+    printf("Co-simulation diverged at step %i (instruction 0x%.8X at PC address 0x%.4X): %s\n",
+        REFERENCE_STEP, IR, INSTRUCTION_PC, reason);
+    dumpState(stdout, INSTRUCTION_PC & ~(DUMP_WORDS_PER_ROW - 1), DUMP_WORDS_PER_ROW * 2);
+
+    exit(-1);
+
+}
+
+void openTrace(char* tracefile) {
+    // Starts writing a trace of the program, which is a JSON array with one object per executed instruction holding
+    // its "pc", "instruction", and the new values of the "registers" and "memory" addresses it changed
+    // Traces can be used as the reference for --reference, and compared against each other with --trace-diff
+    // With --trace-format jsonl, the trace is written with writeTraceLine() instead
+
+    if(!(TRACE_FILE = fopen(tracefile, "w"))) {
+
+        printf("Cannot output to file %s.\n", tracefile);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    if(!TRACE_JSONL) fprintf(TRACE_FILE, "[");
+
+    addEventHandler(traceEvent);
+    atexit(closeTrace);
+
+}
+
+void traceEvent(Event* e) {
+    // Event handler which collects the state changes of each instruction and writes them to the trace
+
+    if(TRACE_JSONL) {
+
+        if(e->type == EVENT_INSTRUCTION_EXECUTED) writeTraceLine(e);
+        return;
+
+    }
+    // JSON lines traces hold the whole state after each instruction, so the changes do not need to be collected
+
+    switch(e->type) {
+
+        case EVENT_REGISTER_WRITE:
+            TRACE_WRITTEN_REGISTERS |= 1 << e->target;
+            return;
+
+        case EVENT_MEMORY_WRITE:
+            TRACE_WRITTEN_ADDRS = realloc(TRACE_WRITTEN_ADDRS, (TRACE_WRITTEN_ADDR_COUNT + 1) * sizeof(uint16_t));
+            TRACE_WRITTEN_ADDRS[TRACE_WRITTEN_ADDR_COUNT++] = e->target;
+            return;
+
+        case EVENT_INSTRUCTION_EXECUTED:
+            break;
+
+        default: return;
+
+    }
+
+    fprintf(TRACE_FILE, "%s\n  { \"pc\": %i, \"instruction\": %u", TRACE_STEP_COUNT++ ? "," : "", e->PCAddress, e->instruction);
+
+    if(CORE_COUNT > 1) fprintf(TRACE_FILE, ", \"core\": %i", e->core);
+
+    if(TRACE_WRITTEN_REGISTERS) {
+
+        bool first = true;
+
+        fprintf(TRACE_FILE, ", \"registers\": {");
+
+        for(int r = 0; r < 0x10; r++) {
+
+            if(!(TRACE_WRITTEN_REGISTERS & (1 << r))) continue;
+
+            fprintf(TRACE_FILE, "%s \"R%i\": %i", first ? "" : ",", r, REG[r]);
+            first = false;
+
+        }
+
+        fprintf(TRACE_FILE, " }");
+
+    }
+
+    if(TRACE_WRITTEN_ADDR_COUNT) {
+
+        fprintf(TRACE_FILE, ", \"memory\": {");
+
+        for(int i = 0; i < TRACE_WRITTEN_ADDR_COUNT; i++) {
+
+            bool repeated = false;
+            for(int j = 0; j < i; j++) if(TRACE_WRITTEN_ADDRS[j] == TRACE_WRITTEN_ADDRS[i]) repeated = true;
+
+            if(!repeated) fprintf(TRACE_FILE, "%s \"0x%.4X\": %i", i ? "," : "", TRACE_WRITTEN_ADDRS[i], MEM[TRACE_WRITTEN_ADDRS[i]]);
+
+        }
+
+        fprintf(TRACE_FILE, " }");
+
+    }
+
+    fprintf(TRACE_FILE, " }");
+
+    TRACE_WRITTEN_REGISTERS = 0;
+    TRACE_WRITTEN_ADDR_COUNT = 0;
+
+}
+
+void writeTraceLine(Event* e) {
+    // Writes an executed instruction to a JSON lines trace, as an object holding its "pc", "opcode", and "operands",
+    // and the values of all "registers" and "flags" after it, e.g.
+    // { "pc": 4, "opcode": "ADD", "operands": ["R1", "R2", "R3"], "registers": { "R0": 0, ... }, "flags": { "ZF": 0, "SF": 0 } }
+
+    fprintf(TRACE_FILE, "{ \"pc\": %i, \"opcode\": \"%s\", \"operands\": ", e->PCAddress, getOpcodeName(getOpcode(e->instruction)));
+
+    writeTraceOperands(e->instruction);
+
+    if(CORE_COUNT > 1) fprintf(TRACE_FILE, ", \"core\": %i", e->core);
+
+    fprintf(TRACE_FILE, ", \"registers\": {");
+
+    for(int r = 0; r < 0x10; r++) fprintf(TRACE_FILE, "%s \"R%i\": %i", r ? "," : "", r, REG[r]);
+
+    fprintf(TRACE_FILE, " }, \"flags\": { \"ZF\": %i, \"SF\": %i } }\n", ZF, SF);
+
+    TRACE_STEP_COUNT++;
+
+}
+
+void writeTraceOperands(uint32_t instruction) {
+    // Writes the operands of an instruction to the trace as a JSON array, with registers given by name and immediate
+    // values and addresses as numbers, leaving out the fields which its opcode does not use
+    // The encoding of custom instructions is not known, so they are written with no operands
+
+    uint8_t opcode = getOpcode(instruction);
+    uint32_t reserved = getReservedBits(opcode);
+    uint8_t regOperands = 0;
+    bool valOperand = false;
+    bool first = true;
+
+    if(opcode >= OP_COPY && opcode <= OP_NOT) regOperands = 3;
+    else if(opcode == OP_SET || (opcode >= OP_ADD_IMM && opcode <= OP_STORE)) {
+
+        regOperands = 2;
+        valOperand = true;
+
+    } else if(opcode >= OP_JUMP && opcode <= OP_HALT) valOperand = true;
+
+    fprintf(TRACE_FILE, "[");
+
+    for(int op = 1; op <= regOperands; op++) {
+
+        if(reserved & (0x00F00000 >> (4 * (op - 1)))) continue;
+
+        fprintf(TRACE_FILE, "%s\"%s\"", first ? "" : ", ", getRegisterName(getRegOperand(instruction, op)));
+        first = false;
+
+    }
+
+    if(valOperand && !(reserved & 0x0000FFFF)) fprintf(TRACE_FILE, "%s%i", first ? "" : ", ", getDestOrImmVal(instruction));
+
+    fprintf(TRACE_FILE, "]");
+
+}
+
+void closeTrace() {
+    // Finishes the trace, which is also done when the program stops with an error, so that the trace stays valid JSON
+
+    if(!TRACE_JSONL) fprintf(TRACE_FILE, "\n]\n");
+    fclose(TRACE_FILE);
+
+}
+
+int diffTraces(char* tracefileA, char* tracefileB) {
+    // Aligns two traces step by step and reports the first step at which they differ, returning the exit code of the emulator
+
+    JsonValue* traceA = parseJsonFile(tracefileA);
+    JsonValue* traceB = parseJsonFile(tracefileB);
+
+    if(traceA->type != JSON_ARRAY || traceB->type != JSON_ARRAY) {
+
+        printf("Traces must be JSON arrays of executed instructions.\n");
+        exit(-1);
+
+    }
+
+    checkRegisterKeys(traceA, tracefileA, "step");
+    checkRegisterKeys(traceB, tracefileB, "step");
+
+    uint32_t stepCount = traceA->count < traceB->count ? traceA->count : traceB->count;
+
+    for(uint32_t i = 0; i < stepCount; i++) {
+
+        char* difference = compareTraceSteps(&traceA->items[i], &traceB->items[i]);
+
+        if(!difference) continue;
+
+        printf("Traces diverge at step %u: %s\n", i, difference);
+        printTraceStep(tracefileA, &traceA->items[i]);
+        printTraceStep(tracefileB, &traceB->items[i]);
+
+        if(i) {
+
+            printf("The previous step was:\n");
+            printTraceStep(tracefileA, &traceA->items[i - 1]);
+
+        }
+
+        return 1;
+
+    }
+
+    if(traceA->count != traceB->count) {
+
+        bool aLonger = traceA->count > traceB->count;
+
+        printf("Traces match for %u steps, after which %s ends and %s continues:\n", stepCount,
+            aLonger ? tracefileB : tracefileA, aLonger ? tracefileA : tracefileB);
+        printTraceStep(aLonger ? tracefileA : tracefileB, &(aLonger ? traceA : traceB)->items[stepCount]);
+
+        return 1;
+
+    }
+
+    printf("Traces are identical (%u steps).\n", stepCount);
+
+    return 0;
+
+}
+
+char* compareTraceSteps(JsonValue* stepA, JsonValue* stepB) {
+    // Compares two steps of different traces, returning a description of the first difference, or NULL if they match
+    // Fields which only one of the traces records (such as "instruction" in reference traces) are not compared
+
+    char* fields[] = {"pc", "instruction", "core"};
+
+    for(int f = 0; f < sizeof(fields) / sizeof(char*); f++) {
+
+        JsonValue* a = getJsonMember(stepA, fields[f]);
+        JsonValue* b = getJsonMember(stepB, fields[f]);
+
+        if(a && b && a->number != b->number) {
+
+            char* difference = malloc(MAX_STRING_LEN * sizeof(char));
+            snprintf(difference, MAX_STRING_LEN, "the %s differs", fields[f]);
+
+            return difference;
+
+        }
+
+    }
+
+    char* difference = compareTraceChanges(getJsonMember(stepA, "registers"), getJsonMember(stepB, "registers"), true);
+
+    if(!difference) difference = compareTraceChanges(getJsonMember(stepA, "memory"), getJsonMember(stepB, "memory"), false);
+
+    return difference;
+
+}
+
+char* compareTraceChanges(JsonValue* changesA, JsonValue* changesB, bool registers) {
+    // Compares the register or memory changes of two trace steps, returning a description of the first difference,
+    // or NULL if both change the same registers or addresses to the same values
+
+    int32_t valuesA[MEMORY_SIZE];
+    int32_t valuesB[MEMORY_SIZE];
+    // Value written to each register or address by each step, or -1 if it was not written
+
+    memset(valuesA, -1, sizeof(valuesA));
+    memset(valuesB, -1, sizeof(valuesB));
+
+    for(int i = 0; changesA && i < changesA->count; i++) {
+
+        uint16_t target = registers ? parseRegisterName(changesA->keys[i]) : strtol(changesA->keys[i], NULL, 0);
+        valuesA[target] = (uint16_t) changesA->items[i].number;
+
+    }
+
+    for(int i = 0; changesB && i < changesB->count; i++) {
+
+        uint16_t target = registers ? parseRegisterName(changesB->keys[i]) : strtol(changesB->keys[i], NULL, 0);
+        valuesB[target] = (uint16_t) changesB->items[i].number;
+
+    }
+
+    for(int target = 0; target < (registers ? 0x10 : MEMORY_SIZE); target++) {
+
+        if(valuesA[target] == valuesB[target]) continue;
+
+        char* difference = malloc(MAX_STRING_LEN * sizeof(char));
+
+        if(registers) snprintf(difference, MAX_STRING_LEN, "%s differs", getRegisterName(target));
+        else snprintf(difference, MAX_STRING_LEN, "memory[0x%.4X] differs", target);
+
+        return difference;
+
+    }
+
+    return NULL;
+
+}
+
+void printTraceStep(char* tracefile, JsonValue* step) {
+    // Prints a single step of a trace on one line
+
+    JsonValue* pc = getJsonMember(step, "pc");
+    JsonValue* instruction = getJsonMember(step, "instruction");
+    JsonValue* registers = getJsonMember(step, "registers");
+    JsonValue* memory = getJsonMember(step, "memory");
+
+    printf("  %s:", tracefile);
+
+    if(pc) printf(" pc=0x%.4X", (uint16_t) pc->number);
+    if(instruction) printf(" instruction=0x%.8X", (uint32_t) instruction->number);
+
+    for(int i = 0; registers && i < registers->count; i++) {
+
+        printf(" %s=0x%.4X", getRegisterName(parseRegisterName(registers->keys[i])), (uint16_t) registers->items[i].number);
+
+    }
+
+    for(int i = 0; memory && i < memory->count; i++) {
+
+        printf(" memory[0x%.4lX]=0x%.4X", strtol(memory->keys[i], NULL, 0) & 0xFFFF, (uint16_t) memory->items[i].number);
+
+    }
+
+    putchar('\n');
+
+}
+
+void writeCheckpoint() {
+    // Saves the complete state of the emulator to a new checkpoint file, and deletes the oldest one
+    // Checkpoints hold the raw in-memory state, so they can only be resumed by the same build of the emulator
+
+    char* path = getCheckpointPath(INSTRUCTION_COUNT);
+    char tempPath[MAX_STRING_LEN];
+    FILE* checkpoint;
+
+    snprintf(tempPath, MAX_STRING_LEN, "%s.tmp", path);
+    if(mkdir(CHECKPOINT_DIR, 0755) && errno != EEXIST) {
+
+        printf("Cannot create checkpoint directory %s.\n", CHECKPOINT_DIR);
+        exit(-1);
+
+    }
+
+    if(!(checkpoint = fopen(tempPath, "wb"))) {
+
+        printf("Cannot write checkpoint %s.\n", tempPath);
+        exit(-1);
+
+    }
+
+    uint32_t header[2] = { CHECKPOINT_MAGIC, CHECKPOINT_VERSION };
+
+    fwrite(header, sizeof(uint32_t), 2, checkpoint);
+    fwrite(&PROGRAM_CHECKSUM, sizeof(uint32_t), 1, checkpoint);
+    fwrite(&INSTRUCTION_COUNT, sizeof(uint64_t), 1, checkpoint);
+    fwrite(&CORE_COUNT, sizeof(uint8_t), 1, checkpoint);
+    fwrite(CORES, sizeof(Core), CORE_COUNT, checkpoint);
+    fwrite(&TEST_AND_SET_LOCK, sizeof(uint16_t), 1, checkpoint);
+    fwrite(OPCODE_COUNTS, sizeof(uint64_t), 0x100, checkpoint);
+    fwrite(MEM, sizeof(uint16_t), MEMORY_SIZE, checkpoint);
+
+    if(fclose(checkpoint) || rename(tempPath, path)) {
+
+        printf("Cannot write checkpoint %s.\n", path);
+        exit(-1);
+
+    }
+    // The checkpoint only replaces its final name once it is complete, so an interruption cannot leave a partial one
+
+    char** slot = &CHECKPOINT_PATHS[CHECKPOINT_COUNT++ % CHECKPOINTS_KEPT];
+
+    if(*slot) {
+
+        remove(*slot);
+        free(*slot);
+
+    }
+
+    *slot = path;
+    NEXT_CHECKPOINT = INSTRUCTION_COUNT + CHECKPOINT_INTERVAL;
+
+}
+
+void resumeCheckpoint() {
+    // Restores the state of the emulator from the latest checkpoint in the checkpoint directory
+
+    DIR* dir = opendir(CHECKPOINT_DIR);
+    struct dirent* entry;
+    uint64_t latest = 0;
+
+    while(dir && (entry = readdir(dir))) {
+
+        uint64_t count;
+
+        if(sscanf(entry->d_name, "checkpoint-%lu", &count) == 1 && endsWith(entry->d_name, ".ckpt") && count > latest) latest = count;
+
+    }
+
+    if(dir) closedir(dir);
+
+    if(!latest) {
+
+        printf("No checkpoint found in %s, starting from the beginning.\n", CHECKPOINT_DIR);
+        return;
+
+    }
+
+    char* path = getCheckpointPath(latest);
+    FILE* checkpoint = fopen(path, "rb");
+    uint32_t header[3];
+    uint8_t coreCount;
+
+    bool valid = checkpoint
+        && fread(header, sizeof(uint32_t), 3, checkpoint) == 3
+        && header[0] == CHECKPOINT_MAGIC && header[1] == CHECKPOINT_VERSION
+        && fread(&INSTRUCTION_COUNT, sizeof(uint64_t), 1, checkpoint) == 1
+        && fread(&coreCount, sizeof(uint8_t), 1, checkpoint) == 1 && coreCount == CORE_COUNT
+        && fread(CORES, sizeof(Core), CORE_COUNT, checkpoint) == CORE_COUNT
+        && fread(&TEST_AND_SET_LOCK, sizeof(uint16_t), 1, checkpoint) == 1
+        && fread(OPCODE_COUNTS, sizeof(uint64_t), 0x100, checkpoint) == 0x100
+        && fread(MEM, sizeof(uint16_t), MEMORY_SIZE, checkpoint) == MEMORY_SIZE;
+
+    if(!valid) {
+
+        printf("Checkpoint %s is corrupted, or was made by a different emulator or with a different number of cores.\n", path);
+        exit(-1);
+
+    }
+
+    if(header[2] != PROGRAM_CHECKSUM) {
+
+        printf("Checkpoint %s was made for a different program.\n", path);
+        exit(-1);
+
+    }
+
+    fclose(checkpoint);
+
+    printf("Resuming from checkpoint %s after %lu instructions.\n", path, INSTRUCTION_COUNT);
+
+    NEXT_CHECKPOINT = INSTRUCTION_COUNT + CHECKPOINT_INTERVAL;
+
+    free(path);
+
+}
+
+char* getCheckpointPath(uint64_t instructionCount) {
+    // Gets the path of the checkpoint taken after the given number of instructions
+
+    char* path = malloc(MAX_STRING_LEN * sizeof(char));
+    snprintf(path, MAX_STRING_LEN, "%s/checkpoint-%.12lu.ckpt", CHECKPOINT_DIR, instructionCount);
+
+    return path;
+
+}
+
+void runtimeError(char* format, ...) {
+    // Reports an error in the running program along with the state of the emulator, writes a crash dump if one
+    // was requested with --crash-dump, and terminates the program
+
+    char description[MAX_STRING_LEN];
+    va_list args;
+
+    va_start(args, format);
+    vsnprintf(description, MAX_STRING_LEN, format, args);
+    va_end(args);
+
+    flushConsole();
+
+    char* location = getSymbolLocation(INSTRUCTION_PC);
+
+    SourceLine* source = getSourceLine(INSTRUCTION_PC);
+
+    printf("%s\n", description);
+    if(location) printf("Location: %s\n", location);
+    if(source) printf("Source: %s:%u: %s\n", source->fileName, source->lineNumber, source->text);
+    dumpState(stdout, INSTRUCTION_PC & ~(DUMP_WORDS_PER_ROW - 1), DUMP_WORDS_PER_ROW * 2);
+
+    if(CRASH_DUMP_FILE) writeCrashDump(description);
+
+    exit(-1);
+
+}
+
+void writeCrashDump(char* description) {
+    // Writes the state of the faulting core and all of memory to the crash dump file, to be read by --inspect-dump
+    // Unlike checkpoints, every field is written separately, so dumps can be inspected by any build of the emulator
+
+    FILE* dump;
+
+    if(!(dump = fopen(CRASH_DUMP_FILE, "wb"))) {
+
+        printf("Cannot write crash dump %s.\n", CRASH_DUMP_FILE);
+        return;
+
+    }
+
+    uint32_t header[3] = { CRASH_DUMP_MAGIC, CRASH_DUMP_VERSION, PROGRAM_CHECKSUM };
+    uint8_t core = CORE - CORES;
+    uint8_t flags[2] = { ZF, SF };
+    uint16_t descriptionLen = strnlen(description, MAX_STRING_LEN);
+
+    fwrite(header, sizeof(uint32_t), 3, dump);
+    fwrite(&INSTRUCTION_COUNT, sizeof(uint64_t), 1, dump);
+    fwrite(&core, sizeof(uint8_t), 1, dump);
+    fwrite(REG, sizeof(uint16_t), 0x10, dump);
+    fwrite(&PC, sizeof(uint16_t), 1, dump);
+    fwrite(&INSTRUCTION_PC, sizeof(uint16_t), 1, dump);
+    fwrite(&IR, sizeof(uint32_t), 1, dump);
+    fwrite(flags, sizeof(uint8_t), 2, dump);
+    fwrite(&descriptionLen, sizeof(uint16_t), 1, dump);
+    fwrite(description, sizeof(char), descriptionLen, dump);
+    fwrite(MEM, sizeof(uint16_t), MEMORY_SIZE, dump);
+
+    if(fclose(dump)) {
+
+        printf("Cannot write crash dump %s.\n", CRASH_DUMP_FILE);
+        return;
+
+    }
+
+    printf("Crash dump written to %s.\n", CRASH_DUMP_FILE);
+
+}
+
+void memoryDumpEvent(Event* e) {
+    // Event handler which writes the memory dump requested with --dump-memory once the program halts
+
+    if(e->type == EVENT_HALT) writeMemoryDump();
+
+}
+
+void writeMemoryDump() {
+    // Writes the window of memory given by --dump-start and --dump-words to the memory dump file
+    // Files ending in .hex get the same table of hex words as crash dumps, and any other file gets the raw big-endian
+    // words, in the same byte order as executables
+
+    FILE* dump;
+    bool hex = endsWith(MEMORY_DUMP_FILE, ".hex");
+
+    if(!(dump = fopen(MEMORY_DUMP_FILE, hex ? "w" : "wb"))) {
+
+        printf("Cannot write memory dump %s.\n", MEMORY_DUMP_FILE);
+        return;
+
+    }
+
+    uint32_t memLen = MEMORY_DUMP_WORDS;
+
+    if(MEMORY_DUMP_START + memLen > MEMORY_SIZE) memLen = MEMORY_SIZE - MEMORY_DUMP_START;
+
+    if(hex) dumpMemory(dump, MEMORY_DUMP_START, memLen);
+    else {
+
+        for(uint32_t addr = MEMORY_DUMP_START; addr < MEMORY_DUMP_START + memLen; addr++) {
+
+            fputc(MEM[addr] >> 8, dump);
+            fputc(MEM[addr] & 0xFF, dump);
+
+        }
+
+    }
+
+    if(fclose(dump)) printf("Cannot write memory dump %s.\n", MEMORY_DUMP_FILE);
+
+}
+
+void inspectCrashDump(char* dumpfile, uint16_t memStart, uint32_t memLen) {
+    // Prints the error, registers, flags, and the given window of memory saved in a crash dump
+    // If no window is given, the memory around the faulting instruction is shown
+
+    FILE* dump;
+
+    if(!(dump = fopen(dumpfile, "rb"))) {
+
+        printf("File %s does not exist.\n", dumpfile);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    if(!(MEM = calloc(MEMORY_SIZE, sizeof(uint16_t)))) {
+
+        printf("Internal error: cannot allocate memory for the crash dump\n");
+        exit(-2);
+
+    }
+
+    uint32_t header[3];
+    uint64_t instructionCount;
+    uint8_t core;
+    uint8_t flags[2];
+    uint16_t descriptionLen;
+    char description[MAX_STRING_LEN];
+
+    bool valid = fread(header, sizeof(uint32_t), 3, dump) == 3
+        && header[0] == CRASH_DUMP_MAGIC && header[1] == CRASH_DUMP_VERSION
+        && fread(&instructionCount, sizeof(uint64_t), 1, dump) == 1
+        && fread(&core, sizeof(uint8_t), 1, dump) == 1
+        && fread(REG, sizeof(uint16_t), 0x10, dump) == 0x10
+        && fread(&PC, sizeof(uint16_t), 1, dump) == 1
+        && fread(&INSTRUCTION_PC, sizeof(uint16_t), 1, dump) == 1
+        && fread(&IR, sizeof(uint32_t), 1, dump) == 1
+        && fread(flags, sizeof(uint8_t), 2, dump) == 2
+        && fread(&descriptionLen, sizeof(uint16_t), 1, dump) == 1 && descriptionLen < MAX_STRING_LEN
+        && fread(description, sizeof(char), descriptionLen, dump) == descriptionLen
+        && fread(MEM, sizeof(uint16_t), MEMORY_SIZE, dump) == MEMORY_SIZE;
+
+    fclose(dump);
+
+    if(!valid) {
+
+        printf("File %s is not a crash dump, or is truncated.\n", dumpfile);
+        exit(-1);
+
+    }
+
+    description[descriptionLen] = '\0';
+    ZF = flags[0];
+    SF = flags[1];
+
+    printf("Crash dump of program with CRC-32 0x%.8X, taken on core %i after %lu instructions:\n", header[2], core, instructionCount);
+    printf("%s\n", description);
+    printf("Faulting instruction: 0x%.8X (%s) at PC address 0x%.4X\n", IR, getOpcodeName(getOpcode(IR)), INSTRUCTION_PC);
+
+    char* location = getSymbolLocation(INSTRUCTION_PC);
+    if(location) printf("Location: %s\n", location);
+
+    SourceLine* source = getSourceLine(INSTRUCTION_PC);
+    if(source) printf("Source: %s:%u: %s\n", source->fileName, source->lineNumber, source->text);
+
+    if(!memLen) {
+
+        memStart = INSTRUCTION_PC & ~(DUMP_WORDS_PER_ROW - 1);
+        memLen = DUMP_WORDS_PER_ROW * 2;
+
+    }
+
+    dumpState(stdout, memStart, memLen);
+
+}
+
+void readSymbolFile(char* symfile) {
+    // Loads the labels from a .sym file written by the assembler into the symbol table
+    // The file starts with a "SMIS-SYMBOLS <version>" header line, followed by one "<address> <kind> <line> <name>"
+    // line per label, where the kind is "func" or "label", and lines starting with '#' are comments
+
+    FILE* symFile;
+
+    if(!(symFile = fopen(symfile, "r"))) {
+
+        printf("File %s does not exist.\n", symfile);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    char line[MAX_STRING_LEN];
+    int version = 0;
+    int lineNumber = 1;
+
+    if(!fgets(line, MAX_STRING_LEN, symFile) || sscanf(line, "SMIS-SYMBOLS %i", &version) != 1) {
+
+        printf("File %s is not a symbol file.\n", symfile);
+        exit(-1);
+
+    }
+
+    if(version < 1 || version > SYMBOL_FILE_VERSION) {
+
+        printf("Symbol file %s has unsupported version %i.\n", symfile, version);
+        exit(-1);
+
+    }
+
+    while(fgets(line, MAX_STRING_LEN, symFile)) {
+
+        unsigned int addr;
+        unsigned int sourceLine;
+        char kind[MAX_STRING_LEN];
+        int nameStart = 0;
+
+        lineNumber++;
+        line[strcspn(line, "\n")] = '\0';
+
+        if(!*line || *line == '#') continue;
+
+        if(sscanf(line, "%x %s %u %n", &addr, kind, &sourceLine, &nameStart) != 3 || !nameStart || !line[nameStart]
+            || addr >= MEMORY_SIZE || (strncmp(kind, "func", MAX_STRING_LEN) && strncmp(kind, "label", MAX_STRING_LEN))) {
+
+            printf("Invalid symbol at line %i of %s\n", lineNumber, symfile);
+            printf("Symbol: %s\n", line);
+            exit(-1);
+
+        }
+
+        SYMBOL_TABLE = realloc(SYMBOL_TABLE, (SYMBOL_COUNT + 1) * sizeof(Symbol));
+        SYMBOL_TABLE[SYMBOL_COUNT].name = strndup(line + nameStart, MAX_STRING_LEN);
+        SYMBOL_TABLE[SYMBOL_COUNT].PCAddress = addr;
+        SYMBOL_COUNT++;
+
+    }
+
+    fclose(symFile);
+
+}
+
+char* getSymbolLocation(uint16_t addr) {
+    // Describes an address by the closest label at or before it, such as "loop" or "loop+4"
+    // Returns NULL if no symbol file was loaded or no label comes before the address
+
+    Symbol* closest = NULL;
+
+    for(int i = 0; i < SYMBOL_COUNT; i++) {
+
+        Symbol* sym = &SYMBOL_TABLE[i];
+        if(sym->PCAddress <= addr && (!closest || sym->PCAddress > closest->PCAddress)) closest = sym;
+
+    }
+
+    if(!closest) return NULL;
+
+    char* location = malloc(MAX_STRING_LEN * sizeof(char));
+
+    if(closest->PCAddress == addr) snprintf(location, MAX_STRING_LEN, "%s", closest->name);
+    else snprintf(location, MAX_STRING_LEN, "%s+%i", closest->name, addr - closest->PCAddress);
+
+    return location;
+
+}
+
+void readDebugInfo(char* debugfile) {
+    // Loads the source line of each instruction from a .dbg file written by the assembler
+    // The file starts with a "SMIS-DEBUG <version>" header line, followed by one "file <index> <path>" line per
+    // source file and one "<address> <file index> <line> <source>" line per instruction, and lines starting with '#'
+    // are comments
+
+    FILE* debugFile;
+
+    if(!(debugFile = fopen(debugfile, "r"))) {
+
+        printf("File %s does not exist.\n", debugfile);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    char line[MAX_STRING_LEN];
+    int version = 0;
+    int lineNumber = 1;
+    char** files = NULL;
+    uint32_t fileCount = 0;
+
+    if(!fgets(line, MAX_STRING_LEN, debugFile) || sscanf(line, "SMIS-DEBUG %i", &version) != 1) {
+
+        printf("File %s is not a debug info file.\n", debugfile);
+        exit(-1);
+
+    }
+
+    if(version < 1 || version > DEBUG_FILE_VERSION) {
+
+        printf("Debug info file %s has unsupported version %i.\n", debugfile, version);
+        exit(-1);
+
+    }
+
+    while(fgets(line, MAX_STRING_LEN, debugFile)) {
+
+        unsigned int addr;
+        unsigned int fileIndex;
+        unsigned int sourceLine;
+        int textStart = 0;
+
+        lineNumber++;
+        line[strcspn(line, "\n")] = '\0';
+
+        if(!*line || *line == '#') continue;
+
+        if(sscanf(line, "file %u %n", &fileIndex, &textStart) == 1 && textStart && line[textStart] && fileIndex == fileCount) {
+
+            files = realloc(files, (fileCount + 1) * sizeof(char*));
+            files[fileCount++] = strndup(line + textStart, MAX_STRING_LEN);
+            continue;
+
+        }
+
+        if(sscanf(line, "%x %u %u %n", &addr, &fileIndex, &sourceLine, &textStart) != 3 || !textStart || !line[textStart]
+            || addr >= MEMORY_SIZE || fileIndex >= fileCount) {
+
+            printf("Invalid debug info at line %i of %s\n", lineNumber, debugfile);
+            printf("Line: %s\n", line);
+            exit(-1);
+
+        }
+
+        SOURCE_LINES = realloc(SOURCE_LINES, (SOURCE_LINE_COUNT + 1) * sizeof(SourceLine));
+        SOURCE_LINES[SOURCE_LINE_COUNT].PCAddress = addr;
+        SOURCE_LINES[SOURCE_LINE_COUNT].fileName = files[fileIndex];
+        SOURCE_LINES[SOURCE_LINE_COUNT].lineNumber = sourceLine;
+        SOURCE_LINES[SOURCE_LINE_COUNT].text = strndup(line + textStart, MAX_STRING_LEN);
+        SOURCE_LINE_COUNT++;
+
+    }
+
+    free(files);
+    // The file names themselves are still used by the source lines
+    fclose(debugFile);
+
+}
+
+bool findSymbolAddress(char* name, uint16_t* addr) {
+    // Gets the address of the label with the given name from the symbol file
+    // Returns false if no symbol file was loaded or it has no such label
+
+    for(int i = 0; i < SYMBOL_COUNT; i++) {
+
+        if(strncmp(SYMBOL_TABLE[i].name, name, MAX_STRING_LEN)) continue;
+
+        *addr = SYMBOL_TABLE[i].PCAddress;
+        return true;
+
+    }
+
+    return false;
+
+}
+
+SourceLine* getSourceLine(uint16_t addr) {
+    // Gets the source line of the instruction at the given address
+    // Returns NULL if no debug info file was loaded or the address has no source line
+
+    for(int i = 0; i < SOURCE_LINE_COUNT; i++) if(SOURCE_LINES[i].PCAddress == addr) return &SOURCE_LINES[i];
+
+    return NULL;
+
+}
+
+#ifdef SMIS_LUA
+
+int scriptCheckRegister(lua_State* L, int arg) {
+    // Gets a register number argument of a script API function, raising a script error if it is out of range
+
+    lua_Integer reg = luaL_checkinteger(L, arg);
+    luaL_argcheck(L, reg >= 0 && reg < 0x10, arg, "register number must be between 0 and 15");
+
+    return reg;
+
+}
+
+int scriptReg(lua_State* L) {
+
+    lua_pushinteger(L, getRegister(scriptCheckRegister(L, 1)));
+    return 1;
+
+}
+
+int scriptSetReg(lua_State* L) {
+
+    setRegister(scriptCheckRegister(L, 1), luaL_checkinteger(L, 2));
+    return 0;
+
+}
+
+int scriptMem(lua_State* L) {
+
+    lua_pushinteger(L, MEM[(uint16_t) luaL_checkinteger(L, 1)]);
+    return 1;
+
+}
+
+int scriptSetMem(lua_State* L) {
+
+    MEM[(uint16_t) luaL_checkinteger(L, 1)] = luaL_checkinteger(L, 2);
+    return 0;
+
+}
+
+int scriptPC(lua_State* L) {
+
+    lua_pushinteger(L, PC);
+    return 1;
+
+}
+
+int scriptSetPC(lua_State* L) {
+
+    PC = luaL_checkinteger(L, 1) & ~1;
+    return 0;
+
+}
+
+int scriptCore(lua_State* L) {
+
+    lua_pushinteger(L, CORE - CORES);
+    return 1;
+
+}
+
+int scriptInstructions(lua_State* L) {
+
+    lua_pushinteger(L, INSTRUCTION_COUNT);
+    return 1;
+
+}
+
+int scriptStop(lua_State* L) {
+
+    for(int c = 0; c < CORE_COUNT; c++) CORES[c].halted = true;
+    return 0;
+
+}
+
+const luaL_Reg SCRIPT_API[] = {
+
+    {"reg", scriptReg},
+    {"set_reg", scriptSetReg},
+    {"mem", scriptMem},
+    {"set_mem", scriptSetMem},
+    {"pc", scriptPC},
+    {"set_pc", scriptSetPC},
+    {"core", scriptCore},
+    {"instructions", scriptInstructions},
+    {"stop", scriptStop},
+    {NULL, NULL}
+
+};
+// Functions available to scripts in the "smis" table, which act on the core that caused the current hook
+// Memory is accessed directly, so scripts neither trigger devices nor cause memory write events
+
+#endif
+
+void loadScript(char* scriptfile) {
+    // Runs a Lua script and registers its hooks, which are the global functions on_instruction(pc, instruction),
+    // on_memory_write(addr, old, new), and on_halt() if the script defines them
+
+#ifdef SMIS_LUA
+
+    SCRIPT = luaL_newstate();
+    luaL_openlibs(SCRIPT);
+
+    luaL_newlib(SCRIPT, SCRIPT_API);
+    lua_setglobal(SCRIPT, "smis");
+
+    if(luaL_dofile(SCRIPT, scriptfile)) {
+
+        printf("Script error: %s\n", lua_tostring(SCRIPT, -1));
+        exit(-1);
+
+    }
+
+    addEventHandler(scriptEvent);
+
+#else
+
+    printf("This emulator was built without scripting support, rebuild it with -DSMIS_LUA to use --script.\n");
+    exit(-1);
+
+#endif
+
+}
+
+void scriptEvent(Event* e) {
+    // Event handler which calls the matching hook of the script, if it defines one
+
+#ifdef SMIS_LUA
+
+    char* hook;
+
+    switch(e->type) {
+
+        case EVENT_INSTRUCTION_EXECUTED:
+            hook = "on_instruction"; break;
+        case EVENT_MEMORY_WRITE:
+            hook = "on_memory_write"; break;
+        case EVENT_HALT:
+            hook = "on_halt"; break;
+        default:
+            return;
+
+    }
+
+    if(lua_getglobal(SCRIPT, hook) != LUA_TFUNCTION) {
+
+        lua_pop(SCRIPT, 1);
+        return;
+
+    }
+
+    int argCount = 0;
+
+    if(e->type == EVENT_INSTRUCTION_EXECUTED) {
+
+        lua_pushinteger(SCRIPT, e->PCAddress);
+        lua_pushinteger(SCRIPT, e->instruction);
+        argCount = 2;
+
+    } else if(e->type == EVENT_MEMORY_WRITE) {
+
+        lua_pushinteger(SCRIPT, e->target);
+        lua_pushinteger(SCRIPT, e->oldVal);
+        lua_pushinteger(SCRIPT, e->newVal);
+        argCount = 3;
+
+    }
+
+    if(lua_pcall(SCRIPT, argCount, 0, 0)) {
+
+        printf("Script error in %s at PC address 0x%.4X: %s\n", hook, e->PCAddress, lua_tostring(SCRIPT, -1));
+        exit(-1);
+
+    }
+
+#endif
+
+}
+
+void runDebugger() {
+    // Runs the program under an interactive prompt which reads commands from stdin, starting paused before the
+    // first instruction and staying at the prompt once the program halts, until it is told to quit
+
+    char line[MAX_STRING_LEN];
+
+    printf("Type \"help\" for a list of debugger commands.\n");
+    printDebuggerLocation();
+
+    addEventHandler(debuggerEvent);
+
+    while(true) {
+
+        flushConsole();
+        printf("(smis) ");
+        fflush(stdout);
+
+        if(!fgets(line, MAX_STRING_LEN, stdin)) break;
+
+        char* command = strtok(line, " \t\n");
+        char* args[3] = { NULL, NULL, NULL };
+        long val, len;
+
+        if(!command) continue;
+
+        for(int a = 0; a < 3 && (args[a] = strtok(NULL, " \t\n")); a++);
+
+        if(!strcmp(command, "step") || !strcmp(command, "s")) {
+
+            if(args[1]) printf("step takes at most one argument, the number of instructions.\n");
+            else if(!args[0] || parseDebuggerArg(args[0], LONG_MAX, &val)) {
+
+                runUntilBreakpoint(args[0] ? val : 1);
+                printDebuggerLocation();
+
+            }
+
+        } else if(!strcmp(command, "continue") || !strcmp(command, "c")) {
+
+            runUntilBreakpoint(-1);
+            printDebuggerLocation();
+
+        } else if(!strcmp(command, "break") || !strcmp(command, "b")) {
+
+            uint16_t addr;
+
+            if(!args[0] || args[1]) printf("break takes an address or label, such as \"break 0x40\" or \"break main_loop\".\n");
+            else if(parseDebuggerAddress(args[0], &addr)) {
+
+                if(addr % 2) printf("Address 0x%.4X is not aligned to an instruction.\n", addr);
+                else addBreakpoint(BREAK_INSTRUCTION, addr, 1);
+
+            }
+
+        } else if(!strcmp(command, "watch") || !strcmp(command, "rwatch") || !strcmp(command, "awatch")) {
+
+            uint16_t addr;
+            uint8_t reg;
+            uint8_t kind = *command == 'w' ? WATCH_WRITE : *command == 'r' ? WATCH_READ : WATCH_ACCESS;
+
+            if(args[0] && parseDebuggerRegister(args[0], &reg)) {
+
+                if(args[1] || kind != WATCH_WRITE) printf("Registers are watched with \"watch <register>\", which stops whenever its value changes.\n");
+                else addBreakpoint(WATCH_REGISTER, reg, 1);
+
+            } else if(!args[0] || args[2]) printf("%s takes an address or label and optionally a number of words, such as \"%s 0x1000 4\".\n", command, command);
+            else if(parseDebuggerAddress(args[0], &addr) && (!args[1] || parseDebuggerArg(args[1], MEMORY_SIZE - addr, &len))) {
+
+                if(args[1] && !len) printf("A watchpoint must cover at least one word.\n");
+                else addBreakpoint(kind, addr, args[1] ? len : 1);
+
+            }
+
+        } else if(!strcmp(command, "delete") || !strcmp(command, "disable") || !strcmp(command, "enable")) {
+
+            Breakpoint* b;
+
+            if(args[1]) printf("%s takes at most one argument, the number of a breakpoint.\n", command);
+            else if(!args[0]) {
+
+                for(uint32_t i = 0; i < BREAKPOINT_COUNT; i++) BREAKPOINTS[i].enabled = !strcmp(command, "enable");
+                if(!strcmp(command, "delete")) BREAKPOINT_COUNT = 0;
+                // Without a number, the command applies to every breakpoint
+
+            } else if(parseDebuggerArg(args[0], UINT32_MAX, &val)) {
+
+                if(!(b = findBreakpoint(val))) printf("There is no breakpoint %li.\n", val);
+                else if(!strcmp(command, "delete")) deleteBreakpoint(val);
+                else b->enabled = !strcmp(command, "enable");
+
+            }
+
+        } else if(!strcmp(command, "list") || !strcmp(command, "l")) listBreakpoints();
+        else if(!strcmp(command, "registers") || !strcmp(command, "r")) printRegisters();
+        else if(!strcmp(command, "mem") || !strcmp(command, "m")) {
+
+            if(!args[0] || args[2]) printf("mem takes an address and optionally a number of words, such as \"mem 0x1000 16\".\n");
+            else if(parseDebuggerArg(args[0], MEMORY_SIZE - 1, &val) && (!args[1] || parseDebuggerArg(args[1], MEMORY_SIZE, &len))) {
+
+                dumpMemory(stdout, val, args[1] ? len : DUMP_WORDS_PER_ROW * 2);
+
+            }
+
+        } else if(!strcmp(command, "quit") || !strcmp(command, "q")) break;
+        else if(!strcmp(command, "help") || !strcmp(command, "h")) printDebuggerHelp();
+        else printf("Unknown command %s, type \"help\" for a list of debugger commands.\n", command);
+
+    }
+
+    flushConsole();
+    free(MEM);
+
+}
+
+void runSingleSteps() {
+    // Runs the program one instruction on each core at a time, printing the next instruction and the registers and
+    // waiting for Enter before each one, without the rest of the debugger
+    // Once stdin ends, the rest of the program runs without stopping
+
+    char line[MAX_STRING_LEN];
+    bool paused = true;
+
+    do {
+
+        if(!paused) continue;
+
+        printRegisters();
+        printDebuggerLocation();
+
+        flushConsole();
+        fflush(stdout);
+
+        paused = fgets(line, MAX_STRING_LEN, stdin);
+
+    } while(executeRound());
+
+}
+
+void printRegisters() {
+    // Prints the registers, flags, PC, and last instruction of each core
+
+    for(int c = 0; c < CORE_COUNT; c++) {
+
+        CORE = &CORES[c];
+
+        if(CORE_COUNT > 1) printf("Core %i:\n", c);
+        dumpState(stdout, 0, 0);
+
+    }
+
+    CORE = &CORES[0];
+
+}
+
+bool parseDebuggerArg(char* arg, long max, long* val) {
+    // Parses a decimal or 0x-prefixed hexadecimal argument of a debugger command, which must not be larger than max
+    // Returns false after printing an error if it is not a number in that range
+
+    char* end;
+
+    *val = strtol(arg, &end, 0);
+
+    if(*end || end == arg || *val < 0) {
+
+        printf("Argument %s is not a number.\n", arg);
+        return false;
+
+    }
+
+    if(*val > max) {
+
+        printf("Argument %s is too large, since the largest allowed is %li.\n", arg, max);
+        return false;
+
+    }
+
+    return true;
+
+}
+
+bool parseDebuggerRegister(char* arg, uint8_t* reg) {
+    // Parses a register argument of a debugger command, which may be its special name (such as RSP) or its plain name
+    // (such as R15)
+    // Returns false if the argument is not a register
+
+    *reg = parseRegisterName(arg);
+
+    return *reg != INVALID_REGISTER;
+
+}
+
+bool parseDebuggerAddress(char* arg, uint16_t* addr) {
+    // Parses the address argument of a debugger command, which may also be the name of a label from the symbol file
+    // Returns false after printing an error if it is neither
+
+    long val;
+
+    if(findSymbolAddress(arg, addr)) return true;
+
+    if(*arg < '0' || *arg > '9') {
+
+        printf("There is no label named %s%s.\n", arg, SYMBOL_COUNT ? "" : ", since labels can only be used with --symbols");
+        return false;
+
+    }
+
+    if(!parseDebuggerArg(arg, MEMORY_SIZE - 1, &val)) return false;
+
+    *addr = val;
+
+    return true;
+
+}
+
+void runUntilBreakpoint(long rounds) {
+    // Executes the given number of rounds on each core, or runs until the program halts if it is negative, stopping
+    // early once a core is about to execute an instruction with an enabled breakpoint
+    // The breakpoints are not checked before the first round, so that execution can continue from one
+
+    for(long i = 0; rounds < 0 || i < rounds; i++) {
+
+        for(uint32_t b = 0; i && b < BREAKPOINT_COUNT; b++) {
+
+            if(!BREAKPOINTS[b].enabled || BREAKPOINTS[b].kind != BREAK_INSTRUCTION) continue;
+
+            for(int c = 0; c < CORE_COUNT; c++) {
+
+                if(CORES[c].halted || CORES[c].programCounter != BREAKPOINTS[b].addr) continue;
+
+                printf("Breakpoint %u reached\n", BREAKPOINTS[b].number);
+                return;
+
+            }
+
+        }
+
+        if(!executeRound()) return;
+
+        if(DEBUGGER_STOP_REQUESTED) {
+
+            DEBUGGER_STOP_REQUESTED = false;
+            return;
+
+        }
+
+    }
+
+}
+
+void addBreakpoint(uint8_t kind, uint16_t addr, uint32_t length) {
+    // Sets a new breakpoint or watchpoint, which starts out enabled
+
+    BREAKPOINTS = realloc(BREAKPOINTS, (BREAKPOINT_COUNT + 1) * sizeof(Breakpoint));
+    BREAKPOINTS[BREAKPOINT_COUNT++] = (Breakpoint) { NEXT_BREAKPOINT_NUMBER, kind, addr, length, true };
+
+    if(kind == BREAK_INSTRUCTION) printf("Breakpoint %u at 0x%.4X\n", NEXT_BREAKPOINT_NUMBER++, addr);
+    else if(kind == WATCH_REGISTER) printf("Watchpoint %u on %s\n", NEXT_BREAKPOINT_NUMBER++, getRegisterName(addr));
+    else if(length == 1) printf("Watchpoint %u on 0x%.4X\n", NEXT_BREAKPOINT_NUMBER++, addr);
+    else printf("Watchpoint %u on 0x%.4X-0x%.4X\n", NEXT_BREAKPOINT_NUMBER++, addr, addr + length - 1);
+
+}
+
+void debuggerEvent(Event* e) {
+    // Event handler which reports the memory accesses and register changes caught by a watchpoint, and asks the
+    // debugger to stop after them
+
+    if(e->type == EVENT_REGISTER_WRITE) {
+
+        for(uint32_t i = 0; i < BREAKPOINT_COUNT; i++) {
+
+            Breakpoint* b = &BREAKPOINTS[i];
+
+            if(!b->enabled || b->kind != WATCH_REGISTER || e->target != b->addr) continue;
+
+            printf("Watchpoint %u: %s changed by %s at 0x%.4X, 0x%.4X -> 0x%.4X\n", b->number, getRegisterName(e->target),
+                getOpcodeName(getOpcode(e->instruction)), e->PCAddress, e->oldVal, e->newVal);
+
+            DEBUGGER_STOP_REQUESTED = true;
+            return;
+
+        }
+
+    }
+    // Register writes are only reported once the instruction has finished, and only if the value has changed
+
+    if(e->type != EVENT_MEMORY_WRITE && e->type != EVENT_MEMORY_READ) return;
+
+    uint8_t access = e->type == EVENT_MEMORY_WRITE ? WATCH_WRITE : WATCH_READ;
+
+    for(uint32_t i = 0; i < BREAKPOINT_COUNT; i++) {
+
+        Breakpoint* b = &BREAKPOINTS[i];
+
+        if(!b->enabled || !(b->kind & access) || e->target < b->addr || e->target >= b->addr + b->length) continue;
+
+        printf("Watchpoint %u: 0x%.4X %s by %s at 0x%.4X", b->number, e->target, access == WATCH_WRITE ? "written" : "read",
+            getOpcodeName(getOpcode(e->instruction)), e->PCAddress);
+
+        if(access == WATCH_WRITE) printf(", 0x%.4X -> 0x%.4X\n", e->oldVal, e->newVal);
+        else printf(", value 0x%.4X\n", e->newVal);
+
+        DEBUGGER_STOP_REQUESTED = true;
+        return;
+
+    }
+
+}
+
+Breakpoint* findBreakpoint(uint32_t number) {
+    // Gets the breakpoint with the given number
+    // Returns NULL if there is no such breakpoint
+
+    for(uint32_t i = 0; i < BREAKPOINT_COUNT; i++) if(BREAKPOINTS[i].number == number) return &BREAKPOINTS[i];
+
+    return NULL;
+
+}
+
+void deleteBreakpoint(uint32_t number) {
+    // Removes the breakpoint with the given number, keeping the others in the order they were set
+
+    Breakpoint* b = findBreakpoint(number);
+    uint32_t index = b - BREAKPOINTS;
+
+    memmove(b, b + 1, (BREAKPOINT_COUNT - index - 1) * sizeof(Breakpoint));
+    BREAKPOINT_COUNT--;
+
+}
+
+void listBreakpoints() {
+    // Prints every breakpoint and watchpoint with its number, whether it is enabled, its address, and its label if known
+
+    if(!BREAKPOINT_COUNT) {
+
+        printf("No breakpoints are set.\n");
+        return;
+
+    }
+
+    char* kindNames[5] = { "break", "watch", "rwatch", "awatch", "watch" };
+    char range[MAX_STRING_LEN];
+
+    printf("Num  Type    Enabled  Address        Location\n");
+
+    for(uint32_t i = 0; i < BREAKPOINT_COUNT; i++) {
+
+        Breakpoint* b = &BREAKPOINTS[i];
+        char* location = b->kind == WATCH_REGISTER ? NULL : getSymbolLocation(b->addr);
+
+        if(b->kind == WATCH_REGISTER) snprintf(range, MAX_STRING_LEN, "%s", getRegisterName(b->addr));
+        else if(b->length == 1) snprintf(range, MAX_STRING_LEN, "0x%.4X", b->addr);
+        else snprintf(range, MAX_STRING_LEN, "0x%.4X-0x%.4X", b->addr, b->addr + b->length - 1);
+
+        printf("%-4u %-7s %-8s %-14s %s\n", b->number, kindNames[b->kind], b->enabled ? "yes" : "no", range, location ? location : "");
+
+        free(location);
+
+    }
+
+}
+
+void printDebuggerLocation() {
+    // Prints the next instruction of each core which has not halted, along with its label and source line if known
+
+    bool halted = true;
+
+    for(int c = 0; c < CORE_COUNT; c++) {
+
+        CORE = &CORES[c];
+
+        if(CORE->halted) continue;
+
+        uint32_t instruction = MEM[PC] << 16 | MEM[PC + 1];
+        char* location = getSymbolLocation(PC);
+        SourceLine* source = getSourceLine(PC);
+
+        if(CORE_COUNT > 1) printf("Core %i ", c);
+
+        printf("0x%.4X", PC);
+        if(location) printf(" <%s>", location);
+        printf(": 0x%.8X %s\n", instruction, getOpcodeName(getOpcode(instruction)));
+
+        if(source) printf("    %s:%u: %s\n", source->fileName, source->lineNumber, source->text);
+
+        free(location);
+        halted = false;
+
+    }
+
+    CORE = &CORES[0];
+
+    if(halted) printf("The program has halted after %lu instructions.\n", INSTRUCTION_COUNT);
+
+}
+
+void printDebuggerHelp() {
+    // Prints the commands understood by the debugger prompt
+
+    printf("step [n]         (s) Execute the next n instructions (default 1) on each core\n");
+    printf("continue         (c) Run until the program halts or reaches a breakpoint or watchpoint\n");
+    printf("break <addr>     (b) Stop before the instruction at addr, which may be a label with --symbols\n");
+    printf("watch <addr> [n]     Stop after a write to the n words starting at addr (default 1)\n");
+    printf("watch <register>     Stop after an instruction changes the value of the register\n");
+    printf("rwatch <addr> [n]    Stop after a read of the n words starting at addr (default 1)\n");
+    printf("awatch <addr> [n]    Stop after a read or write of the n words starting at addr (default 1)\n");
+    printf("delete [n]           Delete breakpoint or watchpoint n, or all of them\n");
+    printf("disable [n]          Disable breakpoint or watchpoint n, or all of them\n");
+    printf("enable [n]           Enable breakpoint or watchpoint n, or all of them\n");
+    printf("list             (l) List the breakpoints and watchpoints\n");
+    printf("registers        (r) Print the registers, flags, and PC of each core\n");
+    printf("mem <addr> [n]   (m) Print n words of memory starting at addr (default 16)\n");
+    printf("quit             (q) End the program\n");
+    printf("Numbers may be decimal or 0x-prefixed hexadecimal.\n");
+
+}
+
+RunResult* runWithLimits(char* binfile, uint64_t instructionLimit, char* input) {
+    // Runs a program with the given console input (or none) until it halts or has executed the given number of
+    // instructions (0 for no limit), and returns its final state, which must be released with freeRunResult()
+    // The program runs in a child process, so that it starts from a clean emulator state and its errors cannot take
+    // down the caller
+
+    RunResult* result = calloc(1, sizeof(RunResult));
+    FILE* messages = tmpfile();
+    // Captures everything the emulator itself prints, such as runtime errors
+    FILE* output = tmpfile();
+    FILE* state = tmpfile();
+
+    fflush(NULL);
+    // Anything still buffered would otherwise be written a second time by the child
+
+    pid_t pid = fork();
+
+    if(pid < 0) {
+
+        printf("Cannot start a process to run %s.\n", binfile);
+        exit(-1);
+
+    }
+
+    if(!pid) {
+
+        dup2(fileno(messages), STDOUT_FILENO);
+
+        CONSOLE_IN = tmpfile();
+        CONSOLE_OUT = output;
+
+        if(input) fputs(input, CONSOLE_IN);
+        rewind(CONSOLE_IN);
+
+        RUN_STATE = state;
+        RUN_INSTRUCTION_LIMIT = instructionLimit;
+        addEventHandler(runEvent);
+
+        loadProgram(binfile);
+        clock_gettime(CLOCK_MONOTONIC, &LAST_CONSOLE_FLUSH);
+
+        executeProgram();
+
+        finishRun(RUN_STOPPED);
+        // executeProgram() only returns if no core reached a HALT instruction
+
+    }
+
+    int status;
+    waitpid(pid, &status, 0);
+
+    rewind(state);
+
+    if(WIFEXITED(status) && !WEXITSTATUS(status) && fread(&result->haltReason, sizeof(HaltReason), 1, state) == 1) {
+
+        result->memory = malloc(MEMORY_SIZE * sizeof(uint16_t));
+
+        if(fread(result->registers, sizeof(uint16_t), 0x10, state) != 0x10
+            || fread(&result->programCounter, sizeof(uint16_t), 1, state) != 1
+            || fread(&result->instructionCount, sizeof(uint64_t), 1, state) != 1
+            || fread(result->memory, sizeof(uint16_t), MEMORY_SIZE, state) != MEMORY_SIZE) {
+
+            printf("Internal error: the final state of %s is incomplete\n", binfile);
+            exit(-2);
+
+        }
+
+    } else {
+
+        char line[MAX_STRING_LEN] = "";
+
+        if(WIFEXITED(status)) {
+
+            rewind(messages);
+            while(fgets(line, MAX_STRING_LEN, messages) && *line == '\n');
+            line[strcspn(line, "\n")] = '\0';
+            // The first line printed by the emulator describes the error
+
+        } else snprintf(line, MAX_STRING_LEN, "signal %i", WTERMSIG(status));
+
+        result->haltReason = WIFEXITED(status) ? RUN_ERROR : RUN_CRASHED;
+        result->error = strndup(line, MAX_STRING_LEN);
+
+    }
+
+    fseek(output, 0, SEEK_END);
+    long len = ftell(output);
+    rewind(output);
+
+    result->output = calloc(len + 1, sizeof(char));
+    if(fread(result->output, 1, len, output) != len) result->output[0] = '\0';
+
+    fclose(messages);
+    fclose(output);
+    fclose(state);
+
+    return result;
+
+}
+
+void freeRunResult(RunResult* result) {
+    // Releases the result of runWithLimits()
+
+    free(result->memory);
+    free(result->output);
+    free(result->error);
+    free(result);
+
+}
+
+void runEvent(Event* e) {
+    // Event handler which enforces the instruction limit of runWithLimits() and records the final state once the program halts
+
+    if(e->type == EVENT_INSTRUCTION_EXECUTED && RUN_INSTRUCTION_LIMIT && INSTRUCTION_COUNT > RUN_INSTRUCTION_LIMIT) finishRun(RUN_LIMIT_REACHED);
+
+    if(e->type == EVENT_HALT) finishRun(RUN_HALTED);
+
+}
+
+void finishRun(HaltReason reason) {
+    // Sends the final state of the program back to runWithLimits() and ends the (child) emulator process
+
+    CORE = &CORES[0];
+
+    fflush(CONSOLE_OUT);
+
+    fwrite(&reason, sizeof(HaltReason), 1, RUN_STATE);
+    fwrite(REG, sizeof(uint16_t), 0x10, RUN_STATE);
+    fwrite(&PC, sizeof(uint16_t), 1, RUN_STATE);
+    fwrite(&INSTRUCTION_COUNT, sizeof(uint64_t), 1, RUN_STATE);
+    fwrite(MEM, sizeof(uint16_t), MEMORY_SIZE, RUN_STATE);
+    fflush(RUN_STATE);
+
+    exit(0);
+
+}
+
+int gradeSpec(char* specfile, char* reportfile) {
+    // Runs every test case of a grading spec and reports which of them passed, returning the exit code of the emulator
+    // The spec is a JSON object with a "cases" array, where each case names the "program" to run and can give the
+    // console "input", the "max_instructions" it may execute, and the "registers", "memory", and console "output"
+    // it is expected to end with, e.g. { "program": "sum.bin", "input": "12", "registers": { "R1": 3 }, "output": "3\n" }
+
+    JsonValue* spec = parseJsonFile(specfile);
+    JsonValue* cases = getJsonMember(spec, "cases");
+
+    if(!cases || cases->type != JSON_ARRAY) {
+
+        printf("Spec file %s must be a JSON object with a \"cases\" array.\n", specfile);
+        exit(-1);
+
+    }
+
+    checkRegisterKeys(cases, specfile, "case");
+
+    FILE* report = NULL;
+
+    if(reportfile && !(report = fopen(reportfile, "w"))) {
+
+        printf("Cannot output to file %s.\n", reportfile);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    if(report) fprintf(report, "{\n  \"cases\": [\n");
+
+    uint32_t passedCount = 0;
+
+    for(int c = 0; c < cases->count; c++) {
+
+        JsonValue* name = getJsonMember(&cases->items[c], "name");
+        FILE* failures = tmpfile();
+        bool passed = runGradeCase(&cases->items[c], failures);
+
+        passedCount += passed;
+
+        if(name && name->type == JSON_STRING) printf("%s  %s\n", passed ? "PASS" : "FAIL", name->string);
+        else printf("%s  case %i\n", passed ? "PASS" : "FAIL", c + 1);
+
+        if(report) {
+
+            fprintf(report, "    { \"name\": ");
+
+            if(name && name->type == JSON_STRING) printJsonString(report, name->string);
+            else fprintf(report, "\"case %i\"", c + 1);
+
+            fprintf(report, ", \"passed\": %s, \"failures\": [", passed ? "true" : "false");
+
+        }
+
+        char line[MAX_STRING_LEN];
+        bool first = true;
+
+        rewind(failures);
+
+        while(fgets(line, MAX_STRING_LEN, failures)) {
+
+            line[strcspn(line, "\n")] = '\0';
+            printf("      - %s\n", line);
+
+            if(report) {
+
+                fprintf(report, first ? " " : ", ");
+                printJsonString(report, line);
+
+            }
+
+            first = false;
+
+        }
+
+        if(report) fprintf(report, "%s] }%s\n", first ? "" : " ", c + 1 < cases->count ? "," : "");
+
+        fclose(failures);
+
+    }
+
+    printf("Passed %u of %u cases.\n", passedCount, cases->count);
+
+    if(report) {
+
+        fprintf(report, "  ],\n  \"passed\": %u,\n  \"total\": %u\n}\n", passedCount, cases->count);
+        fclose(report);
+
+    }
+
+    return passedCount == cases->count ? 0 : 1;
+
+}
+
+bool runGradeCase(JsonValue* testCase, FILE* failures) {
+    // Runs a single test case and writes one line to the failures file for each of its expectations which is not met
+    // Returns whether all of them were met
+
+    JsonValue* program = getJsonMember(testCase, "program");
+    JsonValue* input = getJsonMember(testCase, "input");
+    JsonValue* limit = getJsonMember(testCase, "max_instructions");
+
+    if(!program || program->type != JSON_STRING) {
+
+        fprintf(failures, "the case does not name a \"program\" to run\n");
+        return false;
+
+    }
+
+    uint64_t instructionLimit = limit ? limit->number : 0;
+    RunResult* result = runWithLimits(program->string, instructionLimit, input && input->type == JSON_STRING ? input->string : NULL);
+    bool passed = false;
+
+    switch(result->haltReason) {
+
+        case RUN_HALTED:
+            passed = checkGradeExpectations(testCase, result, failures); break;
+        case RUN_STOPPED:
+            fprintf(failures, "the program stopped without reaching a HALT instruction\n"); break;
+        case RUN_LIMIT_REACHED:
+            fprintf(failures, "the program did not halt within %lu instructions\n", instructionLimit); break;
+        case RUN_ERROR:
+            fprintf(failures, "the emulator stopped with an error: %s\n", result->error); break;
+        case RUN_CRASHED:
+            fprintf(failures, "the emulator crashed (%s)\n", result->error); break;
+
+    }
+
+    freeRunResult(result);
+
+    return passed;
+
+}
+
+bool checkGradeExpectations(JsonValue* testCase, RunResult* result, FILE* failures) {
+    // Compares the final state of a program against the expectations of the test case, returning whether all of them are met
+    // Registers are checked on the first core
+
+    JsonValue* registers = getJsonMember(testCase, "registers");
+    JsonValue* memory = getJsonMember(testCase, "memory");
+    JsonValue* output = getJsonMember(testCase, "output");
+    bool passed = true;
+
+    for(int i = 0; registers && i < registers->count; i++) {
+
+        uint8_t r = parseRegisterName(registers->keys[i]);
+        uint16_t expected = registers->items[i].number;
+
+        if(result->registers[r] != expected) {
+
+            fprintf(failures, "%s is 0x%.4X, expected 0x%.4X\n", getRegisterName(r), result->registers[r], expected);
+            passed = false;
+
+        }
+
+    }
+
+    for(int i = 0; memory && i < memory->count; i++) {
+
+        uint16_t addr = strtol(memory->keys[i], NULL, 0);
+        uint16_t expected = memory->items[i].number;
+
+        if(result->memory[addr] != expected) {
+
+            fprintf(failures, "memory[0x%.4X] is 0x%.4X, expected 0x%.4X\n", addr, result->memory[addr], expected);
+            passed = false;
+
+        }
+
+    }
+
+    if(output && output->type == JSON_STRING && strcmp(result->output, output->string)) {
+
+        fprintf(failures, "the console output is ");
+        printJsonString(failures, result->output);
+        fprintf(failures, ", expected ");
+        printJsonString(failures, output->string);
+        fputc('\n', failures);
+        passed = false;
+
+    }
+
+    return passed;
+
+}
+
+JsonValue* parseJsonFile(char* filename) {
+    // Reads and parses an entire JSON file, terminating the program if it is missing or malformed
+
+    FILE* file;
+
+    if(!(file = fopen(filename, "r"))) {
+
+        printf("File %s does not exist.\n", filename);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    fseek(file, 0, SEEK_END);
+    long len = ftell(file);
+    rewind(file);
+
+    char* text = malloc(len + 1);
+    text[fread(text, 1, len, file)] = '\0';
+
+    fclose(file);
+
+    char* cursor = text;
+    JsonValue* value = parseJsonValue(&cursor);
+
+    skipJsonWhitespace(&cursor);
+
+    if(!value || *cursor) {
+
+        printf("File %s is not valid JSON (error near byte %li).\n", filename, cursor - text);
+        exit(-1);
+
+    }
+
+    return value;
+
+}
+
+JsonValue* parseJsonValue(char** json) {
+    // Parses a single JSON value, advancing the cursor past it
+    // Returns NULL if the text at the cursor is not valid JSON
+
+    JsonValue* value = calloc(1, sizeof(JsonValue));
+
+    skipJsonWhitespace(json);
+
+    if(**json == '{' || **json == '[') {
+
+        bool isObject = **json == '{';
+        char closer = isObject ? '}' : ']';
+
+        value->type = isObject ? JSON_OBJECT : JSON_ARRAY;
+        (*json)++;
+        skipJsonWhitespace(json);
+
+        while(**json != closer) {
+
+            if(value->count) {
+
+                if(**json != ',') return NULL;
+                (*json)++;
+                skipJsonWhitespace(json);
+
+            }
+
+            char* key = NULL;
+
+            if(isObject) {
+
+                if(!(key = parseJsonString(json))) return NULL;
+
+                skipJsonWhitespace(json);
+                if(**json != ':') return NULL;
+                (*json)++;
+
+            }
+
+            JsonValue* item = parseJsonValue(json);
+            if(!item) return NULL;
+
+            value->items = realloc(value->items, (value->count + 1) * sizeof(JsonValue));
+            value->keys = realloc(value->keys, (value->count + 1) * sizeof(char*));
+            value->items[value->count] = *item;
+            value->keys[value->count] = key;
+            value->count++;
+
+            skipJsonWhitespace(json);
+
+            if(!**json) return NULL;
+
+        }
+
+        (*json)++;
+
+    } else if(**json == '"') {
+
+        value->type = JSON_STRING;
+        if(!(value->string = parseJsonString(json))) return NULL;
+
+    } else if(!strncmp(*json, "true", 4) || !strncmp(*json, "false", 5)) {
+
+        value->type = JSON_BOOL;
+        value->number = **json == 't';
+        *json += value->number ? 4 : 5;
+
+    } else if(!strncmp(*json, "null", 4)) {
+
+        value->type = JSON_NULL;
+        *json += 4;
+
+    } else {
+
+        char* end;
+
+        value->type = JSON_NUMBER;
+        value->number = strtod(*json, &end);
+
+        if(end == *json) return NULL;
+        *json = end;
+
+    }
+
+    return value;
+
+}
+
+char* parseJsonString(char** json) {
+    // Parses a JSON string literal, advancing the cursor past it
+    // Returns NULL if the text at the cursor is not a valid string
+
+    if(**json != '"') return NULL;
+    (*json)++;
+
+    char* str = malloc(strlen(*json) + 1);
+    int len = 0;
+
+    while(**json != '"') {
+
+        if(!**json) return NULL;
+
+        if(**json == '\\') {
+
+            (*json)++;
+
+            switch(**json) {
+
+                case 'n': str[len++] = '\n'; break;
+                case 't': str[len++] = '\t'; break;
+                case 'r': str[len++] = '\r'; break;
+                case 'b': str[len++] = '\b'; break;
+                case 'f': str[len++] = '\f'; break;
+                case 'u':
+                    if(strspn(*json + 1, "0123456789abcdefABCDEF") < 4) return NULL;
+                    // The digits are checked before they are read, since the string may end right after the escape
+                    str[len++] = strtol((char[5]) { (*json)[1], (*json)[2], (*json)[3], (*json)[4], '\0' }, NULL, 16);
+                    *json += 4;
+                    break;
+                case '\0': return NULL;
+                default: str[len++] = **json; break;
+
+            }
+
+        } else str[len++] = **json;
+
+        (*json)++;
+
+    }
+
+    (*json)++;
+    str[len] = '\0';
+
+    return str;
+
+}
+
+void skipJsonWhitespace(char** json) {
+    // Advances the cursor past any whitespace
+
+    while(**json == ' ' || **json == '\t' || **json == '\n' || **json == '\r') (*json)++;
+
+}
+
+JsonValue* getJsonMember(JsonValue* object, char* key) {
+    // Gets the value stored under the given key of a JSON object, or NULL if there is none
+
+    if(!object || object->type != JSON_OBJECT) return NULL;
+
+    for(int i = 0; i < object->count; i++) {
+
+        if(!strncmp(object->keys[i], key, MAX_STRING_LEN)) return &object->items[i];
+
+    }
+
+    return NULL;
+
+}
+
+void checkRegisterKeys(JsonValue* array, char* filename, char* itemName) {
+    // Checks that every key of the "registers" object of each item in a grading spec or trace names a register, so
+    // that a misspelled register is reported instead of checking the wrong one
+
+    for(int i = 0; i < array->count; i++) {
+
+        JsonValue* registers = getJsonMember(&array->items[i], "registers");
+
+        for(int k = 0; registers && k < registers->count; k++) {
+
+            if(parseRegisterName(registers->keys[k]) != INVALID_REGISTER) continue;
+
+            printf("File %s names an unknown register \"%s\" in %s %i.\n", filename, registers->keys[k], itemName, i + 1);
+            exit(-1);
+
+        }
+
+    }
+
+}
+
+void printJsonString(FILE* out, char* str) {
+    // Prints a string as a quoted JSON string literal, escaping any characters which cannot appear in one directly
+
+    fputc('"', out);
+
+    for(; *str; str++) {
+
+        if(*str == '"' || *str == '\\') fprintf(out, "\\%c", *str);
+        else if(*str == '\n') fprintf(out, "\\n");
+        else if((unsigned char) *str < 0x20) fprintf(out, "\\u%.4x", *str);
+        else fputc(*str, out);
+
+    }
+
+    fputc('"', out);
+
+}
+
+void consoleWrite(char c) {
+    // Writes a character to the buffered console output, flushing at the end of each line
+
+    fputc(c, CONSOLE_OUT);
+
+    if(c == '\n') flushConsole();
+
+}
+
+void flushConsole() {
+    // Sends any buffered console output and restarts the flush timer
+
+    fflush(CONSOLE_OUT);
+    clock_gettime(CLOCK_MONOTONIC, &LAST_CONSOLE_FLUSH);
+
+}
+
+void flushConsoleIfStale() {
+    // Flushes the console if output has been sitting in the buffer for longer than the flush interval
+
+    if(millisecondsSince(&LAST_CONSOLE_FLUSH) >= CONSOLE_FLUSH_INTERVAL_MS) flushConsole();
+
+}
+
+void startInputThread() {
+    // Starts the thread which reads console input in the background for --async-input
+
+    pthread_t thread;
+
+    if(pthread_create(&thread, NULL, readInputThread, NULL)) {
+
+        printf("Cannot start the console input thread.\n");
+        exit(-1);
+
+    }
+
+    pthread_detach(thread);
+
+}
+
+void* readInputThread(void* arg) {
+    // Moves characters from the console input into the input queue until the input ends
+    // Reading stops while the queue is full, so that a large input is not read into memory all at once
+
+    int c;
+
+    while((c = fgetc(CONSOLE_IN)) != EOF) {
+
+        pthread_mutex_lock(&INPUT_LOCK);
+
+        while(INPUT_QUEUE_LEN == INPUT_QUEUE_SIZE) pthread_cond_wait(&INPUT_CHANGED, &INPUT_LOCK);
+
+        INPUT_QUEUE[(INPUT_QUEUE_START + INPUT_QUEUE_LEN++) % INPUT_QUEUE_SIZE] = c;
+
+        pthread_cond_broadcast(&INPUT_CHANGED);
+        pthread_mutex_unlock(&INPUT_LOCK);
+
+    }
+
+    pthread_mutex_lock(&INPUT_LOCK);
+    INPUT_ENDED = true;
+    pthread_cond_broadcast(&INPUT_CHANGED);
+    pthread_mutex_unlock(&INPUT_LOCK);
+
+    return NULL;
+
+}
+
+uint16_t readConsole() {
+    // Reads a character from the console input, returning 0xFFFF at the end of the input
+    // With --async-input the character comes from the input queue, and the display keeps refreshing while waiting for it
+
+    flushConsole();
+
+    if(!ASYNC_INPUT) {
 
+        int c = fgetc(CONSOLE_IN);
 
-uint16_t MEMORY[0xFFFF];
-uint16_t REGISTERS[0xF];
+        return c == EOF ? 0xFFFF : c;
 
-uint16_t PROGRAM_COUNTER = 0;
-uint32_t INSTRUCTION_REGISTER = 0;
+    }
 
-bool ZERO_FLAG = false;
-bool SIGN_FLAG = false;
+    pthread_mutex_lock(&INPUT_LOCK);
 
+    while(!INPUT_QUEUE_LEN && !INPUT_ENDED) {
 
-void loadProgram(char* binfile);
-void executeProgram();
-void executeInstruction();
-void grabNextInstruction();
-// Program control functions
+        struct timespec deadline;
 
-void setFlags(uint16_t result);
+        clock_gettime(CLOCK_REALTIME, &deadline);
+        deadline.tv_nsec += DISPLAY_REFRESH_INTERVAL_MS * 1000000L;
+        deadline.tv_sec += deadline.tv_nsec / 1000000000L;
+        deadline.tv_nsec %= 1000000000L;
 
-bool RType(uint32_t instruction);
-bool IType(uint32_t instruction);
-bool JType(uint32_t instruction);
+        pthread_cond_timedwait(&INPUT_CHANGED, &INPUT_LOCK, &deadline);
 
-void SET(uint8_t rDest, uint16_t iVal);
-void COPY(uint8_t rDest, uint8_t rSrc);
+        if(DISPLAY_ENABLED) {
 
-void ADD(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
-void SUBTRACT(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
-void MULTIPLY(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
-void DIVIDE(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
-void MODULO(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+            pthread_mutex_unlock(&INPUT_LOCK);
+            refreshDisplayIfStale();
+            pthread_mutex_lock(&INPUT_LOCK);
 
-void COMPARE(uint8_t rOp1, uint8_t rOp2);
+        }
 
-void SHIFT_LEFT(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
-void SHIFT_RIGHT(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
+    }
 
-void AND(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
-void OR(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
-void XOR(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
-void NAND(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
-void NOR(uint8_t rDest, uint8_t rOp1, uint8_t rOp2);
-void NOT(uint8_t rDest, uint8_t rOp);
+    uint16_t val = 0xFFFF;
 
-void ADD_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
-void SUBTRACT_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
-void MULTIPLY_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
-void DIVIDE_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
-void MODULO_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+    if(INPUT_QUEUE_LEN) {
 
-void COMPARE_IMM(uint8_t rOp1, uint16_t iOp2);
+        val = (unsigned char) INPUT_QUEUE[INPUT_QUEUE_START];
+        INPUT_QUEUE_START = (INPUT_QUEUE_START + 1) % INPUT_QUEUE_SIZE;
+        INPUT_QUEUE_LEN--;
 
-void SHIFT_LEFT_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
-void SHIFT_RIGHT_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+        pthread_cond_broadcast(&INPUT_CHANGED);
 
-void AND_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
-void OR_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
-void XOR_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
-void NAND_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
-void NOR_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2);
+    }
 
-void LOAD(uint8_t rDest, uint8_t rBase, uint16_t iOffset);
-void STORE(uint8_t rSrc, uint8_t rBase, uint16_t iOffset);
+    pthread_mutex_unlock(&INPUT_LOCK);
 
-void JUMP(uint16_t destAddr);
-void JUMP_IF_ZERO(uint16_t destAddr);
-void JUMP_IF_NOTZERO(uint16_t destAddr);
-void JUMP_LINK(uint16_t destAddr);
+    return val;
 
-void HALT();
-// Instruction execution functions
+}
 
-uint8_t getOpcode(uint32_t instruction);
-uint16_t getInstructionHalf1(uint32_t instruction);
-uint16_t getInstructionHalf2(uint32_t instruction);
-uint8_t getRegOperand(uint32_t instruction, uint8_t opNum);
-uint16_t getDestOrImmVal(uint32_t instruction);
-// Emulator utility functions
+uint16_t getConsoleStatus() {
+    // Gets the number of input characters which can be read without waiting, or 0xFFFF if the input has ended
+    // Without --async-input reads always wait for input, so a single character is reported as ready until the input ends
 
-bool endsWith(char* str, char* substr);
-// General utility functions
+    if(!ASYNC_INPUT) return feof(CONSOLE_IN) ? 0xFFFF : 1;
 
+    pthread_mutex_lock(&INPUT_LOCK);
+    uint16_t status = !INPUT_QUEUE_LEN && INPUT_ENDED ? 0xFFFF : INPUT_QUEUE_LEN;
+    pthread_mutex_unlock(&INPUT_LOCK);
 
-int main(int argc, char** argv) {
+    return status;
 
-    if(argc != 2) {
+}
 
-        printf("Incorrect number of arguments supplied.\n");
-        printf(USAGE);
+void openDisplay() {
+    // Opens the display window, which is closed again when the emulator exits
+
+#ifdef SMIS_DISPLAY
+
+    if(SDL_Init(SDL_INIT_VIDEO)) {
+
+        printf("Cannot initialize the display: %s\n", SDL_GetError());
         exit(-1);
 
     }
 
-    if(!endsWith(argv[1], ".bin")) {
+    DISPLAY_WINDOW = SDL_CreateWindow("SMIS", SDL_WINDOWPOS_UNDEFINED, SDL_WINDOWPOS_UNDEFINED,
+        DISPLAY_WIDTH * DISPLAY_SCALE, DISPLAY_HEIGHT * DISPLAY_SCALE, 0);
+    DISPLAY_RENDERER = DISPLAY_WINDOW ? SDL_CreateRenderer(DISPLAY_WINDOW, -1, 0) : NULL;
+    DISPLAY_TEXTURE = DISPLAY_RENDERER ? SDL_CreateTexture(DISPLAY_RENDERER, SDL_PIXELFORMAT_ARGB8888,
+        SDL_TEXTUREACCESS_STREAMING, DISPLAY_WIDTH, DISPLAY_HEIGHT) : NULL;
 
-        printf("The supplied file does not have the correct extension.\n");
-        printf(USAGE);
+    if(!DISPLAY_TEXTURE) {
+
+        printf("Cannot open the display window: %s\n", SDL_GetError());
         exit(-1);
 
     }
 
-    loadProgram(argv[1]);
-    executeProgram();
-    
-}
+    atexit(closeDisplay);
 
-void loadProgram(char* binfile) {
-    // Reads the binary file and places it in the memory array
+    refreshDisplay();
 
-    FILE* program;
+#else
 
-    if(!(program = fopen(binfile, "rb"))) {
+    printf("This emulator was built without display support, rebuild it with -DSMIS_DISPLAY to use --display.\n");
+    exit(-1);
 
-        printf("File %s does not exist.\n", binfile);
-        printf(USAGE);
-        exit(-1);
+#endif
 
-    }
+}
 
-    uint32_t instruction;
+void refreshDisplay() {
+    // Redraws the window from the framebuffer and handles window events, stopping the emulator if it is closed
 
-    uint16_t storeAddr = 0;
-    
-    while(fread(&instruction, 4, 1, program)) {
+#ifdef SMIS_DISPLAY
 
-        instruction = ntohl(instruction);
+    uint32_t pixels[DISPLAY_WIDTH * DISPLAY_HEIGHT];
 
-        MEM[storeAddr] = getInstructionHalf1(instruction);
-        MEM[storeAddr + 1] = getInstructionHalf2(instruction);
-        // Split the instruction into two 16-bit segments to put in memory
+    for(int p = 0; p < DISPLAY_WIDTH * DISPLAY_HEIGHT; p++) {
 
-        storeAddr += 2;
+        uint16_t word = MEM[DISPLAY_BASE + p / 16];
+        pixels[p] = (word >> (15 - p % 16)) & 1 ? 0xFFFFFFFF : 0xFF000000;
 
     }
-    
-    MEM[storeAddr] = OP_HALT << 8;
-    // Add a HALT to the end, in case the ASM programmer forgot to do so
 
-    fclose(program);
+    SDL_UpdateTexture(DISPLAY_TEXTURE, NULL, pixels, DISPLAY_WIDTH * sizeof(uint32_t));
+    SDL_RenderCopy(DISPLAY_RENDERER, DISPLAY_TEXTURE, NULL, NULL);
+    SDL_RenderPresent(DISPLAY_RENDERER);
 
-}
+    SDL_Event event;
 
-void executeProgram() {
-    // Calls each instruction in the program until reaching a HALT signal
+    while(SDL_PollEvent(&event)) {
 
-    do {
+        if(event.type == SDL_QUIT) {
 
-        grabNextInstruction();
-        PC += 2;
-        // PC is incremented prior to executing instruction so it does not interfere with J-Type instructions
-        executeInstruction();
+            flushConsole();
+            exit(0);
 
-        RZR = 0x0000;
+        }
 
-    } while(IR != 0x00000000);
+    }
 
-}
+#endif
 
-void executeInstruction() {
-    // Executes the instruction held in the instruction register
+    clock_gettime(CLOCK_MONOTONIC, &LAST_DISPLAY_REFRESH);
 
-    if(RType(IR)) return;
-    else if(IType(IR)) return;
-    else if(JType(IR)) return;
+}
 
-    printf("Unknown instruction 0x%.8X at PC address 0x%.4X\n", IR, PC);
-    exit(-1);
+void refreshDisplayIfStale() {
+    // Redraws the display if the refresh interval has passed since it was last drawn
+
+    if(millisecondsSince(&LAST_DISPLAY_REFRESH) >= DISPLAY_REFRESH_INTERVAL_MS) refreshDisplay();
 
 }
 
-void grabNextInstruction() {
-    // Gets the next instruction from memory and places it in the instruction register
+void waitForDisplayClose() {
+    // Keeps showing the final frame after the program has halted, until the window is closed
 
-    IR = 0;
+    printf("Close the display window to exit.\n");
 
-    IR ^= MEM[PC] << 16;
-    IR ^= MEM[PC + 1];
+#ifdef SMIS_DISPLAY
+
+    refreshDisplay();
+
+    SDL_Event event;
+
+    while(SDL_WaitEvent(&event) && event.type != SDL_QUIT) refreshDisplay();
+
+#endif
 
 }
 
-void setFlags(uint16_t result) {
-    // Sets flags according to the given value, usually the result of an arithmetic operation
+void closeDisplay() {
+    // Destroys the display window
 
-    if(result == 0x0000) ZF = true;
-    else ZF = false;
+#ifdef SMIS_DISPLAY
 
-    if(result >> 15 == 0x1) SF = true;
-    else SF = false;
+    SDL_DestroyTexture(DISPLAY_TEXTURE);
+    SDL_DestroyRenderer(DISPLAY_RENDERER);
+    SDL_DestroyWindow(DISPLAY_WINDOW);
+    SDL_Quit();
+
+#endif
 
 }
 
@@ -307,7 +3920,7 @@ bool IType(uint32_t instruction) {
 
     uint8_t rDest = getRegOperand(IR, 1);
     uint8_t rOp1 = getRegOperand(IR, 2);
-    uint8_t iOp2 = getDestOrImmVal(IR);
+    uint16_t iOp2 = getDestOrImmVal(IR);
 
     switch(opcode) {
 
@@ -347,7 +3960,7 @@ bool JType(uint32_t instruction) {
 
     uint8_t opcode = getOpcode(IR);
 
-    uint8_t destAddr = getDestOrImmVal(IR);
+    uint16_t destAddr = getDestOrImmVal(IR);
 
     switch(opcode) {
 
@@ -366,10 +3979,70 @@ bool JType(uint32_t instruction) {
 
 }
 
+bool CType(uint32_t instruction) {
+    // Executes an instruction registered through the custom opcode extension mechanism
+    // Returns true if the instruction is a registered custom instruction, false if it is not
+
+    uint8_t opcode = getOpcode(IR);
+
+    for(int i = 0; i < CUSTOM_OPCODE_COUNT; i++) {
+
+        if(CUSTOM_OPCODES[i].opcode == opcode) {
+
+            CUSTOM_OPCODES[i].execute(IR);
+            return true;
+
+        }
+
+    }
+
+    return false;
+
+}
+
+void registerOpcode(uint8_t opcode, void (*execute)(uint32_t instruction)) {
+    // Claims an unused opcode for a custom instruction, which is executed by the given handler
+    // Extensions call this from registerExtensions(), which is linked in alongside the emulator, and can
+    // access the machine state through REG, MEM, PC, and the flags, e.g. for a MAC instruction:
+    //
+    //     void executeMAC(uint32_t instruction) {
+    //         REG[getRegOperand(instruction, 1)] += REG[getRegOperand(instruction, 2)] * REG[getRegOperand(instruction, 3)];
+    //     }
+
+    if(opcode < OP_CUSTOM_MIN) {
+
+        printf("Internal error: cannot register opcode %i, which is reserved for built-in instructions\n", opcode);
+        exit(-2);
+
+    }
+
+    for(int i = 0; i < CUSTOM_OPCODE_COUNT; i++) {
+
+        if(CUSTOM_OPCODES[i].opcode == opcode) {
+
+            printf("Internal error: cannot register opcode %i, which has already been registered\n", opcode);
+            exit(-2);
+
+        }
+
+    }
+
+    CustomOpcode c;
+    c.opcode = opcode;
+    c.execute = execute;
+
+    CUSTOM_OPCODES = realloc(CUSTOM_OPCODES, (CUSTOM_OPCODE_COUNT + 1) * sizeof(CustomOpcode));
+
+    CUSTOM_OPCODES[CUSTOM_OPCODE_COUNT] = c;
+
+    CUSTOM_OPCODE_COUNT++;
+
+}
+
 void SET(uint8_t rDest, uint16_t iVal) {
     // Executes a SET instruction
 
-    REG[rDest] = iVal;
+    setRegister(rDest, iVal);
 
     printf("SET\n");
 
@@ -378,7 +4051,7 @@ void SET(uint8_t rDest, uint16_t iVal) {
 void COPY(uint8_t rDest, uint8_t rSrc) {
     // Executes a COPY instruction
 
-    REG[rDest] = REG[rSrc];
+    setRegister(rDest, getRegister(rSrc));
 
     printf("COPY\n");
 
@@ -387,9 +4060,10 @@ void COPY(uint8_t rDest, uint8_t rSrc) {
 void ADD(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
     // Executes an ADD instruction
 
-    REG[rDest] = REG[rOp1] + REG[rOp2];
+    uint16_t result = getRegister(rOp1) + getRegister(rOp2);
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("ADD\n");
 
@@ -398,9 +4072,10 @@ void ADD(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
 void SUBTRACT(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
     // Executes a SUBTRACT instruction
 
-    REG[rDest] = REG[rOp1] - REG[rOp2];
+    uint16_t result = getRegister(rOp1) - getRegister(rOp2);
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("SUBTRACT\n");
 
@@ -409,9 +4084,10 @@ void SUBTRACT(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
 void MULTIPLY(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
     // Executes a MULTIPLY instruction
 
-    REG[rDest] = REG[rOp1] * REG[rOp2];
+    uint16_t result = getRegister(rOp1) * getRegister(rOp2);
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("MULTIPLY\n");
 
@@ -420,9 +4096,12 @@ void MULTIPLY(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
 void DIVIDE(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
     // Executes a DIVIDE instruction
 
-    REG[rDest] = REG[rOp1] / REG[rOp2];
+    if(!getRegister(rOp2)) runtimeError("Division by zero in instruction 0x%.8X at PC address 0x%.4X", IR, INSTRUCTION_PC);
 
-    setFlags(REG[rDest]);
+    uint16_t result = getRegister(rOp1) / getRegister(rOp2);
+
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("DIVIDE\n");
 
@@ -431,9 +4110,12 @@ void DIVIDE(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
 void MODULO(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
     // Executes a MODULO instruction
 
-    REG[rDest] = REG[rOp1] % REG[rOp2];
+    if(!getRegister(rOp2)) runtimeError("Division by zero in instruction 0x%.8X at PC address 0x%.4X", IR, INSTRUCTION_PC);
+
+    uint16_t result = getRegister(rOp1) % getRegister(rOp2);
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("MODULO\n");
 
@@ -442,7 +4124,7 @@ void MODULO(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
 void COMPARE(uint8_t rOp1, uint8_t rOp2) {
     // Executes a COMPARE instruction
 
-    uint16_t throwawayVal = REG[rOp1] + REG[rOp2];
+    uint16_t throwawayVal = getRegister(rOp1) + getRegister(rOp2);
 
     setFlags(throwawayVal);
 
@@ -453,9 +4135,10 @@ void COMPARE(uint8_t rOp1, uint8_t rOp2) {
 void SHIFT_LEFT(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
     // Executes a SHIFT-LEFT instruction
 
-    REG[rDest] = REG[rOp1] << REG[rOp2];
+    uint16_t result = getRegister(rOp1) << getRegister(rOp2);
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("SHIFT-LEFT\n");
 
@@ -464,9 +4147,10 @@ void SHIFT_LEFT(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
 void SHIFT_RIGHT(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
     // Executes a SHIFT-RIGHT instruction
 
-    REG[rDest] = REG[rOp1] >> REG[rOp2];
+    uint16_t result = getRegister(rOp1) >> getRegister(rOp2);
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("SHIFT-RIGHT\n");
 
@@ -475,9 +4159,10 @@ void SHIFT_RIGHT(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
 void AND(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
     // Executes an AND instruction
 
-    REG[rDest] = REG[rOp1] & REG[rOp2];
+    uint16_t result = getRegister(rOp1) & getRegister(rOp2);
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("AND\n");
 
@@ -486,9 +4171,10 @@ void AND(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
 void OR(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
     // Executes an OR instruction
 
-    REG[rDest] = REG[rOp1] | REG[rOp2];
+    uint16_t result = getRegister(rOp1) | getRegister(rOp2);
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("OR\n");
 
@@ -497,9 +4183,10 @@ void OR(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
 void XOR(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
     // Executes an XOR instruction
 
-    REG[rDest] = REG[rOp1] ^ REG[rOp2];
+    uint16_t result = getRegister(rOp1) ^ getRegister(rOp2);
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("XOR\n");
 
@@ -508,9 +4195,10 @@ void XOR(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
 void NAND(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
     // Executes a NAND instruction
 
-    REG[rDest] = ~(REG[rOp1] & REG[rOp2]);
+    uint16_t result = ~(getRegister(rOp1) & getRegister(rOp2));
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("NAND\n");
 
@@ -519,9 +4207,10 @@ void NAND(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
 void NOR(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
     // Executes a NOR instruction
 
-    REG[rDest] = ~(REG[rOp1] | REG[rOp2]);
+    uint16_t result = ~(getRegister(rOp1) | getRegister(rOp2));
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("NOR\n");
 
@@ -530,9 +4219,10 @@ void NOR(uint8_t rDest, uint8_t rOp1, uint8_t rOp2) {
 void NOT(uint8_t rDest, uint8_t rOp) {
     // Executes a NOT instruction
 
-    REG[rDest] = ~REG[rOp];
+    uint16_t result = ~getRegister(rOp);
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("NOT\n");
 
@@ -541,20 +4231,22 @@ void NOT(uint8_t rDest, uint8_t rOp) {
 void ADD_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
     // Executes an ADD-IMM instruction
 
-    REG[rDest] = REG[rOp1] + iOp2;
+    uint16_t result = getRegister(rOp1) + iOp2;
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
-    printf("ADD-IMM result %i\n", REG[rDest]);
+    printf("ADD-IMM result %i\n", result);
 
 }
 
 void SUBTRACT_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
     // Executes a SUBTRACT-IMM instruction
 
-    REG[rDest] = REG[rOp1] - iOp2;
+    uint16_t result = getRegister(rOp1) - iOp2;
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("SUBTRACT-IMM\n");
 
@@ -563,9 +4255,10 @@ void SUBTRACT_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
 void MULTIPLY_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
     // Executes a MULTIPLY-IMM instruction
 
-    REG[rDest] = REG[rOp1] * iOp2;
+    uint16_t result = getRegister(rOp1) * iOp2;
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("MULTIPLY-IMM\n");
 
@@ -574,9 +4267,12 @@ void MULTIPLY_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
 void DIVIDE_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
     // Executes a DIVIDE-IMM instruction
 
-    REG[rDest] = REG[rOp1] / iOp2;
+    if(!iOp2) runtimeError("Division by zero in instruction 0x%.8X at PC address 0x%.4X", IR, INSTRUCTION_PC);
 
-    setFlags(REG[rDest]);
+    uint16_t result = getRegister(rOp1) / iOp2;
+
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("DIVIDE-IMM\n");
 
@@ -585,9 +4281,12 @@ void DIVIDE_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
 void MODULO_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
     // Executes a MODULO-IMM instruction
 
-    REG[rDest] = REG[rOp1] % iOp2;
+    if(!iOp2) runtimeError("Division by zero in instruction 0x%.8X at PC address 0x%.4X", IR, INSTRUCTION_PC);
+
+    uint16_t result = getRegister(rOp1) % iOp2;
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("MODULO-IMM\n");
 
@@ -596,7 +4295,7 @@ void MODULO_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
 void COMPARE_IMM(uint8_t rOp1, uint16_t iOp2) {
     // Executes a COMPARE-IMM instruction
 
-    uint16_t throwawayVal = REG[rOp1] - iOp2;
+    uint16_t throwawayVal = getRegister(rOp1) - iOp2;
 
     setFlags(throwawayVal);
 
@@ -607,9 +4306,10 @@ void COMPARE_IMM(uint8_t rOp1, uint16_t iOp2) {
 void SHIFT_LEFT_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
     // Executes a SHIFT-LEFT-IMM instruction
 
-    REG[rDest] = REG[rOp1] << iOp2;
+    uint16_t result = getRegister(rOp1) << iOp2;
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("SHIFT-LEFT-IMM\n");
 
@@ -618,9 +4318,10 @@ void SHIFT_LEFT_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
 void SHIFT_RIGHT_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
     // Executes a SHIFT-RIGHT-IMM instruction
 
-    REG[rDest] = REG[rOp1] >> iOp2;
+    uint16_t result = getRegister(rOp1) >> iOp2;
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("SHIFT-RIGHT-IMM\n");
 
@@ -629,9 +4330,10 @@ void SHIFT_RIGHT_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
 void AND_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
     // Executes an AND-IMM instruction
 
-    REG[rDest] = REG[rOp1] & iOp2;
+    uint16_t result = getRegister(rOp1) & iOp2;
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("AND-IMM\n");
 
@@ -640,9 +4342,10 @@ void AND_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
 void OR_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
     // Executes an OR-IMM instruction
 
-    REG[rDest] = REG[rOp1] | iOp2;
+    uint16_t result = getRegister(rOp1) | iOp2;
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("OR-IMM\n");
 
@@ -651,9 +4354,10 @@ void OR_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
 void XOR_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
     // Executes an XOR-IMM instruction
 
-    REG[rDest] = REG[rOp1] ^ iOp2;
+    uint16_t result = getRegister(rOp1) ^ iOp2;
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("XOR-IMM\n");
 
@@ -662,9 +4366,10 @@ void XOR_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
 void NAND_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
     // Executes a NAND-IMM instruction
 
-    REG[rDest] = ~(REG[rOp1] & iOp2);
+    uint16_t result = ~(getRegister(rOp1) & iOp2);
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("NAND-IMM\n");
 
@@ -673,9 +4378,10 @@ void NAND_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
 void NOR_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
     // Executes A NOR-IMM instruction
 
-    REG[rDest] = ~(REG[rOp1] | iOp2);
+    uint16_t result = ~(getRegister(rOp1) | iOp2);
 
-    setFlags(REG[rDest]);
+    setRegister(rDest, result);
+    setFlags(result);
 
     printf("NOR-IMM\n");
 
@@ -684,7 +4390,7 @@ void NOR_IMM(uint8_t rDest, uint8_t rOp1, uint16_t iOp2) {
 void LOAD(uint8_t rDest, uint8_t rBase, uint16_t iOffset) {
     // Executes a LOAD instruction
 
-    REG[rDest] = MEM[REG[rBase] + iOffset];
+    setRegister(rDest, readMemory(getRegister(rBase) + iOffset));
 
     printf("LOAD\n");
 
@@ -693,7 +4399,7 @@ void LOAD(uint8_t rDest, uint8_t rBase, uint16_t iOffset) {
 void STORE(uint8_t rSrc, uint8_t rBase, uint16_t iOffset) {
     // Executes a STORE instruction
 
-    MEM[REG[rBase] + iOffset] = REG[rSrc];
+    writeMemory(getRegister(rBase) + iOffset, getRegister(rSrc));
 
     printf("STORE\n");
 
@@ -729,7 +4435,7 @@ void JUMP_IF_NOTZERO(uint16_t destAddr) {
 void JUMP_LINK(uint16_t destAddr) {
     // Executes a JUMP-LINK instruction
 
-    RLR = PC;
+    setRegister(0xD, PC);
     PC = destAddr;
 
     printf("JUMP-LINK\n");
@@ -737,10 +4443,24 @@ void JUMP_LINK(uint16_t destAddr) {
 }
 
 void HALT() {
-    // Executes a HALT instruction
+    // Executes a HALT instruction, which stops the current core and ends the program once every core has stopped
 
     printf("HALT\n");
 
+    CORE->halted = true;
+
+    for(int c = 0; c < CORE_COUNT; c++) if(!CORES[c].halted) return;
+
+    emitEvent(EVENT_HALT, 0, 0, 0);
+
+    flushConsole();
+
+    if(DEBUGGER_ENABLED) return;
+    // The debugger keeps the final state around to be inspected, and ends the program once it is told to quit
+
+    if(DISPLAY_ENABLED) waitForDisplayClose();
+    free(MEM);
+
     exit(0);
 
 }
@@ -790,6 +4510,205 @@ uint16_t getDestOrImmVal(uint32_t instruction) {
 
 }
 
+void dumpState(FILE* out, uint16_t memStart, uint32_t memLen) {
+    // Prints the registers, flags, PC, and the given window of memory as a readable table
+
+    fprintf(out, "PC   0x%.4X    IR   0x%.8X    ZF %i    SF %i\n", PC, IR, ZF, SF);
+
+    for(int r = 0; r < 0x10; r++) {
+
+        fprintf(out, "%-4s 0x%.4X%s", getRegisterName(r), REG[r], (r + 1) % DUMP_REGISTERS_PER_ROW ? "    " : "\n");
+
+    }
+
+    dumpMemory(out, memStart, memLen);
+
+}
+
+void dumpMemory(FILE* out, uint16_t memStart, uint32_t memLen) {
+    // Prints the given window of memory as rows of hex words, each starting with its address
+
+    if(!memLen) return;
+
+    if(memStart + memLen > MEMORY_SIZE) memLen = MEMORY_SIZE - memStart;
+
+    fprintf(out, "Memory 0x%.4X-0x%.4X:\n", memStart, memStart + memLen - 1);
+
+    for(uint32_t addr = memStart; addr < memStart + memLen; addr++) {
+
+        if((addr - memStart) % DUMP_WORDS_PER_ROW == 0) fprintf(out, "0x%.4X ", addr);
+
+        fprintf(out, " %.4X", MEM[addr]);
+
+        if((addr - memStart) % DUMP_WORDS_PER_ROW == DUMP_WORDS_PER_ROW - 1 || addr == memStart + memLen - 1) fputc('\n', out);
+
+    }
+
+}
+
+void printOpcodeStats() {
+    // Prints how many times each opcode was executed, from most to least frequent
+
+    uint8_t order[0x100];
+    uint64_t total = 0;
+
+    for(int i = 0; i < 0x100; i++) {
+
+        order[i] = i;
+        total += OPCODE_COUNTS[i];
+
+    }
+
+    for(int i = 1; i < 0x100; i++) {
+
+        for(int j = i; j > 0 && OPCODE_COUNTS[order[j]] > OPCODE_COUNTS[order[j - 1]]; j--) {
+
+            uint8_t temp = order[j];
+            order[j] = order[j - 1];
+            order[j - 1] = temp;
+
+        }
+
+    }
+    // Insertion sort keeps opcodes with equal counts in numerical order
+
+    fflush(CONSOLE_OUT);
+
+    printf("Opcode execution counts (%lu instructions):\n", total);
+
+    for(int i = 0; i < 0x100 && OPCODE_COUNTS[order[i]]; i++) {
+
+        printf("%-16s %10lu  %5.1f%%\n", getOpcodeName(order[i]), OPCODE_COUNTS[order[i]], 100.0 * OPCODE_COUNTS[order[i]] / total);
+
+    }
+
+}
+
+char* getRegisterName(uint8_t regNum) {
+    // Gets the display name of a given register, using the special names of RZR, RLR, RBP, and RSP
+
+    static char names[0x10][4];
+
+    switch(regNum) {
+
+        case 0x0: return "RZR";
+        case 0xD: return "RLR";
+        case 0xE: return "RBP";
+        case 0xF: return "RSP";
+
+    }
+
+    snprintf(names[regNum & 0xF], 4, "R%i", regNum & 0xF);
+
+    return names[regNum & 0xF];
+
+}
+
+uint8_t parseRegisterName(char* name) {
+    // Gets the number of a register from either its special name (such as RSP) or its plain name (such as R15)
+    // Returns INVALID_REGISTER if the name is neither
+
+    char plainName[4];
+
+    for(int r = 0; r < 0x10; r++) {
+
+        snprintf(plainName, 4, "R%i", r);
+
+        if(!strcasecmp(name, getRegisterName(r)) || !strcasecmp(name, plainName)) return r;
+
+    }
+
+    return INVALID_REGISTER;
+
+}
+
+char* getOpcodeName(uint8_t opcode) {
+    // Gets the mnemonic of a given opcode, or its number if it is not a built-in instruction
+
+    static char name[16];
+
+    if(opcode <= OP_HALT && OPCODE_NAMES[opcode]) return OPCODE_NAMES[opcode];
+
+    snprintf(name, 16, "OPCODE-%i", opcode);
+
+    return name;
+
+}
+
+uint32_t getReservedBits(uint8_t opcode) {
+    // Gets the mask of encoding bits which are unused by the given opcode, and therefore must be zero
+
+    switch(opcode) {
+
+        case OP_COPY:
+        case OP_NOT:
+            return 0x0000FFFF;
+
+        case OP_COMPARE:
+            return 0x00F00FFF;
+
+        case OP_ADD: case OP_SUBTRACT: case OP_MULTIPLY: case OP_DIVIDE: case OP_MODULO:
+        case OP_SHIFT_LEFT: case OP_SHIFT_RIGHT:
+        case OP_AND: case OP_OR: case OP_XOR: case OP_NAND: case OP_NOR:
+            return 0x00000FFF;
+
+        case OP_SET:
+            return 0x000F0000;
+
+        case OP_COMPARE_IMM:
+            return 0x00F00000;
+
+        case OP_JUMP: case OP_JUMP_IF_ZERO: case OP_JUMP_IF_NOTZERO: case OP_JUMP_LINK:
+            return 0x00FF0000;
+
+        case OP_HALT:
+            return 0x00FFFFFF;
+
+    }
+
+    return 0;
+
+}
+
+uint16_t parseNumberArg(char** argv, int argc, int* i) {
+    // Parses the decimal or 0x-prefixed hexadecimal value following a command-line option, advancing past it
+
+    char* end;
+
+    if(*i + 1 >= argc) {
+
+        printf("No value supplied for %s.\n", argv[*i]);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    char* option = argv[*i];
+    char* value = argv[++(*i)];
+    long num = strtol(value, &end, 0);
+
+    if(*end || end == value || num < 0 || num > 0xFFFF) {
+
+        printf("Value %s for %s is not a 16-bit number.\n", value, option);
+        printf(USAGE);
+        exit(-1);
+
+    }
+
+    return num;
+
+}
+
+long millisecondsSince(struct timespec* then) {
+    // Returns the number of milliseconds which have passed since the given time
+
+    struct timespec now;
+    clock_gettime(CLOCK_MONOTONIC, &now);
+
+    return (now.tv_sec - then->tv_sec) * 1000 + (now.tv_nsec - then->tv_nsec) / 1000000;
+
+}
+
 bool endsWith(char* str, char* substr) {
     // Checks if a given string ends with a given substring
 